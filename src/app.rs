@@ -0,0 +1,1090 @@
+// Application core without GTK: the stopwatch, the network connection, measurements, scenarios and the
+// session results. Frontends drive it with `Command`s and follow it through `Event`s.
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use async_channel::{Receiver, Sender, TrySendError};
+use log::{error, info, warn};
+use message_io::network::ToRemoteAddr;
+use serde::Serialize;
+use crate::artifacts::recent_annotated_screenshots;
+use crate::calibration::{calibrate_capture, CaptureCalibration};
+use crate::config::{read_config, write_config, Config, MeasureMode, Profile, WorkloadConfig, WorkloadKind};
+use crate::control;
+use crate::flash::measure_flash;
+use crate::input::{measure_input, InputTiming};
+use crate::metrics::{self, Metrics};
+use crate::network::clock;
+use crate::network::messages::NetworkMessage;
+use crate::network::networkmanager::NetworkManager;
+use crate::report::SessionReport;
+use crate::results::{Measurement, SessionResults};
+use crate::scenario::{load_scenario, run_scenario, write_phase_results, PhaseResult};
+use crate::screenshot::{capture_screen, pick_window, CaptureBox, DelayReading, LockedWindows, ReadingFrames,
+                        WindowRole, WindowSelector};
+use crate::sinks::ResultSinks;
+use crate::stats::{refresh_period_ms, Summary, VsyncFold};
+use crate::{elapsed_to_string, UpdateUI};
+
+// events a subscriber can fall behind before further events are dropped for it
+const EVENT_BACKLOG: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    // connect to or listen on `network.address`
+    Connect,
+    Listen,
+    SetAddress(String),
+    SelectProfile(String),
+    // stores address, transport, monitor, capture box and detection of the current config as profile, the
+    // notes of an existing profile are kept
+    SaveProfile(String),
+    SelectMonitor(String),
+    // locks the capture to the window with this title, an empty title unlocks the remote window
+    SelectWindow(WindowRole, String),
+    // locks the capture to the window clicked next
+    PickWindow(WindowRole),
+    Measure,
+    // captures the own timer to calibrate the capture latency
+    Calibrate,
+    RunScenario(PathBuf),
+    CancelScenario,
+    // load the remote renders from now on
+    SetWorkload(WorkloadKind),
+    // what the following measurements measure
+    SetMode(MeasureMode),
+    // the armed color patch flipped on an input that arrived at this instant
+    InputReceived(Instant),
+    WriteReport,
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    // text of the stopwatch
+    Timer(String),
+    Role(Role),
+    Measured(Measurement),
+    MeasurementFailed,
+    // the own timer and marker have to be shown for the calibration
+    CalibrationStarted,
+    Calibrated(Option<CaptureCalibration>),
+    Ping(Duration),
+    PingLost,
+    // scenario phase that starts now, empty once the scenario is over
+    Phase(String),
+    ScenarioStarted(String),
+    ScenarioFinished(String, Vec<PhaseResult>),
+    // load to render, requested by the local instance
+    Workload(WorkloadConfig),
+    // the color patch has to flip on the next input, requested by the local instance
+    InputArmed,
+    // fill the color patch with this RGB color, requested by the local instance
+    Flash([u8; 3]),
+    // title of the window clicked to lock the capture to, None if no window was picked
+    WindowPicked(WindowRole, Option<String>),
+    // the current config changed, e.g. because a profile was selected
+    ConfigChanged(Box<Config>),
+    ProfileSaved(String),
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // the local instance, it connects and measures
+    Client,
+    // the remote instance, it listens
+    Server,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Status {
+    pub role: Option<Role>,
+    pub address: String,
+    // address the server listens on, the port differs from `address` if that has port 0
+    pub listening: Option<String>,
+    pub profile: String,
+    pub monitor: String,
+    pub mode: MeasureMode,
+    pub scenario_running: bool,
+    pub phase: String,
+    // the stopwatch runs while a measurement is in progress
+    pub measuring: bool,
+    pub measurements: usize,
+}
+
+// Statistics of the session so far, in milliseconds.
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    pub delay_ms: Option<Summary>,
+    pub input_ms: Option<Summary>,
+    // input latencies split at the arrival of the input in the remote window
+    pub input_leg_ms: Option<Summary>,
+    pub display_leg_ms: Option<Summary>,
+    pub flash_ms: Option<Summary>,
+    pub failures: u64,
+    // measurements left out of the statistics because of their quality
+    pub excluded: u64,
+    pub ping_ms: Option<Summary>,
+    pub pings_lost: u64,
+    pub last_delay_ms: Option<f64>,
+    pub last_ping_ms: Option<f64>,
+    // latency of the screenshots, the uncertainty of every measurement read from them
+    pub capture_latency: Option<CaptureCalibration>,
+    // display delays folded modulo the refresh period of the local and the remote display
+    pub vsync: Option<VsyncFold>,
+    pub remote_vsync: Option<VsyncFold>,
+}
+
+pub struct App {
+    sender: Arc<Sender<UpdateUI>>,
+    network: Arc<NetworkManager>,
+    run_stopwatch: Arc<AtomicBool>,
+    metrics: Arc<Metrics>,
+    result_sinks: ResultSinks,
+    // the address is written back to this file on shutdown
+    config_path: PathBuf,
+    state: Mutex<State>,
+    subscribers: Mutex<Vec<Sender<Event>>>,
+}
+
+struct State {
+    // profiles are applied on top of the startup config
+    base_config: Config,
+    config: Config,
+    // area around the marker used if `capture.capture_box` is not set, given by the frontend
+    default_capture_box: Option<CaptureBox>,
+    // color patch around the marker used if `flash.region` is not set, given by the frontend
+    default_flash_region: Option<CaptureBox>,
+    // phase of the running scenario, measurements are tagged with it
+    phase: String,
+    role: Option<Role>,
+    // measurements of the session that failed
+    failures: u64,
+    calibration: Option<CaptureCalibration>,
+    // remote timer of the previous display delay, a repeated value means the remote screen did not update
+    last_remote: Option<Duration>,
+    // stopwatch start of the running display delay measurement and the frames of the last accepted reading,
+    // the readings of the next measurement must not go back before them
+    timer_start: Option<Instant>,
+    last_frames: Option<ReadingFrames>,
+    // ids of the windows picked by clicking, used instead of `capture.remote_window` and `local_window`
+    picked_remote: Option<u32>,
+    picked_local: Option<u32>,
+    // refresh rate of the display showing this window, given by the frontend, and the one reported by the remote
+    refresh_hz: Option<f64>,
+    remote_refresh_hz: Option<f64>,
+    // local clock (`clock::micros`) at which the remote received the input of the running input measurement
+    input_arrival: Option<u64>,
+    // results of a running scenario are forwarded here, dropping it cancels the scenario
+    scenario_results: Option<Sender<Option<Duration>>>,
+    results: SessionResults,
+    // added to the metadata of the report, e.g. the version of the UI toolkit
+    report_metadata: Vec<(&'static str, String)>,
+}
+
+impl State {
+    // Windows captured instead of the monitors, picked by clicking or found by the titles in the config. None if
+    // either of them is neither picked nor has a title.
+    fn locked_windows(&self) -> Option<LockedWindows> {
+        let select = |picked: Option<u32>, title: &str| picked.map(WindowSelector::Id)
+            .or_else(|| Some(WindowSelector::Title(title.to_string())).filter(|_| !title.is_empty()));
+        Some(LockedWindows {
+            remote: select(self.picked_remote, &self.config.capture.remote_window)?,
+            local: select(self.picked_local, &self.config.capture.local_window)?,
+        })
+    }
+}
+
+impl App {
+    // Creates the core and starts the thread handling the updates of the network, capture and scenario threads.
+    pub fn start(config: Config, config_path: PathBuf) -> Arc<App> {
+        let (sender, receiver) = async_channel::bounded::<UpdateUI>(10);
+        let network = Arc::new(NetworkManager::new(Duration::from_millis(config.network.ping_interval_ms),
+                                                   &config.impairment));
+        let metrics = Arc::new(Metrics::default());
+        if config.metrics.enabled {
+            metrics::serve(Arc::clone(&metrics), &config.metrics.address);
+        }
+        let app = Arc::new(App {
+            sender: Arc::new(sender),
+            network,
+            run_stopwatch: Arc::new(AtomicBool::new(false)),
+            metrics,
+            result_sinks: ResultSinks::start(&config.sinks),
+            config_path,
+            state: Mutex::new(State {
+                base_config: config.clone(),
+                config,
+                default_capture_box: None,
+                default_flash_region: None,
+                phase: String::new(),
+                role: None,
+                failures: 0,
+                calibration: None,
+                last_remote: None,
+                timer_start: None,
+                last_frames: None,
+                picked_remote: None,
+                picked_local: None,
+                refresh_hz: None,
+                remote_refresh_hz: None,
+                input_arrival: None,
+                scenario_results: None,
+                results: SessionResults::default(),
+                report_metadata: vec![],
+            }),
+            subscribers: Mutex::new(vec![]),
+        });
+        let core = Arc::clone(&app);
+        thread::spawn(move || {
+            while let Ok(update) = receiver.recv_blocking() {
+                core.update(update);
+            }
+        });
+        let control = app.config().control;
+        if control.enabled {
+            control::serve(Arc::clone(&app), &control.address);
+        }
+        app
+    }
+
+    // Events from now on, a subscriber that falls too far behind misses events.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = async_channel::bounded(EVENT_BACKLOG);
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn config(&self) -> Config {
+        self.state.lock().unwrap().config.clone()
+    }
+
+    pub fn scenario_running(&self) -> bool {
+        self.state.lock().unwrap().scenario_results.is_some()
+    }
+
+    // Address the instance listens on as server, with the port chosen by the system if `network.address` has
+    // port 0. None if it is not listening.
+    pub fn listening_address(&self) -> Option<SocketAddr> {
+        self.network.listening_address()
+    }
+
+    pub fn status(&self) -> Status {
+        let state = self.state.lock().unwrap();
+        Status {
+            role: state.role,
+            listening: self.listening_address().map(|a| a.to_string()),
+            address: state.config.network.address.clone(),
+            profile: state.config.session.profile.clone(),
+            monitor: state.config.capture.monitor.clone(),
+            mode: state.config.session.mode,
+            scenario_running: state.scenario_results.is_some(),
+            phase: state.phase.clone(),
+            measuring: self.run_stopwatch.load(Ordering::Relaxed),
+            measurements: state.results.measurements.len(),
+        }
+    }
+
+    pub fn measurements(&self) -> Vec<Measurement> {
+        self.state.lock().unwrap().results.measurements.clone()
+    }
+
+    pub fn stats(&self) -> Stats {
+        let state = self.state.lock().unwrap();
+        let delays = |mode: MeasureMode| -> Vec<f64> {
+            state.results.measurements.iter().filter(|m| m.mode == mode && !m.excluded).map(|m| m.delay_ms).collect()
+        };
+        let (input_legs, display_legs): (Vec<f64>, Vec<f64>) = state.results.measurements.iter()
+            .filter(|m| !m.excluded)
+            .filter_map(|m| m.arrival_ms.map(|arrival| (arrival, m.delay_ms - arrival)))
+            .unzip();
+        let fold = |refresh_hz: fn(&Measurement) -> Option<f64>| {
+            let display: Vec<&Measurement> = state.results.measurements.iter()
+                .filter(|m| m.mode == MeasureMode::Display && !m.excluded).collect();
+            let period_ms = refresh_period_ms(display.iter().filter_map(|m| refresh_hz(m)))?;
+            VsyncFold::from_samples(&delays(MeasureMode::Display), period_ms)
+        };
+        Stats {
+            delay_ms: Summary::from_samples(&delays(MeasureMode::Display)),
+            input_ms: Summary::from_samples(&delays(MeasureMode::Input)),
+            input_leg_ms: Summary::from_samples(&input_legs),
+            display_leg_ms: Summary::from_samples(&display_legs),
+            flash_ms: Summary::from_samples(&delays(MeasureMode::Flash)),
+            failures: state.failures,
+            excluded: state.results.measurements.iter().filter(|m| m.excluded).count() as u64,
+            ping_ms: Summary::from_samples(&state.results.pings),
+            pings_lost: state.results.pings_lost,
+            last_delay_ms: state.results.measurements.last().map(|m| m.delay_ms),
+            last_ping_ms: state.results.pings.last().copied(),
+            capture_latency: state.calibration.clone(),
+            vsync: fold(|m| m.refresh_hz),
+            remote_vsync: fold(|m| m.remote_refresh_hz),
+        }
+    }
+
+    pub fn set_default_capture_box(&self, capture_box: CaptureBox) {
+        self.state.lock().unwrap().default_capture_box = Some(capture_box);
+    }
+
+    pub fn set_default_flash_region(&self, region: CaptureBox) {
+        self.state.lock().unwrap().default_flash_region = Some(region);
+    }
+
+    pub fn set_refresh_rate(&self, hz: f64) {
+        self.state.lock().unwrap().refresh_hz = Some(hz).filter(|hz| *hz > 0.0);
+    }
+
+    pub fn add_report_metadata(&self, key: &'static str, value: String) {
+        self.state.lock().unwrap().report_metadata.push((key, value));
+    }
+
+    pub fn execute(&self, command: Command) -> Result<(), String> {
+        match command {
+            Command::Connect | Command::Listen => {
+                let is_client = command == Command::Connect;
+                let config = self.config();
+                let address = config.network.address.as_str().to_remote_addr()
+                    .map_err(|e| format!("invalid address {:?}: {}", config.network.address, e))?;
+                Arc::clone(&self.network).connect(is_client, config.network.transport.into(), address,
+                                                  Arc::clone(&self.sender));
+                let role = if is_client { Role::Client } else { Role::Server };
+                self.state.lock().unwrap().role = Some(role);
+                self.publish(Event::Role(role));
+            }
+            Command::SetAddress(address) => {
+                let mut state = self.state.lock().unwrap();
+                state.config.network.address = address;
+                self.publish(Event::ConfigChanged(Box::new(state.config.clone())));
+            }
+            Command::SelectProfile(name) => {
+                let mut state = self.state.lock().unwrap();
+                state.config = state.base_config.with_profile(&name)?;
+                info!(profile = name.as_str(); "profile selected");
+                self.publish(Event::ConfigChanged(Box::new(state.config.clone())));
+            }
+            Command::SaveProfile(name) => {
+                let name = name.trim().to_string();
+                if name.is_empty() {
+                    return Err("the profile needs a name".to_string());
+                }
+                let mut state = self.state.lock().unwrap();
+                let config = &state.config;
+                let result = read_config(&self.config_path).and_then(|mut file_config| {
+                    let profile = file_config.profiles.entry(name.clone()).or_insert_with(Profile::default);
+                    profile.address = Some(config.network.address.clone());
+                    profile.transport = Some(config.network.transport);
+                    profile.monitor = Some(config.capture.monitor.clone()).filter(|m| !m.is_empty());
+                    profile.capture_box = config.capture.capture_box;
+                    profile.detection = Some(config.detection.clone());
+                    let profile = profile.clone();
+                    write_config(&file_config, &self.config_path)?;
+                    Ok(profile)
+                });
+                let profile = result.map_err(|e| format!("error saving profile {:?}: {}", name, e))?;
+                state.base_config.profiles.insert(name.clone(), profile);
+                info!(profile = name.as_str(); "profile saved");
+                self.publish(Event::ProfileSaved(name));
+            }
+            Command::SelectMonitor(name) => {
+                let mut state = self.state.lock().unwrap();
+                state.config.capture.monitor = name;
+                self.publish(Event::ConfigChanged(Box::new(state.config.clone())));
+            }
+            Command::SelectWindow(role, title) => {
+                let mut state = self.state.lock().unwrap();
+                // a title replaces a window picked before
+                match role {
+                    WindowRole::Remote => {
+                        state.config.capture.remote_window = title;
+                        state.picked_remote = None;
+                    }
+                    WindowRole::Local => {
+                        state.config.capture.local_window = title;
+                        state.picked_local = None;
+                    }
+                }
+                self.publish(Event::ConfigChanged(Box::new(state.config.clone())));
+            }
+            Command::PickWindow(role) => pick_window(Arc::clone(&self.sender), role),
+            Command::Measure => self.measure()?,
+            Command::Calibrate => {
+                let (config, capture_box) = {
+                    let state = self.state.lock().unwrap();
+                    if state.role == Some(Role::Server) {
+                        return Err("the capture is calibrated on the local instance".to_string());
+                    }
+                    let capture_box = state.config.capture.capture_box
+                        .map(|b| CaptureBox::new(b.width, b.height, b.x_offset, b.y_offset))
+                        .or(state.default_capture_box);
+                    (state.config.clone(), capture_box)
+                };
+                let capture_box = capture_box.ok_or("capture.capture_box is not set")?;
+                if self.run_stopwatch.load(Ordering::Relaxed) {
+                    return Err("a measurement is running".to_string());
+                }
+                self.publish(Event::CalibrationStarted);
+                let start = Instant::now();
+                self.start_timer(start);
+                calibrate_capture(Arc::clone(&self.sender), start, capture_box, Arc::new(config));
+            }
+            Command::RunScenario(path) => {
+                let mut state = self.state.lock().unwrap();
+                if state.scenario_results.is_some() {
+                    return Err("a scenario is already running".to_string());
+                }
+                let scenario = load_scenario(&path)
+                    .map_err(|e| format!("error loading scenario {:?}: {}", path, e))?;
+                let (results_sender, results_receiver) = async_channel::unbounded();
+                state.scenario_results = Some(results_sender);
+                let name = scenario.name.clone();
+                run_scenario(scenario, Arc::clone(&self.sender), results_receiver, Arc::clone(&self.network),
+                             state.config.workload);
+                self.publish(Event::ScenarioStarted(name));
+            }
+            Command::CancelScenario => {
+                // the scenario notices the closed channel and finishes after the running measurement
+                self.state.lock().unwrap().scenario_results.take().ok_or("no scenario is running")?;
+            }
+            Command::SetWorkload(kind) => {
+                let mut state = self.state.lock().unwrap();
+                state.config.workload.kind = kind;
+                self.network.send(NetworkMessage::Workload(state.config.workload));
+                self.publish(Event::ConfigChanged(Box::new(state.config.clone())));
+            }
+            Command::SetMode(mode) => {
+                let mut state = self.state.lock().unwrap();
+                state.config.session.mode = mode;
+                self.publish(Event::ConfigChanged(Box::new(state.config.clone())));
+            }
+            Command::InputReceived(arrived) => {
+                self.network.send(NetworkMessage::InputArrived(clock::micros(arrived)));
+            }
+            Command::WriteReport => self.write_report(&self.state.lock().unwrap())?,
+        }
+        Ok(())
+    }
+
+    // Writes the report if there are measurements and the address back to the config file.
+    pub fn shutdown(&self) {
+        let state = self.state.lock().unwrap();
+        if !state.results.measurements.is_empty() {
+            if let Err(e) = self.write_report(&state) {
+                error!(error:% = e; "error writing report");
+            }
+        }
+        // only the file contents are written back, not the command line overrides
+        let result = read_config(&self.config_path).and_then(|mut file_config| {
+            file_config.network.address = state.config.network.address.clone();
+            write_config(&file_config, &self.config_path)
+        });
+        if let Err(e) = result {
+            error!(path:? = self.config_path, error:% = e; "error writing config");
+        }
+    }
+
+    fn measure(&self) -> Result<(), String> {
+        let (config, capture_box, flash_region, windows, previous) = {
+            let state = self.state.lock().unwrap();
+            let capture_box = state.config.capture.capture_box
+                .map(|b| CaptureBox::new(b.width, b.height, b.x_offset, b.y_offset))
+                .or(state.default_capture_box);
+            let flash_region = state.config.flash.region
+                .map(|b| CaptureBox::new(b.width, b.height, b.x_offset, b.y_offset))
+                .or(state.default_flash_region);
+            (state.config.clone(), capture_box, flash_region, state.locked_windows(), state.last_frames)
+        };
+        if config.session.mode == MeasureMode::Flash {
+            let region = flash_region.ok_or("flash.region is not set")?;
+            measure_flash(Arc::clone(&self.sender), Arc::clone(&self.network), region, Arc::new(config));
+            return Ok(());
+        }
+        if config.session.mode == MeasureMode::Input {
+            let patch = config.input.patch.ok_or("input.patch is not set")?;
+            self.state.lock().unwrap().input_arrival = None;
+            self.network.send(NetworkMessage::ArmInput);
+            measure_input(Arc::clone(&self.sender), patch, Arc::new(config));
+            return Ok(());
+        }
+        let capture_box = capture_box.ok_or("capture.capture_box is not set")?;
+        self.network.send(NetworkMessage::StartTimer);
+        let start = Instant::now();
+        self.state.lock().unwrap().timer_start = Some(start);
+        self.start_timer(start);
+        capture_screen(Arc::clone(&self.sender), Arc::new(capture_box), Arc::new(config), start, windows, previous);
+        Ok(())
+    }
+
+    fn start_timer(&self, inst: Instant) {
+        let tick = Duration::from_millis(self.state.lock().unwrap().config.session.timer_tick_ms);
+        let run = Arc::clone(&self.run_stopwatch);
+        let sender = Arc::clone(&self.sender);
+        run.store(true, Ordering::Relaxed);
+        thread::spawn(move || timer_update(sender, run, inst, tick));
+    }
+
+    // Handles an update of the network, capture, timer or scenario threads.
+    fn update(&self, update: UpdateUI) {
+        match update {
+            UpdateUI::SetTimer(text) => self.publish(Event::Timer(text)),
+            UpdateUI::StartTimer(inst) => {
+                self.report_refresh_rate();
+                self.start_timer(inst);
+            }
+            UpdateUI::ResetTimer => self.run_stopwatch.store(false, Ordering::Relaxed),
+            UpdateUI::DelayMeasured(delay) => {
+                self.run_stopwatch.store(false, Ordering::Relaxed);
+                self.network.send(NetworkMessage::ResetTimer);
+                self.delay_measured(delay);
+            }
+            UpdateUI::InputMeasured(timing) => self.input_measured(timing),
+            UpdateUI::FlashMeasured(delay) => self.measured(MeasureMode::Flash, delay, |_, _| {}),
+            UpdateUI::Flash(color) => {
+                self.report_refresh_rate();
+                self.publish(Event::Flash(color));
+            }
+            UpdateUI::Calibrated(calibration) => {
+                self.run_stopwatch.store(false, Ordering::Relaxed);
+                self.state.lock().unwrap().calibration = calibration.clone();
+                self.publish(Event::Calibrated(calibration));
+            }
+            UpdateUI::InputArrived(remote) => {
+                let arrival = self.network.to_local_clock(remote);
+                if arrival.is_none() {
+                    warn!("input arrival without clock offset, no ping was answered yet");
+                }
+                self.state.lock().unwrap().input_arrival = arrival;
+            }
+            UpdateUI::Ping(rtt) => {
+                self.metrics.record_ping(rtt);
+                self.state.lock().unwrap().results.pings.push(rtt.as_secs_f64() * 1000.0);
+                self.publish(Event::Ping(rtt));
+            }
+            UpdateUI::PingLost => {
+                self.metrics.record_ping_lost();
+                self.state.lock().unwrap().results.pings_lost += 1;
+                self.publish(Event::PingLost);
+            }
+            UpdateUI::CaptureAttempt(failure, timings) => self.metrics.record_attempt(failure, &timings),
+            UpdateUI::Measure => {
+                if !self.scenario_running() {
+                    return;
+                }
+                if let Err(e) = self.measure() {
+                    error!(error:% = e; "error starting measurement");
+                    self.measured(self.config().session.mode, None, |_, _| {});
+                }
+            }
+            UpdateUI::Phase(label) => {
+                self.state.lock().unwrap().phase = label.clone();
+                self.publish(Event::Phase(label));
+            }
+            UpdateUI::ScenarioFinished(name, results) => {
+                let dir = {
+                    let mut state = self.state.lock().unwrap();
+                    state.scenario_results.take();
+                    PathBuf::from(&state.config.output.results_dir)
+                };
+                match write_phase_results(&dir, &name, &results) {
+                    Ok(path) => info!(scenario = name.as_str(), path:? = path; "scenario finished"),
+                    Err(e) => error!(error:% = e; "error writing scenario results"),
+                }
+                self.publish(Event::ScenarioFinished(name, results));
+            }
+            UpdateUI::Workload(workload) => self.publish(Event::Workload(workload)),
+            UpdateUI::ArmInput => {
+                self.report_refresh_rate();
+                self.publish(Event::InputArmed);
+            }
+            UpdateUI::RemoteRefreshRate(hz) => self.state.lock().unwrap().remote_refresh_hz = Some(hz),
+            UpdateUI::WindowPicked(role, window) => {
+                let id = window.as_ref().map(|(id, _)| *id);
+                let mut state = self.state.lock().unwrap();
+                // a failed pick keeps the window picked before
+                match role {
+                    WindowRole::Remote => state.picked_remote = id.or(state.picked_remote),
+                    WindowRole::Local => state.picked_local = id.or(state.picked_local),
+                }
+                drop(state);
+                self.publish(Event::WindowPicked(role, window.map(|(_, title)| title)));
+            }
+        }
+    }
+
+    // Tells the instance measuring this one the refresh rate of the display showing this window.
+    fn report_refresh_rate(&self) {
+        if let Some(hz) = self.state.lock().unwrap().refresh_hz {
+            self.network.send(NetworkMessage::RefreshRate(hz));
+        }
+    }
+
+    // Records an input latency, split at the arrival of the input on the remote if it is known.
+    fn input_measured(&self, timing: Option<InputTiming>) {
+        let arrival = self.state.lock().unwrap().input_arrival.take();
+        let Some(timing) = timing else {
+            self.measured(MeasureMode::Input, None, |_, _| {});
+            return;
+        };
+        let arrival_ms = match arrival {
+            // the remote reports the arrival long before its screen shows the reaction
+            None => {
+                warn!("input arrival not reported by the remote, latency is not split");
+                None
+            }
+            Some(arrival) => {
+                let arrival_ms = (arrival as f64 - clock::micros(timing.pressed) as f64) / 1000.0;
+                let latency_ms = timing.latency().as_secs_f64() * 1000.0;
+                // the clock offset is only as exact as half of the ping round trip
+                if (0.0..=latency_ms).contains(&arrival_ms) {
+                    Some(arrival_ms)
+                } else {
+                    warn!(arrival_ms = arrival_ms, latency_ms = latency_ms;
+                          "input arrival outside of the measured latency, latency is not split");
+                    None
+                }
+            }
+        };
+        self.measured(MeasureMode::Input, Some(timing.latency()), |m, _| m.arrival_ms = arrival_ms);
+    }
+
+    // Records a display delay with the quality of its readings, excluding it from the statistics if it is
+    // below the limits of `quality`.
+    fn delay_measured(&self, reading: Option<DelayReading>) {
+        let Some(reading) = reading else {
+            self.measured(MeasureMode::Display, None, |_, _| {});
+            return;
+        };
+        self.measured(MeasureMode::Display, Some(reading.delay), |measurement, state| {
+            let tick_ms = state.config.session.timer_tick_ms;
+            // each timer is up to a tick old and shown up to a frame late, timers on different monitors were
+            // captured at different times, and the screenshots themselves vary in latency if that was calibrated
+            let capture_ms = state.calibration.as_ref().map_or(0.0, |c| c.uncertainty_ms);
+            let uncertainty_ms = tick_ms as f64 + reading.frame_ms + reading.skew_ms + capture_ms;
+            let stale = state.last_remote.replace(reading.remote) == Some(reading.remote);
+            if let Some(start) = state.timer_start {
+                state.last_frames = Some(ReadingFrames::of(&reading, start));
+            }
+            measurement.uncertainty_ms = Some(uncertainty_ms);
+            // the capture backend knows the monitor the remote window was found on
+            if reading.refresh_hz > 0.0 {
+                measurement.refresh_hz = Some(reading.refresh_hz as f64);
+            }
+            measurement.local_monitor = Some(reading.local_monitor.clone()).filter(|m| !m.is_empty());
+            measurement.remote_monitor = Some(reading.remote_monitor.clone()).filter(|m| !m.is_empty());
+            measurement.match_confidence = Some(reading.match_confidence);
+            measurement.ocr_confidence = Some(reading.ocr_confidence);
+            measurement.tick_ms = Some(tick_ms);
+            measurement.stale = stale;
+            let quality = &state.config.quality;
+            let reason = if reading.ocr_confidence < quality.min_ocr_confidence {
+                Some("low OCR confidence")
+            } else if uncertainty_ms > quality.max_uncertainty_ms {
+                Some("high uncertainty")
+            } else if stale && quality.exclude_stale {
+                Some("stale remote timer")
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                warn!(reason = reason, delay:? = reading.delay; "measurement excluded from the statistics");
+                measurement.excluded = true;
+            }
+        });
+    }
+
+    // Records the result of a measurement after `complete` filled in the details of its mode and hands it to
+    // a running scenario.
+    fn measured(&self, mode: MeasureMode, delay: Option<Duration>,
+                complete: impl FnOnce(&mut Measurement, &mut State)) {
+        let mut state = self.state.lock().unwrap();
+        let measurement = delay.map(|delay| {
+            let mut measurement = Measurement::new(mode, delay, &state.config.session.profile, &state.phase);
+            measurement.refresh_hz = state.refresh_hz;
+            measurement.remote_refresh_hz = state.remote_refresh_hz;
+            complete(&mut measurement, &mut state);
+            measurement
+        });
+        if let Some(results) = &state.scenario_results {
+            // excluded measurements count as failed in the phase statistics
+            let _ = results.try_send(delay.filter(|_| measurement.as_ref().is_some_and(|m| !m.excluded)));
+        }
+        let Some(measurement) = measurement else {
+            state.failures += 1;
+            self.publish(Event::MeasurementFailed);
+            return;
+        };
+        if !measurement.excluded {
+            self.metrics.record_measurement(&measurement);
+        }
+        self.result_sinks.publish(&measurement);
+        let dir = PathBuf::from(&state.config.output.results_dir);
+        state.results.record(measurement.clone(), &dir);
+        self.publish(Event::Measured(measurement));
+    }
+
+    fn publish(&self, event: Event) {
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            !matches!(subscriber.try_send(event.clone()), Err(TrySendError::Closed(_)))
+        });
+    }
+
+    // Writes the report of the session next to its result files.
+    fn write_report(&self, state: &State) -> Result<(), String> {
+        let path = state.results.report_path().ok_or("no measurements for a report yet")?;
+        let config = &state.config;
+        let mut screenshots = vec![];
+        if config.output.debug_image && Path::new("debug.jpg").is_file() {
+            screenshots.push(PathBuf::from("debug.jpg"));
+        }
+        screenshots.extend(recent_annotated_screenshots(config.output.debug_dir.as_ref(), 3));
+        let profile = if config.session.profile.is_empty() { "-".to_string() } else { config.session.profile.clone() };
+        let monitor = if config.capture.monitor.is_empty() { "-".to_string() } else { config.capture.monitor.clone() };
+        let mut metadata = vec![
+            ("Started", state.results.measurements.first().map(|m| m.timestamp.clone()).unwrap_or_default()),
+            ("Profile", profile),
+            ("Address", config.network.address.clone()),
+            ("Transport", format!("{:?}", config.network.transport)),
+            ("Monitor", monitor),
+            ("OCR engine", format!("{:?}", config.ocr.engine)),
+            ("Latency Tester", env!("CARGO_PKG_VERSION").to_string()),
+        ];
+        if let Some(c) = &state.calibration {
+            metadata.push(("Capture latency", format!("median {:.1} ms, jitter {:.1} ms, uncertainty ±{:.1} ms \
+                                                       ({} screenshots)", c.latency_ms.median, c.latency_ms.stddev,
+                                                      c.uncertainty_ms, c.latency_ms.count)));
+        }
+        metadata.extend(state.report_metadata.iter().cloned());
+        metadata.push(("OS", format!("{} {}", std::env::consts::OS, std::env::consts::ARCH)));
+        let report = SessionReport {
+            metadata,
+            measurements: &state.results.measurements,
+            pings: &state.results.pings,
+            pings_lost: state.results.pings_lost,
+            screenshots,
+        };
+        report.write(&path).map_err(|e| format!("error writing report {:?}: {}", path, e))?;
+        info!(path:? = path; "report written");
+        Ok(())
+    }
+}
+
+fn timer_update(sender: Arc<Sender<UpdateUI>>, run: Arc<AtomicBool>, inst: Instant, tick: Duration) {
+    loop {
+        if !run.load(Ordering::Relaxed) {
+            let _ = sender.send_blocking(UpdateUI::SetTimer(elapsed_to_string(&Instant::now())));
+            break;
+        }
+        if sender.send_blocking(UpdateUI::SetTimer(elapsed_to_string(&inst))).is_err() {
+            break;
+        }
+        thread::sleep(tick);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::config::CaptureBoxConfig;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("latency_tester_app_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn start(name: &str) -> (Arc<App>, Receiver<Event>, PathBuf) {
+        let dir = temp_dir(name);
+        let mut config = Config::default();
+        config.output.results_dir = dir.join("results").display().to_string();
+        config.output.debug_image = false;
+        let app = App::start(config, dir.join("config.toml"));
+        let events = app.subscribe();
+        (app, events, dir)
+    }
+
+    // Waits for the first event `matches` returns a value for, other events are skipped.
+    fn wait_for<T>(events: &Receiver<Event>, matches: impl Fn(Event) -> Option<T>) -> T {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            match events.try_recv() {
+                Ok(event) => {
+                    if let Some(value) = matches(event) {
+                        return value;
+                    }
+                }
+                Err(_) => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+        panic!("event not received");
+    }
+
+    fn reading(delay_ms: u64, remote_ms: u64, ocr_confidence: f32) -> DelayReading {
+        DelayReading {
+            delay: Duration::from_millis(delay_ms),
+            local: Duration::from_millis(remote_ms + delay_ms),
+            remote: Duration::from_millis(remote_ms),
+            match_confidence: 0.95,
+            ocr_confidence,
+            frame_ms: 1000.0 / 60.0,
+            refresh_hz: 60.0,
+            local_at: (100, 100, 40, 40, 0.95),
+            remote_at: (900, 100, 40, 40, 0.95),
+            skew_ms: 0.0,
+            local_monitor: "DP-1 1920x1080+0+0@60Hz".to_string(),
+            remote_monitor: "DP-1 1920x1080+0+0@60Hz".to_string(),
+        }
+    }
+
+    #[test]
+    fn measurements_are_tagged_recorded_and_reported() {
+        let (app, events, _) = start("measured");
+        app.sender.send_blocking(UpdateUI::Phase("idle".to_string())).unwrap();
+        app.sender.send_blocking(UpdateUI::DelayMeasured(Some(reading(42, 2042, 0.9)))).unwrap();
+        let measurement = wait_for(&events, |e| match e { Event::Measured(m) => Some(m), _ => None });
+        assert_eq!(measurement.phase, "idle");
+        assert_eq!(measurement.delay_ms, 42.0);
+        assert_eq!(measurement.tick_ms, Some(4));
+        assert!((measurement.uncertainty_ms.unwrap() - (4.0 + 1000.0 / 60.0)).abs() < 1e-9);
+        assert!(!measurement.excluded);
+
+        app.execute(Command::WriteReport).unwrap();
+        let state = app.state.lock().unwrap();
+        assert_eq!(state.results.measurements, vec![measurement]);
+        assert!(state.results.report_path().unwrap().is_file());
+    }
+
+    #[test]
+    fn calibrated_capture_latency_adds_to_the_uncertainty() {
+        let (app, events, _) = start("calibrated");
+        let latency = Summary::from_samples(&[20.0, 25.0, 30.0]).unwrap();
+        let calibration = CaptureCalibration { failures: 0, capture_ms: latency.clone(), latency_ms: latency,
+                                               uncertainty_ms: 3.5 };
+        app.sender.send_blocking(UpdateUI::Calibrated(Some(calibration))).unwrap();
+        wait_for(&events, |e| matches!(e, Event::Calibrated(_)).then_some(()));
+        app.sender.send_blocking(UpdateUI::DelayMeasured(Some(reading(42, 2042, 0.9)))).unwrap();
+        let measurement = wait_for(&events, |e| match e { Event::Measured(m) => Some(m), _ => None });
+        assert!((measurement.uncertainty_ms.unwrap() - (4.0 + 1000.0 / 60.0 + 3.5)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn low_quality_delays_are_left_out_of_the_statistics() {
+        let (app, events, _) = start("quality");
+        let timer_start = Instant::now();
+        app.state.lock().unwrap().timer_start = Some(timer_start);
+        // the second delay shows the remote timer of the first one again, the third one was hard to read
+        for reading in [reading(40, 2040, 0.9), reading(50, 2040, 0.9), reading(60, 2060, 0.3)] {
+            app.sender.send_blocking(UpdateUI::DelayMeasured(Some(reading))).unwrap();
+            wait_for(&events, |e| matches!(e, Event::Measured(_)).then_some(()));
+        }
+        let flags: Vec<(bool, bool)> = app.measurements().iter().map(|m| (m.stale, m.excluded)).collect();
+        assert_eq!(flags, vec![(false, false), (true, true), (false, true)]);
+        // the next measurement is checked against the last reading, also if it was left out of the statistics
+        assert_eq!(app.state.lock().unwrap().last_frames,
+                   Some(ReadingFrames::of(&reading(60, 2060, 0.3), timer_start)));
+        assert_eq!(app.measurements()[0].local_monitor.as_deref(), Some("DP-1 1920x1080+0+0@60Hz"));
+        let stats = app.stats();
+        assert_eq!(stats.excluded, 2);
+        assert_eq!(stats.delay_ms.map(|s| s.count), Some(1));
+        // the refresh rate of the remote display was never reported
+        assert_eq!(stats.vsync.map(|v| v.period_ms), Some(1000.0 / 60.0));
+        assert_eq!(stats.remote_vsync, None);
+    }
+
+    #[test]
+    fn failed_measurement_is_published() {
+        let (app, events, _) = start("failed");
+        assert!(app.execute(Command::WriteReport).is_err());
+        app.sender.send_blocking(UpdateUI::DelayMeasured(None)).unwrap();
+        wait_for(&events, |e| matches!(e, Event::MeasurementFailed).then_some(()));
+        assert!(app.state.lock().unwrap().results.measurements.is_empty());
+    }
+
+    #[test]
+    fn measurement_needs_a_capture_box() {
+        let (app, _, _) = start("capture_box");
+        assert_eq!(app.execute(Command::Measure), Err("capture.capture_box is not set".to_string()));
+        assert_eq!(app.execute(Command::Calibrate), Err("capture.capture_box is not set".to_string()));
+        app.execute(Command::SetMode(MeasureMode::Flash)).unwrap();
+        assert_eq!(app.execute(Command::Measure), Err("flash.region is not set".to_string()));
+    }
+
+    #[test]
+    fn capture_is_locked_to_picked_or_titled_windows() {
+        let (app, events, _) = start("windows");
+        let locked = || app.state.lock().unwrap().locked_windows();
+        assert_eq!(locked(), None);
+        app.execute(Command::SelectWindow(WindowRole::Remote, "Remote Desktop".to_string())).unwrap();
+        let title = |t: &str| WindowSelector::Title(t.to_string());
+        assert_eq!(locked(), Some(LockedWindows { remote: title("Remote Desktop"), local: title("Latency Tester") }));
+        // a picked window wins over the title until another title is selected
+        app.sender.send_blocking(UpdateUI::WindowPicked(WindowRole::Local, Some((42, "tester".to_string()))))
+            .unwrap();
+        let picked = wait_for(&events, |e| match e {
+            Event::WindowPicked(role, title) => Some((role, title)),
+            _ => None,
+        });
+        assert_eq!(picked, (WindowRole::Local, Some("tester".to_string())));
+        assert_eq!(locked().map(|w| w.local), Some(WindowSelector::Id(42)));
+        app.sender.send_blocking(UpdateUI::WindowPicked(WindowRole::Local, None)).unwrap();
+        wait_for(&events, |e| matches!(e, Event::WindowPicked(_, None)).then_some(()));
+        assert_eq!(locked().map(|w| w.local), Some(WindowSelector::Id(42)));
+        app.execute(Command::SelectWindow(WindowRole::Local, "Latency Tester".to_string())).unwrap();
+        assert_eq!(locked().map(|w| w.local), Some(title("Latency Tester")));
+        app.execute(Command::SelectWindow(WindowRole::Remote, String::new())).unwrap();
+        assert_eq!(locked(), None);
+    }
+
+    #[test]
+    fn input_latencies_are_kept_apart_from_display_delays() {
+        let (app, events, _) = start("input");
+        app.execute(Command::SetMode(MeasureMode::Input)).unwrap();
+        assert_eq!(app.execute(Command::Measure), Err("input.patch is not set".to_string()));
+        let pressed = Instant::now();
+        let timing = InputTiming { pressed, changed: pressed + Duration::from_millis(80) };
+        app.sender.send_blocking(UpdateUI::InputMeasured(Some(timing))).unwrap();
+        let measurement = wait_for(&events, |e| match e { Event::Measured(m) => Some(m), _ => None });
+        assert_eq!(measurement.mode, MeasureMode::Input);
+        // without a connection the arrival of the input is not known
+        assert_eq!(measurement.arrival_ms, None);
+        let stats = app.stats();
+        assert!(stats.delay_ms.is_none());
+        assert_eq!(stats.input_ms.map(|s| s.median), Some(80.0));
+    }
+
+    #[test]
+    fn input_latency_is_split_at_the_arrival_on_the_remote() {
+        let (app, events, _) = start("input_split");
+        let pressed = Instant::now();
+        let timing = InputTiming { pressed, changed: pressed + Duration::from_millis(80) };
+        for arrival_ms in [30, 95] {
+            app.state.lock().unwrap().input_arrival = Some(clock::micros(pressed) + arrival_ms * 1000);
+            app.sender.send_blocking(UpdateUI::InputMeasured(Some(timing))).unwrap();
+            wait_for(&events, |e| matches!(e, Event::Measured(_)).then_some(()));
+        }
+        // an arrival after the reaction was seen is not plausible and not used
+        let arrivals: Vec<Option<f64>> = app.measurements().iter().map(|m| m.arrival_ms).collect();
+        assert_eq!(arrivals, vec![Some(30.0), None]);
+        let stats = app.stats();
+        assert_eq!(stats.input_leg_ms.map(|s| s.median), Some(30.0));
+        assert_eq!(stats.display_leg_ms.map(|s| s.median), Some(50.0));
+    }
+
+    #[test]
+    fn profiles_are_saved_and_selected() {
+        let (app, events, dir) = start("profiles");
+        fs::write(dir.join("config.toml"), "[profiles.lab]\nnotes = \"rack 3\"\n").unwrap();
+        app.execute(Command::SetAddress("10.0.0.2:4999".to_string())).unwrap();
+        app.execute(Command::SelectMonitor("DP-1".to_string())).unwrap();
+        let capture_box = CaptureBoxConfig { width: 120, height: 30, x_offset: -130, y_offset: 0 };
+        {
+            let mut state = app.state.lock().unwrap();
+            state.config.capture.capture_box = Some(capture_box);
+            state.config.detection.tolerance = 40;
+        }
+        assert!(app.execute(Command::SaveProfile(" ".to_string())).is_err());
+        app.execute(Command::SaveProfile("lab".to_string())).unwrap();
+        wait_for(&events, |e| match e { Event::ProfileSaved(name) => Some(name), _ => None });
+        let saved = &read_config(&dir.join("config.toml")).unwrap().profiles["lab"];
+        assert_eq!(saved.address.as_deref(), Some("10.0.0.2:4999"));
+        assert_eq!(saved.capture_box, Some(capture_box));
+        assert_eq!(saved.detection.as_ref().map(|d| d.tolerance), Some(40));
+        assert_eq!(saved.notes, "rack 3");
+
+        app.execute(Command::SetAddress("127.0.0.1:1".to_string())).unwrap();
+        {
+            let mut state = app.state.lock().unwrap();
+            state.config.capture.capture_box = None;
+            state.config.detection.tolerance = 0;
+        }
+        app.execute(Command::SelectProfile("lab".to_string())).unwrap();
+        // the settings changed above are published as well
+        let config = wait_for(&events, |e| match e {
+            Event::ConfigChanged(c) if c.session.profile == "lab" => Some(c),
+            _ => None,
+        });
+        assert_eq!(config.network.address, "10.0.0.2:4999");
+        assert_eq!(config.capture.monitor, "DP-1");
+        assert_eq!(config.capture.capture_box, Some(capture_box));
+        assert_eq!(config.detection.tolerance, 40);
+        assert_eq!(config.session.profile, "lab");
+        assert!(app.execute(Command::SelectProfile("missing".to_string())).is_err());
+    }
+
+    #[test]
+    fn scenario_runs_and_counts_failed_measurements() {
+        let (app, events, dir) = start("scenario");
+        let path = dir.join("scenario.toml");
+        fs::write(&path, "name = \"short\"\ninterval_ms = 1\n\
+                          [[phases]]\nlabel = \"a\"\nsamples = 2\n\
+                          [[phases]]\nlabel = \"b\"\nsamples = 1\n").unwrap();
+        assert!(app.execute(Command::CancelScenario).is_err());
+        app.execute(Command::RunScenario(path.clone())).unwrap();
+        assert!(app.scenario_running());
+        assert!(app.execute(Command::RunScenario(path)).is_err());
+        assert_eq!(wait_for(&events, |e| match e { Event::ScenarioStarted(n) => Some(n), _ => None }), "short");
+        // without a capture box every measurement fails
+        let (name, results) = wait_for(&events, |e| match e {
+            Event::ScenarioFinished(name, results) => Some((name, results)),
+            _ => None,
+        });
+        assert_eq!(name, "short");
+        assert_eq!(results.iter().map(|r| (r.label.as_str(), r.failures)).collect::<Vec<_>>(),
+                   vec![("a", 2), ("b", 1)]);
+        assert!(!app.scenario_running());
+        assert!(fs::read_dir(dir.join("results")).unwrap()
+            .any(|f| f.unwrap().file_name().to_string_lossy().starts_with("scenario_short_")));
+    }
+
+    #[test]
+    fn remote_instance_follows_the_local_one() {
+        let (remote, remote_events, _) = start("remote");
+        let (local, local_events, _) = start("local");
+        remote.execute(Command::SetAddress("127.0.0.1:0".to_string())).unwrap();
+        remote.execute(Command::Listen).unwrap();
+        // the remote listens on a port chosen by the system
+        let address = remote.listening_address().unwrap().to_string();
+        local.execute(Command::SetAddress(address)).unwrap();
+        local.execute(Command::Connect).unwrap();
+        assert_eq!(wait_for(&local_events, |e| match e { Event::Role(r) => Some(r), _ => None }), Role::Client);
+        wait_for(&local_events, |e| matches!(e, Event::Ping(_)).then_some(()));
+        // both instances share the clock of the process, the estimated offset is at most half a round trip
+        let offset = local.network.to_local_clock(10_000_000).unwrap() as i64 - 10_000_000;
+        assert!(offset.abs() < 50_000, "offset {} us", offset);
+
+        local.execute(Command::SetWorkload(WorkloadKind::Noise)).unwrap();
+        let workload = wait_for(&remote_events, |e| match e { Event::Workload(w) => Some(w), _ => None });
+        assert_eq!(workload.kind, WorkloadKind::Noise);
+
+        // the remote stopwatch runs from StartTimer to ResetTimer, the remote reports its refresh rate
+        remote.set_refresh_rate(75.0);
+        local.network.send(NetworkMessage::StartTimer);
+        wait_for(&remote_events, |e| matches!(e, Event::Timer(_)).then_some(()));
+        assert!(remote.run_stopwatch.load(Ordering::Relaxed));
+        local.network.send(NetworkMessage::ResetTimer);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while remote.run_stopwatch.load(Ordering::Relaxed) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(!remote.run_stopwatch.load(Ordering::Relaxed));
+        while local.state.lock().unwrap().remote_refresh_hz.is_none() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(local.state.lock().unwrap().remote_refresh_hz, Some(75.0));
+
+        // the arrival of an input is reported in the clock of the local instance
+        let arrived = Instant::now();
+        remote.execute(Command::InputReceived(arrived)).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while local.state.lock().unwrap().input_arrival.is_none() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let arrival = local.state.lock().unwrap().input_arrival.unwrap() as i64;
+        assert!((arrival - clock::micros(arrived) as i64).abs() < 50_000);
+        local.network.disconnect();
+        remote.network.disconnect();
+    }
+}
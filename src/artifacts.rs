@@ -0,0 +1,192 @@
+// Debug artifacts written for every failed capture attempt, one directory per attempt containing
+// the raw screenshot, the annotated screenshot, the OCR crops and texts and a summary.json.
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::Local;
+use image::RgbImage;
+use log::{info, warn};
+use regex::Regex;
+use serde::Serialize;
+use crate::config::OutputConfig;
+use crate::screenshot::{CaptureFailure, StageTimings};
+
+pub struct DebugArtifacts {
+    pub dir: PathBuf,
+    // number of attempt directories to keep, older ones are deleted
+    pub retention: usize,
+}
+
+#[derive(Serialize)]
+pub struct MarkerArtifact {
+    pub name: &'static str,
+    pub found: bool,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub confidence: f32,
+    pub ocr_text: Option<String>,
+    pub reading_ms: Option<u128>,
+    pub error: Option<String>,
+    #[serde(skip)]
+    pub crop: Option<RgbImage>,
+}
+
+#[derive(Serialize)]
+struct Summary<'a> {
+    timestamp: String,
+    attempt: i32,
+    monitor: &'a str,
+    reason: CaptureFailure,
+    capture_ms: f64,
+    locate_ms: f64,
+    ocr_ms: f64,
+    markers: &'a [MarkerArtifact],
+}
+
+pub struct AttemptBundle {
+    attempt: i32,
+    monitor: String,
+    screenshot: RgbImage,
+    markers: Vec<MarkerArtifact>,
+}
+
+impl DebugArtifacts {
+    pub fn from_config(config: &OutputConfig) -> Option<DebugArtifacts> {
+        config.debug_artifacts.then(|| DebugArtifacts {
+            dir: config.debug_dir.clone().into(),
+            retention: config.debug_retention,
+        })
+    }
+}
+
+impl MarkerArtifact {
+    pub fn not_found(name: &'static str) -> MarkerArtifact {
+        MarkerArtifact {
+            name,
+            found: false,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            confidence: 0.0,
+            ocr_text: None,
+            reading_ms: None,
+            error: Some("could not locate program window".to_string()),
+            crop: None,
+        }
+    }
+}
+
+impl AttemptBundle {
+    pub fn new(attempt: i32, monitor: &str, screenshot: &RgbImage) -> AttemptBundle {
+        AttemptBundle {
+            attempt,
+            monitor: monitor.to_string(),
+            screenshot: screenshot.clone(),
+            markers: vec![],
+        }
+    }
+
+    pub fn add_marker(&mut self, marker: MarkerArtifact) {
+        self.markers.push(marker);
+    }
+
+    pub fn save(&self, settings: &DebugArtifacts, annotated: &RgbImage, reason: CaptureFailure,
+                timings: &StageTimings) {
+        let timestamp = Local::now();
+        let dir = settings.dir.join(format!("{}_attempt{}", timestamp.format("%Y%m%d-%H%M%S%.3f"), self.attempt));
+        match self.write(&dir, annotated, reason, timings, timestamp.to_rfc3339()) {
+            Ok(()) => info!(dir:? = dir; "debug artifacts saved"),
+            Err(e) => warn!(dir:? = dir, error:% = e; "error saving debug artifacts"),
+        }
+        prune(&settings.dir, settings.retention);
+    }
+
+    fn write(&self, dir: &Path, annotated: &RgbImage, reason: CaptureFailure,
+             timings: &StageTimings, timestamp: String)
+             -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(dir)?;
+        self.screenshot.save(dir.join("screenshot.png"))?;
+        annotated.save(dir.join("annotated.png"))?;
+        for marker in &self.markers {
+            if let Some(crop) = &marker.crop {
+                crop.save(dir.join(format!("crop_{}.png", marker.name)))?;
+            }
+            if let Some(text) = &marker.ocr_text {
+                fs::write(dir.join(format!("ocr_{}.txt", marker.name)), text)?;
+            }
+        }
+        let summary = Summary {
+            timestamp,
+            attempt: self.attempt,
+            monitor: &self.monitor,
+            reason,
+            capture_ms: timings.capture.as_secs_f64() * 1000.0,
+            locate_ms: timings.locate.as_secs_f64() * 1000.0,
+            ocr_ms: timings.ocr.as_secs_f64() * 1000.0,
+            markers: &self.markers,
+        };
+        fs::write(dir.join("summary.json"), serde_json::to_string_pretty(&summary)?)?;
+        Ok(())
+    }
+}
+
+// Attempt directories in `dir`, oldest first. Other directories are left alone, `dir` may hold other data.
+fn attempt_dirs(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else { return vec![]; };
+    // names made by `AttemptBundle::save`
+    let name = Regex::new(r"^\d{8}-\d{6}\.\d{3}_attempt\d+$").unwrap();
+    let mut attempts: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_dir() && p.file_name().and_then(|n| n.to_str()).is_some_and(|n| name.is_match(n)))
+        .collect();
+    // directory names start with the timestamp, so they sort by age
+    attempts.sort();
+    attempts
+}
+
+// Annotated screenshots of the newest `count` attempts, newest first.
+pub fn recent_annotated_screenshots(dir: &Path, count: usize) -> Vec<PathBuf> {
+    attempt_dirs(dir).into_iter().rev()
+        .map(|d| d.join("annotated.png"))
+        .filter(|p| p.is_file())
+        .take(count)
+        .collect()
+}
+
+// Deletes the oldest attempt directories so that at most `retention` remain.
+fn prune(dir: &Path, retention: usize) {
+    let attempts = attempt_dirs(dir);
+    let excess = attempts.len().saturating_sub(retention);
+    for path in &attempts[..excess] {
+        if let Err(e) = fs::remove_dir_all(path) {
+            warn!(dir:? = path, error:% = e; "error deleting old debug artifacts");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_attempt_directories_are_pruned() {
+        let dir = std::env::temp_dir().join(format!("latency_tester_artifacts_{}", std::process::id()));
+        let attempts = ["20240610-061320.100_attempt0", "20240610-061321.200_attempt1",
+                        "20240610-061322.300_attempt12"];
+        for name in attempts.iter().chain(&["results", ".git", "20240610_attempt", "20240610-061319.000_attempt0x"]) {
+            fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        fs::write(dir.join("20240610-061318.000_attempt0"), "not a directory").unwrap();
+        prune(&dir, 1);
+        let mut left: Vec<String> = fs::read_dir(&dir).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        assert_eq!(left, [".git", "20240610-061318.000_attempt0", "20240610-061319.000_attempt0x",
+                          "20240610-061322.300_attempt12", "20240610_attempt", "results"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+// Capture latency calibration: a single instance runs its stopwatch, captures itself repeatedly and compares the
+// timer read from every screenshot with the instants the capture call started and returned. The timer shows
+// when the captured frame was drawn, so the difference to the end of the call is the time from the frame to the
+// screenshot, and its spread is the uncertainty every timestamp taken from a screenshot carries.
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use async_channel::Sender;
+use image::DynamicImage;
+use log::{error, info, warn};
+use serde::Serialize;
+use crate::config::Config;
+use crate::screenshot::{get_monitors, read_local_timer, CaptureBox};
+use crate::stats::{quantile, Summary};
+use crate::UpdateUI;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CaptureCalibration {
+    // screenshots the timer could not be read from
+    pub failures: usize,
+    // duration of the capture call
+    pub capture_ms: Summary,
+    // time from the frame in the screenshot to the end of the capture call
+    pub latency_ms: Summary,
+    // half of the range between the 5th and the 95th percentile of the latency
+    pub uncertainty_ms: f64,
+}
+
+// Instants of one screenshot relative to the start of the stopwatch.
+#[derive(Debug, Clone, Copy)]
+struct CaptureSample {
+    start: Duration,
+    end: Duration,
+    // timer read from the screenshot
+    reading: Duration,
+}
+
+impl CaptureCalibration {
+    fn from_samples(samples: &[CaptureSample], failures: usize) -> Option<CaptureCalibration> {
+        let millis = |d: Duration| d.as_secs_f64() * 1000.0;
+        let durations: Vec<f64> = samples.iter().map(|s| millis(s.end.saturating_sub(s.start))).collect();
+        let mut latencies: Vec<f64> = samples.iter().map(|s| millis(s.end) - millis(s.reading)).collect();
+        let capture_ms = Summary::from_samples(&durations)?;
+        let latency_ms = Summary::from_samples(&latencies)?;
+        latencies.sort_by(f64::total_cmp);
+        let uncertainty_ms = (quantile(&latencies, 0.95) - quantile(&latencies, 0.05)) / 2.0;
+        Some(CaptureCalibration { failures, capture_ms, latency_ms, uncertainty_ms })
+    }
+}
+
+// Captures the monitor `capture.calibration_samples` times while the stopwatch started at `timer_start` runs
+// and reads the timer in `capture_box` around the marker of this instance.
+pub fn calibrate_capture(sender: Arc<Sender<UpdateUI>>, timer_start: Instant, capture_box: CaptureBox,
+                         config: Arc<Config>) {
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(config.capture.screenshot_delay_ms));
+        let monitors = get_monitors();
+        let Some(monitor) = monitors.iter().find(|m| m.name() == config.capture.monitor).or(monitors.first()) else {
+            error!("no monitor to capture");
+            let _ = sender.send_blocking(UpdateUI::Calibrated(None));
+            return;
+        };
+        let mut samples = vec![];
+        let mut failures = 0;
+        for _ in 0..config.capture.calibration_samples {
+            let start = Instant::now();
+            let image = match monitor.capture_image() {
+                Ok(image) => image,
+                Err(e) => {
+                    warn!(error:% = e; "error on image capture");
+                    failures += 1;
+                    continue;
+                }
+            };
+            let end = Instant::now();
+            match read_local_timer(&DynamicImage::ImageRgba8(image), &capture_box, &config) {
+                Ok(reading) => samples.push(CaptureSample {
+                    start: start.saturating_duration_since(timer_start),
+                    end: end.saturating_duration_since(timer_start),
+                    reading,
+                }),
+                Err(reason) => {
+                    warn!(reason:? = reason; "calibration capture failed");
+                    failures += 1;
+                }
+            }
+        }
+        let calibration = CaptureCalibration::from_samples(&samples, failures);
+        match &calibration {
+            Some(c) => info!(latency_ms = c.latency_ms.median, jitter_ms = c.latency_ms.stddev,
+                             uncertainty_ms = c.uncertainty_ms, capture_ms = c.capture_ms.median;
+                             "capture latency calibrated"),
+            None => warn!(failures = failures; "capture latency calibration failed"),
+        }
+        let _ = sender.send_blocking(UpdateUI::Calibrated(calibration));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(start_ms: u64, end_ms: u64, reading_ms: u64) -> CaptureSample {
+        CaptureSample {
+            start: Duration::from_millis(start_ms),
+            end: Duration::from_millis(end_ms),
+            reading: Duration::from_millis(reading_ms),
+        }
+    }
+
+    #[test]
+    fn latency_is_measured_from_the_frame_to_the_end_of_the_capture() {
+        // the frames were drawn 5 to 25 ms before the capture calls returned
+        let samples: Vec<CaptureSample> = (0..=20)
+            .map(|i| sample(1000 * i + 20, 1000 * i + 30, 1000 * i + 5 + i))
+            .collect();
+        let calibration = CaptureCalibration::from_samples(&samples, 2).unwrap();
+        assert_eq!(calibration.failures, 2);
+        assert_eq!(calibration.capture_ms.median, 10.0);
+        assert_eq!(calibration.latency_ms.min, 5.0);
+        assert_eq!(calibration.latency_ms.max, 25.0);
+        assert_eq!(calibration.latency_ms.median, 15.0);
+        assert!((calibration.uncertainty_ms - 9.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn no_readings_give_no_calibration() {
+        assert_eq!(CaptureCalibration::from_samples(&[], 20), None);
+    }
+}
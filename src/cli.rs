@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+
+#[derive(Parser, Debug)]
+#[command(version, about = "A tool for measuring the delay of remote displays.")]
+pub struct Cli {
+    /// Config file to use instead of the one in the platform config directory
+    #[arg(long, env = "LATENCY_TESTER_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Overrides a config value, can be given multiple times
+    #[arg(long = "set", value_name = "SECTION.KEY=VALUE", global = true)]
+    pub overrides: Vec<String>,
+
+    /// Shortcut for --set network.address=ADDRESS
+    #[arg(long)]
+    pub address: Option<String>,
+
+    /// Profile to use, shortcut for --set session.profile=PROFILE
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Scenario file, shortcut for --set session.scenario=FILE
+    #[arg(long)]
+    pub scenario: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Compares two exported result files (.csv or .jsonl), exits with 1 on a regression
+    Compare {
+        baseline: PathBuf,
+        candidate: PathBuf,
+    },
+    /// Runs a local and a remote instance in this process on network.address with the configured
+    /// impairment and prints the ping statistics
+    Loopback {
+        #[arg(long, default_value_t = 10)]
+        seconds: u64,
+    },
+}
+
+impl Cli {
+    // All overrides, the dedicated options come last so they take precedence.
+    pub fn config_overrides(&self) -> Vec<String> {
+        let mut overrides = self.overrides.clone();
+        if let Some(profile) = &self.profile {
+            overrides.push(format!("session.profile={:?}", profile));
+        }
+        if let Some(scenario) = &self.scenario {
+            overrides.push(format!("session.scenario={:?}", scenario.display().to_string()));
+        }
+        if let Some(address) = &self.address {
+            overrides.push(format!("network.address={:?}", address));
+        }
+        overrides
+    }
+}
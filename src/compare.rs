@@ -0,0 +1,284 @@
+// Comparison of two exported result files: differences of median and p95 with bootstrap confidence
+// intervals and a Mann-Whitney U test, used to detect latency regressions.
+use std::path::Path;
+use crate::config::{CompareConfig, MeasureMode};
+use crate::results::{read_results, Measurement};
+use crate::stats::{quantile, Summary};
+
+// fixed seed so the same files always give the same intervals
+const BOOTSTRAP_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+pub struct Comparison {
+    pub baseline: Summary,
+    pub candidate: Summary,
+    // candidate minus baseline in milliseconds with the bootstrap confidence interval
+    pub median_diff: Estimate,
+    pub p95_diff: Estimate,
+    // two-sided p-value of the Mann-Whitney U test
+    pub p_value: f64,
+}
+
+pub struct Estimate {
+    pub value: f64,
+    pub low: f64,
+    pub high: f64,
+}
+
+impl Comparison {
+    pub fn new(baseline: &[f64], candidate: &[f64], config: &CompareConfig) -> Result<Comparison, String> {
+        let summary = |samples: &[f64], name: &str| Summary::from_samples(samples)
+            .ok_or(format!("{} has no measurements", name));
+        let baseline_summary = summary(baseline, "baseline")?;
+        let candidate_summary = summary(candidate, "candidate")?;
+        let (median_diff, p95_diff) = bootstrap(baseline, candidate, config.bootstrap_samples, config.confidence);
+        Ok(Comparison {
+            median_diff: Estimate { value: candidate_summary.median - baseline_summary.median, ..median_diff },
+            p95_diff: Estimate { value: candidate_summary.p95 - baseline_summary.p95, ..p95_diff },
+            baseline: baseline_summary,
+            candidate: candidate_summary,
+            p_value: mann_whitney_p(baseline, candidate),
+        })
+    }
+
+    // Reasons why the candidate counts as regression: a difference above its threshold whose confidence interval
+    // is above zero. The Mann-Whitney test only sees shifts of the whole distribution, it is only required for
+    // the median, a slower tail is found by its interval alone.
+    pub fn regressions(&self, config: &CompareConfig) -> Vec<String> {
+        let mut regressions = vec![];
+        if self.median_diff.value > config.max_median_increase_ms && self.median_diff.low > 0.0
+            && self.p_value < config.significance {
+            regressions.push(format!("median increased by {:.1} ms, more than {} ms",
+                                     self.median_diff.value, config.max_median_increase_ms));
+        }
+        if self.p95_diff.value > config.max_p95_increase_ms && self.p95_diff.low > 0.0 {
+            regressions.push(format!("p95 increased by {:.1} ms, more than {} ms",
+                                     self.p95_diff.value, config.max_p95_increase_ms));
+        }
+        regressions
+    }
+
+    pub fn print(&self, config: &CompareConfig) {
+        println!("{:<10} {:>8} {:>10} {:>10} {:>10}", "", "samples", "median", "p95", "mean");
+        for (name, s) in [("baseline", &self.baseline), ("candidate", &self.candidate)] {
+            println!("{:<10} {:>8} {:>7.1} ms {:>7.1} ms {:>7.1} ms", name, s.count, s.median, s.p95, s.mean);
+        }
+        let confidence = config.confidence * 100.0;
+        for (name, e) in [("median", &self.median_diff), ("p95", &self.p95_diff)] {
+            println!("{} difference: {:+.1} ms ({}% CI {:+.1} .. {:+.1} ms)", name, e.value, confidence, e.low, e.high);
+        }
+        println!("Mann-Whitney U test: p = {:.4}", self.p_value);
+    }
+}
+
+// Compares two result files and returns whether the candidate is a regression. Display delays and input
+// latencies are compared separately, a mode is skipped if one of the files has no measurements of it.
+pub fn compare_files(baseline: &Path, candidate: &Path, config: &CompareConfig)
+                     -> Result<bool, Box<dyn std::error::Error>> {
+    let (baseline, candidate) = (read_results(baseline)?, read_results(candidate)?);
+    let delays = |results: &[Measurement], mode: MeasureMode| -> Vec<f64> {
+        results.iter().filter(|m| m.mode == mode && !m.excluded).map(|m| m.delay_ms).collect()
+    };
+    let modes: Vec<MeasureMode> = MeasureMode::ALL.into_iter()
+        .filter(|&mode| !delays(&baseline, mode).is_empty() && !delays(&candidate, mode).is_empty())
+        .collect();
+    if modes.is_empty() {
+        return Err("the files have no measurements of the same mode".into());
+    }
+    let mut regression = false;
+    for mode in modes {
+        println!("{} latency", mode.name());
+        let comparison = Comparison::new(&delays(&baseline, mode), &delays(&candidate, mode), config)?;
+        comparison.print(config);
+        let regressions = comparison.regressions(config);
+        for regression in &regressions {
+            println!("REGRESSION: {}", regression);
+        }
+        regression |= !regressions.is_empty();
+    }
+    Ok(regression)
+}
+
+// Percentile bootstrap intervals of the median and p95 differences, only `low` and `high` are set.
+fn bootstrap(baseline: &[f64], candidate: &[f64], samples: usize, confidence: f64) -> (Estimate, Estimate) {
+    let mut rng = XorShift(BOOTSTRAP_SEED);
+    let mut median_diffs = Vec::with_capacity(samples);
+    let mut p95_diffs = Vec::with_capacity(samples);
+    let mut b = vec![0.0; baseline.len()];
+    let mut c = vec![0.0; candidate.len()];
+    for _ in 0..samples.max(1) {
+        rng.resample(baseline, &mut b);
+        rng.resample(candidate, &mut c);
+        median_diffs.push(quantile(&c, 0.5) - quantile(&b, 0.5));
+        p95_diffs.push(quantile(&c, 0.95) - quantile(&b, 0.95));
+    }
+    let alpha = (1.0 - confidence) / 2.0;
+    let interval = |mut diffs: Vec<f64>| {
+        diffs.sort_by(f64::total_cmp);
+        Estimate { value: f64::NAN, low: quantile(&diffs, alpha), high: quantile(&diffs, 1.0 - alpha) }
+    };
+    (interval(median_diffs), interval(p95_diffs))
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // Fills `out` with values drawn with replacement from `samples` and sorts them.
+    fn resample(&mut self, samples: &[f64], out: &mut [f64]) {
+        for value in out.iter_mut() {
+            *value = samples[(self.next() % samples.len() as u64) as usize];
+        }
+        out.sort_by(f64::total_cmp);
+    }
+}
+
+// Two-sided p-value of the Mann-Whitney U test with the normal approximation, corrected for ties.
+pub fn mann_whitney_p(a: &[f64], b: &[f64]) -> f64 {
+    let (n1, n2) = (a.len() as f64, b.len() as f64);
+    if a.is_empty() || b.is_empty() {
+        return 1.0;
+    }
+    let mut values: Vec<(f64, bool)> = a.iter().map(|&v| (v, true)).chain(b.iter().map(|&v| (v, false))).collect();
+    values.sort_by(|x, y| x.0.total_cmp(&y.0));
+    let n = values.len() as f64;
+    let mut rank_sum_a = 0.0;
+    let mut tie_term = 0.0;
+    let mut i = 0;
+    while i < values.len() {
+        let mut j = i;
+        while j + 1 < values.len() && values[j + 1].0 == values[i].0 {
+            j += 1;
+        }
+        // tied values get the mean of their ranks
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        let ties = (j - i + 1) as f64;
+        tie_term += ties * ties * ties - ties;
+        rank_sum_a += rank * values[i..=j].iter().filter(|v| v.1).count() as f64;
+        i = j + 1;
+    }
+    let u = rank_sum_a - n1 * (n1 + 1.0) / 2.0;
+    let mean = n1 * n2 / 2.0;
+    let variance = n1 * n2 / 12.0 * ((n + 1.0) - tie_term / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        return 1.0;
+    }
+    let z = ((u - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+    erfc(z / std::f64::consts::SQRT_2).min(1.0)
+}
+
+// Complementary error function with a relative error below 1.2e-7 (Numerical Recipes, erfcc).
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t * (-z * z - 1.265_512_23 + t * (1.000_023_68 + t * (0.374_091_96 + t * (0.096_784_18
+        + t * (-0.186_288_06 + t * (0.278_868_07 + t * (-1.135_203_98 + t * (1.488_515_87
+        + t * (-0.822_152_23 + t * 0.170_872_77))))))))).exp();
+    if x >= 0.0 { r } else { 2.0 - r }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+
+    // 40 delays between 30 and 39 ms, shifted by `shift` ms
+    fn delays(shift: f64) -> Vec<f64> {
+        (0..40).map(|i| 30.0 + (i * 7 % 10) as f64 + shift).collect()
+    }
+
+    #[test]
+    fn erfc_matches_reference_values() {
+        for (x, expected) in [(0.0, 1.0), (0.5, 0.479_500_122_186_953_5), (1.0, 0.157_299_207_050_285_13),
+                              (-1.0, 1.842_700_792_949_715), (2.0, 0.004_677_734_981_047_265)] {
+            assert!((erfc(x) - expected).abs() <= expected * 1.2e-7, "erfc({}) = {}", x, erfc(x));
+        }
+    }
+
+    #[test]
+    fn mann_whitney_matches_textbook_examples() {
+        // U = 17, p = 0.1113 with the normal approximation and continuity correction
+        let p = mann_whitney_p(&[19.0, 22.0, 16.0, 29.0, 24.0], &[20.0, 11.0, 17.0, 12.0]);
+        assert!((p - 0.111_346_886_5).abs() < 1e-6, "{}", p);
+        // completely separated samples, U = 0
+        let p = mann_whitney_p(&[1.0, 2.0, 3.0, 4.0, 5.0], &[6.0, 7.0, 8.0, 9.0, 10.0]);
+        assert!((p - 0.012_185_780).abs() < 1e-6, "{}", p);
+        assert!((mann_whitney_p(&delays(0.0), &delays(0.0)) - 1.0).abs() < 1e-9);
+        assert_eq!(mann_whitney_p(&[5.0, 5.0], &[5.0, 5.0, 5.0]), 1.0);
+        assert_eq!(mann_whitney_p(&[], &[5.0]), 1.0);
+    }
+
+    #[test]
+    fn bootstrap_intervals_contain_the_shift() {
+        let (median, p95) = bootstrap(&delays(0.0), &delays(4.0), 2000, 0.95);
+        assert!(median.low <= 4.0 && 4.0 <= median.high, "{} .. {}", median.low, median.high);
+        assert!(median.low > 0.0, "{}", median.low);
+        assert!(p95.low <= 4.0 && 4.0 <= p95.high, "{} .. {}", p95.low, p95.high);
+        // the fixed seed gives the same intervals every time
+        let (again, _) = bootstrap(&delays(0.0), &delays(4.0), 2000, 0.95);
+        assert_eq!((median.low, median.high), (again.low, again.high));
+        let (same, _) = bootstrap(&delays(0.0), &delays(0.0), 2000, 0.95);
+        assert!(same.low <= 0.0 && 0.0 <= same.high);
+    }
+
+    #[test]
+    fn regressions_need_a_significant_increase_above_the_threshold() {
+        let config = CompareConfig::default();
+        let comparison = Comparison::new(&delays(0.0), &delays(8.0), &config).unwrap();
+        assert_eq!(comparison.median_diff.value, 8.0);
+        assert_eq!(comparison.regressions(&config), vec!["median increased by 8.0 ms, more than 5 ms".to_string()]);
+        // below the threshold
+        let comparison = Comparison::new(&delays(0.0), &delays(3.0), &config).unwrap();
+        assert!(comparison.p_value < config.significance);
+        assert!(comparison.regressions(&config).is_empty());
+        // above the threshold, but too few samples to be significant
+        let comparison = Comparison::new(&[30.0, 31.0, 32.0], &[38.0, 39.0, 40.0], &config).unwrap();
+        assert!(comparison.p_value >= config.significance);
+        assert!(comparison.regressions(&config).is_empty());
+        assert!(Comparison::new(&[], &delays(0.0), &config).is_err());
+    }
+
+    #[test]
+    fn slower_tail_is_a_regression() {
+        let config = CompareConfig::default();
+        // the slowest fifth takes 50 ms longer, the median stays
+        let candidate: Vec<f64> = delays(0.0).into_iter().map(|d| if d >= 38.0 { d + 50.0 } else { d }).collect();
+        let comparison = Comparison::new(&delays(0.0), &candidate, &config).unwrap();
+        assert_eq!(comparison.median_diff.value, 0.0);
+        assert!(comparison.p_value >= config.significance, "{}", comparison.p_value);
+        assert!(comparison.p95_diff.low > 0.0, "{}", comparison.p95_diff.low);
+        assert_eq!(comparison.regressions(&config), vec!["p95 increased by 50.0 ms, more than 10 ms".to_string()]);
+        // a p95 difference whose interval reaches below zero is noise
+        let comparison = Comparison::new(&[30.0, 31.0, 32.0, 33.0], &[30.0, 31.0, 32.0, 60.0], &config).unwrap();
+        assert!(comparison.p95_diff.value > config.max_p95_increase_ms);
+        assert!(comparison.regressions(&config).is_empty());
+    }
+
+    #[test]
+    fn shifted_results_fail_the_comparison() {
+        let dir = std::env::temp_dir().join(format!("latency_tester_compare_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, shift: f64| {
+            let path = dir.join(name);
+            let lines: Vec<String> = delays(shift).into_iter()
+                .map(|d| Measurement::new(MeasureMode::Display, Duration::from_secs_f64(d / 1000.0), "", ""))
+                .map(|m| serde_json::to_string(&m).unwrap())
+                .collect();
+            fs::write(&path, lines.join("\n")).unwrap();
+            path
+        };
+        let (baseline, same, slower) = (write("baseline.jsonl", 0.0), write("same.jsonl", 1.0),
+                                        write("slower.jsonl", 6.0));
+        let config = CompareConfig::default();
+        assert!(!compare_files(&baseline, &same, &config).unwrap());
+        assert!(compare_files(&baseline, &slower, &config).unwrap());
+        assert!(compare_files(&baseline, &dir.join("missing.jsonl"), &config).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
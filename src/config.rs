@@ -1,30 +1,830 @@
+use std::collections::BTreeMap;
 use std::fs;
+use std::path::{Path, PathBuf};
 use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+use crate::sinks::{SinkConfig, SinkKind};
 
-#[derive(Deserialize, Serialize, Debug)]
+pub const CONFIG_VERSION: u32 = 1;
+const APP_DIR: &str = "latency_tester";
+const CONFIG_FILE: &str = "config.toml";
+// config file of older versions, read from the working directory if there is no config yet
+const LEGACY_CONFIG_PATH: &str = "latency_reader.toml";
+const ENV_PREFIX: &str = "LATENCY_TESTER_";
+// keys without a default value, they are missing in `Config::default_table`
+const OPTIONAL_KEYS: [&str; 4] = ["capture.capture_box", "input.patch", "input.click", "flash.region"];
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
 pub struct Config {
-    #[serde(default = "default_address")]
+    pub version: u32,
+    pub network: NetworkConfig,
+    pub impairment: ImpairmentConfig,
+    pub capture: CaptureConfig,
+    pub detection: DetectionConfig,
+    pub ocr: OcrConfig,
+    pub session: SessionConfig,
+    pub workload: WorkloadConfig,
+    pub input: InputConfig,
+    pub flash: FlashConfig,
+    pub quality: QualityConfig,
+    pub output: OutputConfig,
+    pub metrics: MetricsConfig,
+    pub control: ControlConfig,
+    pub compare: CompareConfig,
+    // services every measurement is pushed to
+    pub sinks: Vec<SinkConfig>,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct NetworkConfig {
     pub address: String,
+    pub transport: TransportKind,
+    pub ping_interval_ms: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    Udp,
+    Tcp,
+    Ws,
+}
+
+// Simulated impairment of the received network messages, for testing only.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct ImpairmentConfig {
+    pub delay_ms: u64,
+    // the delay varies uniformly by up to this much in both directions
+    pub jitter_ms: u64,
+    // probabilities per message
+    pub loss: f64,
+    pub duplicate: f64,
+    pub reorder: f64,
+    // extra delay of reordered messages, so that later messages overtake them
+    pub reorder_delay_ms: u64,
+    // seed of the random decisions, the same seed gives the same decisions for the same messages
+    pub seed: u64,
+}
+
+// `capture.monitor` capturing every monitor, the windows of both instances may be on different monitors
+pub const ALL_MONITORS: &str = "*";
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct CaptureConfig {
+    // retries after the first failed capture
+    pub max_tries: i32,
+    // screenshots taken by the capture latency calibration
+    pub calibration_samples: u32,
+    // time between starting the timers and the first screenshot
+    pub screenshot_delay_ms: u64,
+    // name of the monitor to capture, the first one if empty, all of them if `ALL_MONITORS`
+    pub monitor: String,
+    // title of the VDI client window showing the remote instance, if set only this window and the one titled
+    // `local_window` are captured instead of the monitors
+    pub remote_window: String,
+    pub local_window: String,
+    // area around the located window marker that contains the timer, derived from the timer label if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture_box: Option<CaptureBoxConfig>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct CaptureBoxConfig {
+    pub width: i32,
+    pub height: i32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct DetectionConfig {
+    // fraction of matching pixels needed to accept a window marker
+    pub min_confidence: f64,
+    // maximal difference per color channel for a pixel to match
+    pub tolerance: u8,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OcrEngine {
+    Builtin,
+    Tesseract,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct OcrConfig {
+    pub engine: OcrEngine,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SessionConfig {
+    // refresh interval of the timer label
+    pub timer_tick_ms: u64,
+    // profile applied on top of this config, none if empty
+    pub profile: String,
+    // scenario file run by "Run Scenario", asked for if empty
+    pub scenario: String,
+    // what a measurement measures
+    pub mode: MeasureMode,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MeasureMode {
+    // delay of the remote timer compared to the local one
+    #[default]
+    Display,
+    // time from an injected input to the reaction of the remote on the screen
+    Input,
+    // time from asking the remote for another color to the color showing up on the screen
+    Flash,
+}
+
+impl MeasureMode {
+    pub const ALL: [MeasureMode; 3] = [MeasureMode::Display, MeasureMode::Input, MeasureMode::Flash];
+
+    // name used in the config file
+    pub fn name(&self) -> &'static str {
+        match self {
+            MeasureMode::Display => "display",
+            MeasureMode::Input => "input",
+            MeasureMode::Flash => "flash",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkloadKind {
+    None,
+    Noise,
+    Text,
+    Gradient,
+}
+
+impl WorkloadKind {
+    pub const ALL: [WorkloadKind; 4] = [WorkloadKind::None, WorkloadKind::Noise, WorkloadKind::Text,
+                                        WorkloadKind::Gradient];
+
+    // name used in the config file
+    pub fn name(&self) -> &'static str {
+        match self {
+            WorkloadKind::None => "none",
+            WorkloadKind::Noise => "noise",
+            WorkloadKind::Text => "text",
+            WorkloadKind::Gradient => "gradient",
+        }
+    }
+}
+
+// Load rendered by the remote instance, sent to it by the local instance.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct WorkloadConfig {
+    pub kind: WorkloadKind,
+    // size of the load window, full screen if 0
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+}
+
+// Input latency measurements: the input injected with XTest and how the reaction of the remote is seen.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct InputConfig {
+    pub method: InputMethod,
+    // X11 keycode of the injected key, 65 is the space bar
+    pub keycode: u8,
+    // area of the captured monitor showing the color patch of the remote
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patch: Option<Region>,
+    // position of the click on the captured monitor, the center of the patch if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub click: Option<(i32, i32)>,
+    // mean difference of the color channels to the patch before the input that counts as changed
+    pub threshold: f64,
+    // time between asking the remote to react to input and injecting it
+    pub arm_delay_ms: u64,
+    // time after the input until the measurement fails without a change of the patch
+    pub timeout_ms: u64,
+}
+
+// Color flash measurements: the remote fills its color patch with the next color on command and the local
+// instance reads the patch until the color changed.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct FlashConfig {
+    // RGB colors the patch cycles through, consecutive colors have to differ by more than `threshold`
+    pub colors: Vec<[u8; 3]>,
+    // area around the located marker of the remote that is read, derived from the patch if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<CaptureBoxConfig>,
+    // mean difference of the color channels to the color before the flash that counts as changed
+    pub threshold: f64,
+    // time after the command until the measurement fails without a change of the color
+    pub timeout_ms: u64,
+}
+
+// Display delays below these limits are recorded but left out of the statistics.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct QualityConfig {
+    // lower OCR confidence of the two timers, in [0, 1]
+    pub min_ocr_confidence: f32,
+    // estimated error from the timer tick and the monitor refresh
+    pub max_uncertainty_ms: f64,
+    // leave out delays whose remote timer shows the same value as in the previous measurement
+    pub exclude_stale: bool,
+    // timer readings may be this far off the stopwatch time of the capture before they are rejected
+    pub reading_tolerance_ms: u64,
+    // delays above this are taken as a misread remote timer and rejected
+    pub max_delay_ms: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InputMethod {
+    // press and release `keycode`, the focused window receives it
+    Key,
+    // left click at `click`
+    Click,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width as i32 && y < self.y + self.height as i32
+    }
+
+    pub fn inside(&self, other: &Region) -> bool {
+        self.x >= other.x && self.y >= other.y && self.x + self.width as i32 <= other.x + other.width as i32
+            && self.y + self.height as i32 <= other.y + other.height as i32
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct OutputConfig {
+    // one of error, warn, info, debug, trace
+    pub log_level: String,
+    // empty to disable the log file
+    pub log_file: String,
+    pub log_max_size: u64,
+    // number of rotated log files to keep
+    pub log_files: usize,
+    // overwrite debug.jpg in the working directory with the annotated screenshot of every capture attempt
+    pub debug_image: bool,
+    // save a directory with screenshots, OCR crops and a summary for every failed capture attempt
+    pub debug_artifacts: bool,
+    pub debug_dir: String,
+    // number of attempt directories to keep
+    pub debug_retention: usize,
+    // directory for the CSV and JSON lines files with the measurements of each session
+    pub results_dir: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct MetricsConfig {
+    // serve Prometheus metrics on http://<address>/metrics
+    pub enabled: bool,
+    pub address: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ControlConfig {
+    // serve the HTTP control API on http://<address>, only on loopback addresses as it is not authenticated
+    pub enabled: bool,
+    pub address: String,
+}
+
+// Thresholds of the compare command, a candidate is a regression if a difference is above its
+// threshold and its confidence interval above zero. A median increase also needs a significant Mann-Whitney test.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct CompareConfig {
+    pub max_median_increase_ms: f64,
+    pub max_p95_increase_ms: f64,
+    pub significance: f64,
+    // level of the bootstrap confidence intervals
+    pub confidence: f64,
+    pub bootstrap_samples: usize,
+}
+
+// Named set of settings for one test target, unset values are taken from the rest of the config.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Profile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport: Option<TransportKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture_box: Option<CaptureBoxConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detection: Option<DetectionConfig>,
+    pub notes: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            version: CONFIG_VERSION,
+            network: NetworkConfig::default(),
+            impairment: ImpairmentConfig::default(),
+            capture: CaptureConfig::default(),
+            detection: DetectionConfig::default(),
+            ocr: OcrConfig::default(),
+            session: SessionConfig::default(),
+            workload: WorkloadConfig::default(),
+            input: InputConfig::default(),
+            flash: FlashConfig::default(),
+            quality: QualityConfig::default(),
+            output: OutputConfig::default(),
+            metrics: MetricsConfig::default(),
+            control: ControlConfig::default(),
+            compare: CompareConfig::default(),
+            sinks: vec![],
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            address: "127.0.0.1:4999".to_string(),
+            transport: TransportKind::Udp,
+            ping_interval_ms: 1000,
+        }
+    }
+}
+
+impl Default for ImpairmentConfig {
+    fn default() -> Self {
+        ImpairmentConfig {
+            delay_ms: 0,
+            jitter_ms: 0,
+            loss: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            reorder_delay_ms: 100,
+            seed: 1,
+        }
+    }
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            max_tries: 3,
+            calibration_samples: 20,
+            screenshot_delay_ms: 2000,
+            monitor: String::new(),
+            remote_window: String::new(),
+            local_window: "Latency Tester".to_string(),
+            capture_box: None,
+        }
+    }
 }
 
-fn default_address() -> String {
-    "127.0.0.1:4999".to_string()
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        DetectionConfig {
+            min_confidence: 0.9,
+            tolerance: 10,
+        }
+    }
+}
+
+impl Default for OcrConfig {
+    fn default() -> Self {
+        OcrConfig {
+            engine: if cfg!(feature = "tesseract") { OcrEngine::Tesseract } else { OcrEngine::Builtin },
+        }
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            timer_tick_ms: 4,
+            profile: String::new(),
+            scenario: String::new(),
+            mode: MeasureMode::Display,
+        }
+    }
+}
+
+impl Default for WorkloadConfig {
+    fn default() -> Self {
+        WorkloadConfig {
+            kind: WorkloadKind::None,
+            width: 1280,
+            height: 720,
+            fps: 30,
+        }
+    }
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        InputConfig {
+            method: InputMethod::Key,
+            keycode: 65,
+            patch: None,
+            click: None,
+            threshold: 60.0,
+            arm_delay_ms: 300,
+            timeout_ms: 2000,
+        }
+    }
+}
+
+impl Default for FlashConfig {
+    fn default() -> Self {
+        FlashConfig {
+            colors: vec![[0, 0, 0], [255, 255, 255]],
+            region: None,
+            threshold: 60.0,
+            timeout_ms: 2000,
+        }
+    }
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        QualityConfig {
+            min_ocr_confidence: 0.5,
+            max_uncertainty_ms: 50.0,
+            exclude_stale: true,
+            reading_tolerance_ms: 250,
+            max_delay_ms: 2000,
+        }
+    }
 }
 
-pub fn read_config(path: &str) -> Result<Config, Box<dyn std::error::Error>> {
-    let mut data = fs::read(path);
-    if matches!(data,Err(_)) {
-        fs::write(path, "").expect(format!("error creating config file {:?}", path).as_str());
-        data = fs::read(path);
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            log_level: "info".to_string(),
+            log_file: "latency_tester.log".to_string(),
+            log_max_size: 1024 * 1024,
+            log_files: 3,
+            debug_image: false,
+            debug_artifacts: false,
+            debug_dir: "debug".to_string(),
+            debug_retention: 20,
+            results_dir: "results".to_string(),
+        }
     }
-    let data = data.unwrap();
-    let text = String::from_utf8(data)?;
-    let config: Config = toml::from_str(&text)?;
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            enabled: false,
+            address: "127.0.0.1:9464".to_string(),
+        }
+    }
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        ControlConfig {
+            enabled: false,
+            address: "127.0.0.1:9465".to_string(),
+        }
+    }
+}
+
+impl Default for CompareConfig {
+    fn default() -> Self {
+        CompareConfig {
+            max_median_increase_ms: 5.0,
+            max_p95_increase_ms: 10.0,
+            significance: 0.05,
+            confidence: 0.95,
+            bootstrap_samples: 2000,
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = vec![];
+        if self.version > CONFIG_VERSION {
+            errors.push(format!("version {} is newer than the supported version {}", self.version, CONFIG_VERSION));
+        }
+        if !self.network.address.contains(':') {
+            errors.push(format!("network.address {:?} needs a port", self.network.address));
+        }
+        if self.network.ping_interval_ms == 0 {
+            errors.push("network.ping_interval_ms must be greater than 0".to_string());
+        }
+        for (name, probability) in [("loss", self.impairment.loss), ("duplicate", self.impairment.duplicate),
+                                    ("reorder", self.impairment.reorder)] {
+            if !(0.0..=1.0).contains(&probability) {
+                errors.push(format!("impairment.{} must be in [0, 1]", name));
+            }
+        }
+        if self.capture.max_tries < 0 {
+            errors.push("capture.max_tries must not be negative".to_string());
+        }
+        if self.capture.calibration_samples == 0 {
+            errors.push("capture.calibration_samples must be positive".to_string());
+        }
+        if !(self.detection.min_confidence > 0.0 && self.detection.min_confidence <= 1.0) {
+            errors.push("detection.min_confidence must be in (0, 1]".to_string());
+        }
+        if self.ocr.engine == OcrEngine::Tesseract && !cfg!(feature = "tesseract") {
+            errors.push("ocr.engine tesseract needs a build with the tesseract feature".to_string());
+        }
+        if self.session.timer_tick_ms == 0 {
+            errors.push("session.timer_tick_ms must be greater than 0".to_string());
+        }
+        if self.metrics.enabled && !self.metrics.address.contains(':') {
+            errors.push(format!("metrics.address {:?} needs a port", self.metrics.address));
+        }
+        if self.control.enabled && !is_loopback(&self.control.address) {
+            errors.push(format!("control.address {:?} must be a loopback address with a port", self.control.address));
+        }
+        if !(self.compare.significance > 0.0 && self.compare.significance < 1.0) {
+            errors.push("compare.significance must be in (0, 1)".to_string());
+        }
+        if !(self.compare.confidence > 0.0 && self.compare.confidence < 1.0) {
+            errors.push("compare.confidence must be in (0, 1)".to_string());
+        }
+        for sink in &self.sinks {
+            match &sink.kind {
+                SinkKind::InfluxHttp { url, .. } | SinkKind::Webhook { url } if !url.starts_with("http://") => {
+                    errors.push(format!("sink url {:?} must start with http://", url));
+                }
+                SinkKind::InfluxUdp { address } if !address.contains(':') => {
+                    errors.push(format!("sink address {:?} needs a port", address));
+                }
+                _ => {}
+            }
+        }
+        if !(self.input.threshold > 0.0 && self.input.threshold < 255.0) {
+            errors.push("input.threshold must be in (0, 255)".to_string());
+        }
+        if self.input.patch.is_some_and(|p| p.width == 0 || p.height == 0) {
+            errors.push("input.patch must not be empty".to_string());
+        }
+        if !(self.flash.threshold > 0.0 && self.flash.threshold < 255.0) {
+            errors.push("flash.threshold must be in (0, 255)".to_string());
+        }
+        let colors = &self.flash.colors;
+        if colors.len() < 2 {
+            errors.push("flash.colors needs at least two colors".to_string());
+        } else if (0..colors.len()).any(|i| {
+            let (a, b) = (colors[i], colors[(i + 1) % colors.len()]);
+            a.iter().zip(b).map(|(a, b)| a.abs_diff(b) as f64).sum::<f64>() / 3.0 <= self.flash.threshold
+        }) {
+            errors.push("consecutive flash.colors must differ by more than flash.threshold".to_string());
+        }
+        if self.flash.region.is_some_and(|r| r.width <= 0 || r.height <= 0) {
+            errors.push("flash.region must not be empty".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.quality.min_ocr_confidence) {
+            errors.push("quality.min_ocr_confidence must be in [0, 1]".to_string());
+        }
+        if self.quality.max_uncertainty_ms <= 0.0 {
+            errors.push("quality.max_uncertainty_ms must be positive".to_string());
+        }
+        if !(1..=240).contains(&self.workload.fps) {
+            errors.push("workload.fps must be in [1, 240]".to_string());
+        }
+        if self.output.log_level.parse::<log::LevelFilter>().is_err() {
+            errors.push(format!("output.log_level {:?} is not a log level", self.output.log_level));
+        }
+        if !self.session.profile.is_empty() && !self.profiles.contains_key(&self.session.profile) {
+            errors.push(format!("session.profile {:?} does not exist", self.session.profile));
+        }
+        for (name, profile) in &self.profiles {
+            if profile.address.as_ref().is_some_and(|a| !a.contains(':')) {
+                errors.push(format!("address of profile {:?} needs a port", name));
+            }
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors.join(", ")) }
+    }
+
+    // Returns this config with the settings of profile `name` applied.
+    pub fn with_profile(&self, name: &str) -> Result<Config, String> {
+        let profile = self.profiles.get(name).ok_or(format!("profile {:?} does not exist", name))?;
+        let mut config = self.clone();
+        config.session.profile = name.to_string();
+        if let Some(address) = &profile.address {
+            config.network.address = address.clone();
+        }
+        if let Some(transport) = profile.transport {
+            config.network.transport = transport;
+        }
+        if let Some(monitor) = &profile.monitor {
+            config.capture.monitor = monitor.clone();
+        }
+        if profile.capture_box.is_some() {
+            config.capture.capture_box = profile.capture_box;
+        }
+        if let Some(detection) = &profile.detection {
+            config.detection = detection.clone();
+        }
+        Ok(config)
+    }
+
+    fn default_table() -> Table {
+        Value::try_from(Config::default()).ok()
+            .and_then(|v| v.as_table().cloned())
+            .unwrap_or_default()
+    }
+}
+
+// Config file in the platform config directory, e.g. ~/.config/latency_tester/config.toml
+pub fn default_config_path() -> PathBuf {
+    match preferences::prefs_base_dir() {
+        Some(dir) => dir.join(APP_DIR).join(CONFIG_FILE),
+        None => PathBuf::from(LEGACY_CONFIG_PATH),
+    }
+}
+
+// True for `host:port` addresses that only accept connections of this machine.
+fn is_loopback(address: &str) -> bool {
+    match address.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => {
+            host == "localhost" || host.trim_start_matches('[').trim_end_matches(']')
+                .parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback())
+        }
+        _ => false,
+    }
+}
+
+// Reads the config file and applies environment (`LATENCY_TESTER_<SECTION>_<KEY>=value`)
+// and command line (`section.key=value`) overrides, in that order.
+// The profile selected by `session.profile` is applied below the overrides.
+pub fn load_config(path: &Path, overrides: &[String]) -> Result<Config, Box<dyn std::error::Error>> {
+    let env = env_overrides(std::env::vars());
+    let mut table = read_table(path)?;
+    apply_overrides(&mut table, &env, overrides)?;
+    let mut config: Config = Value::Table(table).try_into()?;
+    config.validate()?;
+    if !config.session.profile.is_empty() {
+        let mut table = Value::try_from(config.with_profile(&config.session.profile)?)?
+            .as_table().cloned().unwrap_or_default();
+        apply_overrides(&mut table, &env, overrides)?;
+        config = Value::Table(table).try_into()?;
+        config.validate()?;
+    }
+    Ok(config)
+}
+
+fn apply_overrides(table: &mut Table, env: &[(String, String)], overrides: &[String]) -> Result<(), String> {
+    for (key, value) in env {
+        set_value(table, key, value)?;
+    }
+    for entry in overrides {
+        let (key, value) = entry.split_once('=')
+            .ok_or(format!("override {:?} is not of the form section.key=value", entry))?;
+        set_value(table, key.trim(), value.trim())?;
+    }
+    Ok(())
+}
+
+// Reads the config file without overrides, so that it can be modified and written back.
+pub fn read_config(path: &Path) -> Result<Config, Box<dyn std::error::Error>> {
+    let config: Config = Value::Table(read_table(path)?).try_into()?;
     Ok(config)
 }
 
-pub fn write_config(config: &Config, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+pub fn write_config(config: &Config, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
     let text = toml::to_string(config)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
     fs::write(path, text)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+fn read_table(path: &Path) -> Result<Table, Box<dyn std::error::Error>> {
+    let text = if path.exists() {
+        fs::read_to_string(path)?
+    } else if Path::new(LEGACY_CONFIG_PATH).exists() {
+        fs::read_to_string(LEGACY_CONFIG_PATH)?
+    } else {
+        String::new()
+    };
+    let mut table: Table = toml::from_str(&text)?;
+    migrate(&mut table);
+    Ok(table)
+}
+
+// Moves the keys of older config versions to their current place.
+fn migrate(table: &mut Table) {
+    let version = table.get("version").and_then(|v| v.as_integer()).unwrap_or(0);
+    if version < 1 {
+        for (key, section) in [("address", "network"), ("log_level", "output"), ("log_file", "output"),
+                               ("log_max_size", "output"), ("log_files", "output"),
+                               ("debug_artifacts", "output"), ("debug_dir", "output"),
+                               ("debug_retention", "output")] {
+            if let Some(value) = table.remove(key) {
+                let section = table.entry(section).or_insert(Value::Table(Table::new()));
+                if let Some(section) = section.as_table_mut() {
+                    section.insert(key.to_string(), value);
+                }
+            }
+        }
+        table.insert("version".to_string(), Value::Integer(1));
+    }
+}
+
+// Overrides of the `LATENCY_TESTER_<SECTION>_<KEY>` variables among `vars`, variables of unknown keys are skipped.
+fn env_overrides(vars: impl Iterator<Item = (String, String)>) -> Vec<(String, String)> {
+    vars.filter_map(|(name, value)| {
+            let (section, key) = name.strip_prefix(ENV_PREFIX)?.split_once('_')?;
+            let key = format!("{}.{}", section.to_lowercase(), key.to_lowercase());
+            if !is_known_key(&key) {
+                // the log is not set up before the config is read
+                eprintln!("ignoring environment variable {}, {:?} is not a config key", name, key);
+                return None;
+            }
+            Some((key, value))
+        })
+        .collect()
+}
+
+// True for `section.key` keys of the config.
+fn is_known_key(key: &str) -> bool {
+    OPTIONAL_KEYS.contains(&key) || key.split_once('.').is_some_and(|(section, name)| {
+        Config::default_table().get(section).and_then(|s| s.as_table()).is_some_and(|s| s.contains_key(name))
+    })
+}
+
+// Sets `section.key` to `raw`, which is parsed as a TOML value and taken as string if that fails.
+fn set_value(table: &mut Table, key: &str, raw: &str) -> Result<(), String> {
+    let (section, name) = key.split_once('.').ok_or(format!("key {:?} needs a section", key))?;
+    let value = toml::from_str::<Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut t| t.remove("value"))
+        .unwrap_or(Value::String(raw.to_string()));
+    if !is_known_key(key) {
+        return Err(format!("unknown config key {:?}", key));
+    }
+    let section = table.entry(section).or_insert(Value::Table(Table::new()));
+    section.as_table_mut().ok_or(format!("{:?} is not a section", key))?
+        .insert(name.to_string(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_environment_keys_are_skipped() {
+        let vars = [("LATENCY_TESTER_CAPTURE_MAX_TRIES", "5"), ("LATENCY_TESTER_CAPTURE_MAX_TRYS", "5"),
+                    ("LATENCY_TESTER_UNKNOWN_KEY", "1"), ("LATENCY_TESTER_CONFIG", "config.toml"), ("HOME", "/root")]
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+        assert_eq!(env_overrides(vars.into_iter()), vec![("capture.max_tries".to_string(), "5".to_string())]);
+    }
+
+    #[test]
+    fn capture_box_can_be_overridden() {
+        let mut table = Config::default_table();
+        let env = [("capture.max_tries".to_string(), "5".to_string()),
+                   ("input.patch".to_string(), "{ x = 10, y = 20, width = 30, height = 40 }".to_string())];
+        let overrides = ["capture.capture_box = { width = 120, height = 30, x_offset = -130, y_offset = 0 }",
+                         "input.click = [100, 200]",
+                         "flash.region = { width = 20, height = 20, x_offset = 40, y_offset = 0 }"]
+            .map(String::from);
+        apply_overrides(&mut table, &env, &overrides).unwrap();
+        let config: Config = Value::Table(table).try_into().unwrap();
+        assert_eq!(config.capture.max_tries, 5);
+        assert_eq!(config.capture.capture_box,
+                   Some(CaptureBoxConfig { width: 120, height: 30, x_offset: -130, y_offset: 0 }));
+        assert_eq!(config.input.patch, Some(Region { x: 10, y: 20, width: 30, height: 40 }));
+        assert_eq!(config.input.click, Some((100, 200)));
+        assert_eq!(config.flash.region,
+                   Some(CaptureBoxConfig { width: 20, height: 20, x_offset: 40, y_offset: 0 }));
+        let vars = [("LATENCY_TESTER_INPUT_PATCH".to_string(), "{ x = 0, y = 0, width = 8, height = 8 }".to_string())];
+        assert_eq!(env_overrides(vars.into_iter()).len(), 1);
+        assert!(set_value(&mut Config::default_table(), "capture.capture_boxes", "1").is_err());
+    }
+}
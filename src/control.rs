@@ -0,0 +1,269 @@
+// HTTP control API for scripted test runs, served as JSON on top of the application core. Commands
+// go through `App::execute` like the ones of the window, so the window follows their effects.
+//
+//   GET  /status    role, address, listening address, profile, scenario and measurement state
+//   GET  /monitors  monitors that can be captured
+//   GET  /results   measurements of the session
+//   GET  /stats     delay and ping statistics of the session
+//   POST /connect   {"address": "host:port"}, the address is optional
+//   POST /listen    {"address": "host:port"}, the address is optional
+//   POST /measure
+//   POST /calibrate
+//   POST /scenario  {"path": "scenario.toml"}
+//   POST /cancel
+//   POST /profile   {"name": "..."}
+//   POST /monitor   {"name": "..."}
+//   POST /window    {"role": "remote" | "local", "title": "..."}, an empty title unlocks the capture
+//   POST /window/pick {"role": "remote" | "local"}, the window clicked next
+//   POST /workload  {"kind": "none" | "noise" | "text" | "gradient"}
+//   POST /mode      {"mode": "display" | "input" | "flash"}
+//   POST /report
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use log::{error, info, warn};
+use serde::Serialize;
+use serde_json::{json, Value};
+use crate::app::{App, Command};
+use crate::config::{MeasureMode, WorkloadKind};
+use crate::screenshot::{get_monitors, WindowRole};
+
+// larger request bodies are refused, the commands only take a few short strings
+const MAX_BODY: usize = 64 * 1024;
+
+#[derive(Serialize)]
+struct MonitorInfo {
+    name: String,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    frequency: f32,
+    primary: bool,
+}
+
+// Serves the control API on `address` from its own thread and returns the address it listens on, with the port
+// chosen by the system for port 0.
+pub fn serve(app: Arc<App>, address: &str) -> Option<SocketAddr> {
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(address = address, error:% = e; "can not serve control API");
+            return None;
+        }
+    };
+    let local_address = listener.local_addr().ok();
+    info!(address = address; "serving control API");
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = handle(stream, &app) {
+                warn!(error:% = e; "error answering control request");
+            }
+        }
+    });
+    local_address
+}
+
+fn handle(stream: TcpStream, app: &App) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let mut content_length = 0;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+        header.clear();
+    }
+    let mut parts = request.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let (status, body) = if content_length > MAX_BODY {
+        ("413 Payload Too Large", json!({ "error": "request body is too large" }))
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        respond(app, method, path, &body)
+    };
+    let body = body.to_string();
+    let mut stream = &stream;
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                    Connection: close\r\n\r\n{}", status, body.len(), body)?;
+    stream.flush()
+}
+
+fn respond(app: &App, method: &str, path: &str, body: &[u8]) -> (&'static str, Value) {
+    let result = match (method, path) {
+        ("GET", "/status") => Ok(json!(app.status())),
+        ("GET", "/monitors") => Ok(json!(monitors())),
+        ("GET", "/results") => Ok(json!(app.measurements())),
+        ("GET", "/stats") => Ok(json!(app.stats())),
+        ("POST", _) => match command(path, body) {
+            Some(Ok(commands)) => commands.into_iter().try_for_each(|c| app.execute(c)).map(|_| json!(app.status())),
+            Some(Err(e)) => return ("400 Bad Request", json!({ "error": e })),
+            None => return ("404 Not Found", json!({ "error": "not found" })),
+        },
+        _ => return ("404 Not Found", json!({ "error": "not found" })),
+    };
+    match result {
+        Ok(value) => ("200 OK", value),
+        // the request was valid but the application refused it, e.g. while a scenario is running
+        Err(e) => ("409 Conflict", json!({ "error": e })),
+    }
+}
+
+// Commands of a POST request, None for unknown paths.
+fn command(path: &str, body: &[u8]) -> Option<Result<Vec<Command>, String>> {
+    let arguments: Value = if body.is_empty() {
+        json!({})
+    } else {
+        match serde_json::from_slice(body) {
+            Ok(arguments) => arguments,
+            Err(e) => return Some(Err(format!("invalid JSON: {}", e))),
+        }
+    };
+    let string = |name: &str| arguments.get(name).and_then(Value::as_str).map(str::to_string);
+    let required = |name: &str| string(name).ok_or(format!("{:?} is missing", name));
+    let with_address = |command: Command| match string("address") {
+        Some(address) => vec![Command::SetAddress(address), command],
+        None => vec![command],
+    };
+    Some(match path {
+        "/connect" => Ok(with_address(Command::Connect)),
+        "/listen" => Ok(with_address(Command::Listen)),
+        "/measure" => Ok(vec![Command::Measure]),
+        "/calibrate" => Ok(vec![Command::Calibrate]),
+        "/scenario" => required("path").map(|path| vec![Command::RunScenario(PathBuf::from(path))]),
+        "/cancel" => Ok(vec![Command::CancelScenario]),
+        "/profile" => required("name").map(|name| vec![Command::SelectProfile(name)]),
+        "/monitor" => required("name").map(|name| vec![Command::SelectMonitor(name)]),
+        "/window" => required("role").and_then(|role| {
+            let role = window_role(role)?;
+            Ok(vec![Command::SelectWindow(role, required("title")?)])
+        }),
+        "/window/pick" => required("role").and_then(window_role).map(|role| vec![Command::PickWindow(role)]),
+        "/workload" => required("kind").and_then(|kind| {
+            let kind: WorkloadKind = serde_json::from_value(Value::String(kind.clone()))
+                .map_err(|_| format!("unknown workload {:?}", kind))?;
+            Ok(vec![Command::SetWorkload(kind)])
+        }),
+        "/mode" => required("mode").and_then(|mode| {
+            let mode: MeasureMode = serde_json::from_value(Value::String(mode.clone()))
+                .map_err(|_| format!("unknown mode {:?}", mode))?;
+            Ok(vec![Command::SetMode(mode)])
+        }),
+        "/report" => Ok(vec![Command::WriteReport]),
+        _ => return None,
+    })
+}
+
+fn window_role(role: String) -> Result<WindowRole, String> {
+    serde_json::from_value(Value::String(role.clone())).map_err(|_| format!("unknown window role {:?}", role))
+}
+
+fn monitors() -> Vec<MonitorInfo> {
+    get_monitors().iter().map(|m| MonitorInfo {
+        name: m.name().to_string(),
+        x: m.x(),
+        y: m.y(),
+        width: m.width(),
+        height: m.height(),
+        frequency: m.frequency(),
+        primary: m.is_primary(),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::config::Config;
+
+    // Starts an app serving the control API on a port chosen by the system.
+    fn start(name: &str) -> (Arc<App>, String) {
+        let dir = std::env::temp_dir().join(format!("latency_tester_control_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut config = Config::default();
+        config.output.results_dir = dir.join("results").display().to_string();
+        config.output.debug_image = false;
+        let app = App::start(config, dir.join("config.toml"));
+        let address = serve(Arc::clone(&app), "127.0.0.1:0").unwrap().to_string();
+        (app, address)
+    }
+
+    // Sends a request and returns the status code and the JSON body of the answer.
+    fn request(address: &str, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n{}", method, path, address,
+               body.len(), body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn status_follows_commands() {
+        let (app, address) = start("status");
+        let (status, body) = request(&address, "GET", "/status", "");
+        assert_eq!(status, 200);
+        assert_eq!(body["role"], Value::Null);
+        assert_eq!(body["measurements"], 0);
+
+        let (status, body) = request(&address, "POST", "/listen", r#"{"address": "127.0.0.1:0"}"#);
+        assert_eq!(status, 200);
+        assert_eq!(body["role"], "server");
+        assert_eq!(body["address"], "127.0.0.1:0");
+        assert_eq!(app.config().network.address, "127.0.0.1:0");
+        let listening = app.listening_address().unwrap();
+        assert!(listening.ip().is_loopback() && listening.port() != 0, "{}", listening);
+        assert_eq!(request(&address, "GET", "/status", "").1["listening"], listening.to_string());
+
+        let (status, body) = request(&address, "POST", "/workload", r#"{"kind": "noise"}"#);
+        assert_eq!(status, 200, "{}", body);
+        assert_eq!(app.config().workload.kind, WorkloadKind::Noise);
+
+        let (status, body) = request(&address, "POST", "/mode", r#"{"mode": "input"}"#);
+        assert_eq!((status, body["mode"].as_str()), (200, Some("input")));
+
+        let (status, body) = request(&address, "POST", "/window", r#"{"role": "remote", "title": "vdi01"}"#);
+        assert_eq!(status, 200, "{}", body);
+        assert_eq!(app.config().capture.remote_window, "vdi01");
+    }
+
+    #[test]
+    fn results_and_stats_start_empty() {
+        let (_app, address) = start("results");
+        assert_eq!(request(&address, "GET", "/results", ""), (200, json!([])));
+        let (status, stats) = request(&address, "GET", "/stats", "");
+        assert_eq!(status, 200);
+        assert_eq!(stats["delay_ms"], Value::Null);
+        assert_eq!(stats["failures"], 0);
+        let (status, monitors) = request(&address, "GET", "/monitors", "");
+        assert_eq!(status, 200);
+        assert!(monitors.is_array());
+    }
+
+    #[test]
+    fn invalid_requests_are_refused() {
+        let (_app, address) = start("invalid");
+        assert_eq!(request(&address, "GET", "/nothing", "").0, 404);
+        assert_eq!(request(&address, "POST", "/nothing", "").0, 404);
+        assert_eq!(request(&address, "POST", "/scenario", "").0, 400);
+        assert_eq!(request(&address, "POST", "/workload", r#"{"kind": "fire"}"#).0, 400);
+        assert_eq!(request(&address, "POST", "/window", r#"{"role": "both", "title": ""}"#).0, 400);
+        assert_eq!(request(&address, "POST", "/window/pick", "").0, 400);
+        assert_eq!(request(&address, "POST", "/profile", "{").0, 400);
+        let (status, body) = request(&address, "POST", "/cancel", "");
+        assert_eq!((status, body["error"].as_str()), (409, Some("no scenario is running")));
+        assert_eq!(request(&address, "POST", "/measure", "").0, 409);
+    }
+}
@@ -0,0 +1,255 @@
+// Template based reader for the timer label.
+// The timer is rendered by this program in a known font, so instead of running a full OCR engine
+// the glyphs are learned once from a rendered reference string and the screenshot crop is matched
+// against them column segment by column segment.
+use std::sync::OnceLock;
+use image::{imageops, GrayImage, Luma, RgbImage};
+
+pub const REFERENCE_TEXT: &str = "0123456789:.";
+// Relative ink difference above which a segment is not considered a match.
+const MAX_MISMATCH: f32 = 0.6;
+// Ink level a pixel needs to count as part of a glyph when separating glyphs.
+const INK_THRESHOLD: u8 = 100;
+// Horizontal oversampling, glyphs are compared at quarter pixel offsets.
+const SUBPIXELS: u32 = 4;
+
+static GLYPHS: OnceLock<GlyphSet> = OnceLock::new();
+
+// 5x7 bitmap font for the timer characters, one string per row. The tests draw timers with it and the
+// annotated screenshots show the confidences with it.
+const FONT: [(char, [&str; 7]); 12] = [
+    ('0', [".###.", "#...#", "#..##", "#.#.#", "##..#", "#...#", ".###."]),
+    ('1', ["..#..", ".##..", "..#..", "..#..", "..#..", "..#..", ".###."]),
+    ('2', [".###.", "#...#", "....#", "...#.", "..#..", ".#...", "#####"]),
+    ('3', ["#####", "...#.", "..#..", "...#.", "....#", "#...#", ".###."]),
+    ('4', ["...#.", "..##.", ".#.#.", "#..#.", "#####", "...#.", "...#."]),
+    ('5', ["#####", "#....", "####.", "....#", "....#", "#...#", ".###."]),
+    ('6', ["..##.", ".#...", "#....", "####.", "#...#", "#...#", ".###."]),
+    ('7', ["#####", "....#", "...#.", "..#..", ".#...", ".#...", ".#..."]),
+    ('8', [".###.", "#...#", "#...#", ".###.", "#...#", "#...#", ".###."]),
+    ('9', [".###.", "#...#", "#...#", ".####", "....#", "...#.", ".##.."]),
+    (':', [".", ".", "#", ".", "#", ".", "."]),
+    ('.', [".", ".", ".", ".", ".", ".", "#"]),
+];
+// font pixels between two characters
+pub const LETTER_SPACING: u32 = 1;
+
+// Rows of `c` in the bitmap font, None if it has no glyph.
+pub fn font_glyph(c: char) -> Option<[&'static str; 7]> {
+    FONT.iter().find(|(f, _)| *f == c).map(|(_, rows)| *rows)
+}
+
+struct Glyph {
+    character: char,
+    bitmap: GrayImage,
+}
+
+pub struct GlyphSet {
+    glyphs: Vec<Glyph>,
+    line_height: u32,
+    digit_width: u32,
+}
+
+pub fn set_glyphs(glyphs: GlyphSet) {
+    let _ = GLYPHS.set(glyphs);
+}
+
+pub fn glyphs() -> Option<&'static GlyphSet> {
+    GLYPHS.get()
+}
+
+impl GlyphSet {
+    // Learns the glyphs from an image showing exactly `text` on a single line.
+    pub fn learn(reference: &RgbImage, text: &str) -> Result<GlyphSet, String> {
+        let ink = binarize(reference);
+        let (top, bottom) = line_bounds(&ink).ok_or("reference image is empty")?;
+        let segments = column_segments(&ink, top, bottom);
+        let characters: Vec<char> = text.chars().collect();
+        if segments.len() != characters.len() {
+            return Err(format!("found {} glyphs in reference, expected {}",
+                               segments.len(), characters.len()));
+        }
+        let glyphs: Vec<Glyph> = characters.iter().zip(segments.iter())
+            .map(|(&character, &(x0, x1))| Glyph {
+                character,
+                bitmap: oversample(&imageops::crop_imm(&ink, x0, top, x1 - x0, bottom - top).to_image(),
+                                   x1 - x0, bottom - top),
+            })
+            .collect();
+        let digit_width = glyphs.iter()
+            .filter(|g| g.character.is_ascii_digit())
+            .map(|g| g.bitmap.width() / SUBPIXELS)
+            .max()
+            .unwrap_or(1);
+        Ok(GlyphSet {
+            glyphs,
+            line_height: bottom - top,
+            digit_width,
+        })
+    }
+
+    // Reads the text in `image`, unknown segments are returned as '?'. The confidence in [0, 1] is the mean of
+    // how well the segments match their glyphs.
+    pub fn read(&self, image: &RgbImage) -> Result<(String, f32), String> {
+        let ink = binarize(image);
+        let (top, bottom) = line_bounds(&ink).ok_or("no text found")?;
+        let scale = (bottom - top) as f32 / self.line_height as f32;
+        let max_width = (self.digit_width as f32 * scale * 1.5).ceil() as u32;
+        let mut text = String::new();
+        let mut confidence = 0.0;
+        for (x0, x1) in column_segments(&ink, top, bottom) {
+            // touching glyphs (e.g. from compression artifacts) are split into digit sized parts
+            let parts = if x1 - x0 > max_width {
+                ((x1 - x0) as f32 / (self.digit_width as f32 * scale)).round().max(1.0) as u32
+            } else {
+                1
+            };
+            let part_width = (x1 - x0) / parts;
+            for p in 0..parts {
+                let start = x0 + p * part_width;
+                let segment = imageops::crop_imm(&ink, start, top, part_width.max(1), bottom - top).to_image();
+                let (character, mismatch) = self.classify(&segment, scale);
+                text.push(character);
+                confidence += 1.0 - mismatch / MAX_MISMATCH;
+            }
+        }
+        let characters = text.chars().count();
+        Ok((text, if characters == 0 { 0.0 } else { confidence / characters as f32 }))
+    }
+
+    // Best matching glyph and its mismatch, unknown segments are '?' with `MAX_MISMATCH`.
+    fn classify(&self, segment: &GrayImage, scale: f32) -> (char, f32) {
+        let width = ((segment.width() as f32 / scale).round() as u32).max(1);
+        let height = ((segment.height() as f32 / scale).round() as u32).max(1);
+        let resized = oversample(segment, width, height);
+        let shift = SUBPIXELS as i32;
+        let mut best = ('?', MAX_MISMATCH);
+        for glyph in &self.glyphs {
+            let score = (-shift..=shift)
+                .map(|s| mismatch(&resized, &glyph.bitmap, s))
+                .fold(f32::MAX, f32::min);
+            if score < best.1 {
+                best = (glyph.character, score);
+            }
+        }
+        best
+    }
+}
+
+// Scales the ink map to `width` x `height` pixels with `SUBPIXELS` columns per pixel.
+fn oversample(ink: &GrayImage, width: u32, height: u32) -> GrayImage {
+    imageops::resize(ink, width * SUBPIXELS, height, imageops::FilterType::Triangle)
+}
+
+// Mean difference of two ink masks, centered on each other and moved by `shift` columns.
+fn mismatch(a: &GrayImage, b: &GrayImage, shift: i32) -> f32 {
+    let width = a.width().max(b.width()) as i32 + 2 * SUBPIXELS as i32;
+    let offset_a = (width - a.width() as i32) / 2 + shift;
+    let offset_b = (width - b.width() as i32) / 2;
+    let ink = |image: &GrayImage, offset: i32, x: i32, y: u32| -> f32 {
+        let x = x - offset;
+        if x < 0 || x >= image.width() as i32 { 0.0 } else { image.get_pixel(x as u32, y)[0] as f32 / 255.0 }
+    };
+    let mut difference = 0.0;
+    let mut total = 0.0;
+    for y in 0..a.height().min(b.height()) {
+        for x in 0..width {
+            let (ia, ib) = (ink(a, offset_a, x, y), ink(b, offset_b, x, y));
+            difference += (ia - ib).abs();
+            total += ia.max(ib);
+        }
+    }
+    if total == 0.0 { 1.0 } else { difference / total }
+}
+
+// Converts the image into an ink map where 255 is text and 0 is background,
+// works for dark and light themes.
+fn binarize(image: &RgbImage) -> GrayImage {
+    let gray = imageops::grayscale(image);
+    let threshold = otsu_threshold(&gray);
+    let (mut dark, mut dark_sum, mut light_sum) = (0u64, 0u64, 0u64);
+    for p in gray.pixels() {
+        if p[0] <= threshold {
+            dark += 1;
+            dark_sum += p[0] as u64;
+        } else {
+            light_sum += p[0] as u64;
+        }
+    }
+    let light = gray.pixels().len() as u64 - dark;
+    if dark == 0 || light == 0 {
+        return GrayImage::new(gray.width(), gray.height());
+    }
+    let dark_level = (dark_sum / dark) as f32;
+    let light_level = (light_sum / light) as f32;
+    // the text covers less area than the background
+    let (text_level, background_level) = if dark < light {
+        (dark_level, light_level)
+    } else {
+        (light_level, dark_level)
+    };
+    GrayImage::from_fn(gray.width(), gray.height(), |x, y| {
+        let value = gray.get_pixel(x, y)[0] as f32;
+        let ink = (value - background_level) / (text_level - background_level);
+        Luma([(ink.clamp(0.0, 1.0) * 255.0) as u8])
+    })
+}
+
+fn otsu_threshold(image: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for p in image.pixels() {
+        histogram[p[0] as usize] += 1;
+    }
+    let total = image.pixels().len() as f64;
+    let sum: f64 = histogram.iter().enumerate().map(|(i, &c)| i as f64 * c as f64).sum();
+    let (mut sum_b, mut weight_b) = (0.0, 0.0);
+    let (mut best, mut best_variance) = (0u8, 0.0);
+    for (i, &count) in histogram.iter().enumerate() {
+        weight_b += count as f64;
+        if weight_b == 0.0 {
+            continue;
+        }
+        let weight_f = total - weight_b;
+        if weight_f == 0.0 {
+            break;
+        }
+        sum_b += i as f64 * count as f64;
+        let mean_b = sum_b / weight_b;
+        let mean_f = (sum - sum_b) / weight_f;
+        let variance = weight_b * weight_f * (mean_b - mean_f) * (mean_b - mean_f);
+        if variance > best_variance {
+            best_variance = variance;
+            best = i as u8;
+        }
+    }
+    best
+}
+
+// First and one past the last row containing ink.
+fn line_bounds(ink: &GrayImage) -> Option<(u32, u32)> {
+    let rows: Vec<u32> = (0..ink.height())
+        .filter(|&y| (0..ink.width()).any(|x| ink.get_pixel(x, y)[0] > INK_THRESHOLD))
+        .collect();
+    Some((*rows.first()?, *rows.last()? + 1))
+}
+
+// Ranges of consecutive columns containing ink between `top` and `bottom`.
+fn column_segments(ink: &GrayImage, top: u32, bottom: u32) -> Vec<(u32, u32)> {
+    let mut segments = vec![];
+    let mut start = None;
+    for x in 0..ink.width() {
+        let has_ink = (top..bottom).any(|y| ink.get_pixel(x, y)[0] > INK_THRESHOLD);
+        match (has_ink, start) {
+            (true, None) => start = Some(x),
+            (false, Some(s)) => {
+                segments.push((s, x));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        segments.push((s, ink.width()));
+    }
+    segments
+}
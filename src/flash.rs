@@ -0,0 +1,137 @@
+// Color flash measurements: the local instance asks the remote to fill its color patch with another color and
+// reads only the pixels of the patch in a tight loop until the new color shows up. This does not depend on text
+// surviving the compression of the remote display and resolves much finer than reading the timers from one
+// screenshot every few seconds.
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use async_channel::Sender;
+use image::{DynamicImage, Rgba, RgbaImage};
+use log::{debug, info, warn};
+use xcb::x;
+use crate::config::{Config, Region};
+use crate::input::{difference, mean_color, wait_for_change};
+use crate::network::messages::NetworkMessage;
+use crate::network::networkmanager::NetworkManager;
+use crate::screenshot::{get_monitors, locate_remote_marker, CaptureBox};
+use crate::UpdateUI;
+
+// Reads areas of the screen straight from the X server, much faster than capturing the whole monitor.
+struct RegionReader {
+    connection: xcb::Connection,
+    root: x::Window,
+    // byte order of the pixels, BGRX if the least significant byte comes first
+    lsb_first: bool,
+}
+
+impl RegionReader {
+    fn connect() -> Result<RegionReader, String> {
+        let (connection, screen) = xcb::Connection::connect(None)
+            .map_err(|e| format!("can not connect to the X server: {}", e))?;
+        let setup = connection.get_setup();
+        let root = setup.roots().nth(screen as usize).ok_or("X screen not found")?.root();
+        let lsb_first = setup.image_byte_order() == x::ImageOrder::LsbFirst;
+        Ok(RegionReader { connection, root, lsb_first })
+    }
+
+    // Pixels of `region` in root window coordinates.
+    fn read(&self, region: &Region) -> Result<RgbaImage, String> {
+        let cookie = self.connection.send_request(&x::GetImage {
+            format: x::ImageFormat::ZPixmap,
+            drawable: x::Drawable::Window(self.root),
+            x: region.x as i16,
+            y: region.y as i16,
+            width: region.width as u16,
+            height: region.height as u16,
+            plane_mask: u32::MAX,
+        });
+        let reply = self.connection.wait_for_reply(cookie).map_err(|e| format!("error reading screen: {}", e))?;
+        pixels(reply.data(), region.width, region.height, self.lsb_first)
+    }
+}
+
+// Measures the time from asking the remote for the next color until its patch in `region` around the located
+// marker of the remote shows the color.
+pub fn measure_flash(sender: Arc<Sender<UpdateUI>>, network: Arc<NetworkManager>, region: CaptureBox,
+                     config: Arc<Config>) {
+    thread::spawn(move || {
+        let result = measure(&network, &region, &config);
+        match &result {
+            Ok(delay) => info!(delay:? = delay; "flash delay measured"),
+            Err(e) => warn!(error = e.as_str(); "flash measurement failed"),
+        }
+        let _ = sender.send_blocking(UpdateUI::FlashMeasured(result.ok()));
+    });
+}
+
+fn measure(network: &NetworkManager, region: &CaptureBox, config: &Config) -> Result<Duration, String> {
+    let monitors = get_monitors();
+    let monitor = monitors.iter().find(|m| m.name() == config.capture.monitor).or(monitors.first())
+        .ok_or("no monitor to capture")?;
+    // the patch is located once, the loop below only reads its pixels
+    let screenshot = monitor.capture_image().map_err(|e| format!("error on image capture: {}", e))?;
+    let (x, y, _, _, confidence) = locate_remote_marker(&DynamicImage::ImageRgba8(screenshot), &config.detection)
+        .ok_or("could not locate the remote window")?;
+    debug!(x = x, y = y, confidence = confidence; "remote marker found");
+    let patch = region.at((x, y));
+    if patch.x < 0 || patch.y < 0 || patch.x + patch.width as i32 > monitor.width() as i32
+        || patch.y + patch.height as i32 > monitor.height() as i32 {
+        return Err("flash region is outside of the monitor".to_string());
+    }
+    let on_screen = Region { x: monitor.x() + patch.x, y: monitor.y() + patch.y, ..patch };
+    let whole = Region { x: 0, y: 0, ..patch };
+
+    let reader = RegionReader::connect()?;
+    let reference = mean_color(&reader.read(&on_screen)?, &whole).ok_or("flash region is empty")?;
+    let color = next_color(&config.flash.colors, reference);
+    network.send(NetworkMessage::Flash(color));
+    let sent = Instant::now();
+    let deadline = sent + Duration::from_millis(config.flash.timeout_ms);
+    let changed = wait_for_change(|| reader.read(&on_screen), &whole, reference, config.flash.threshold, deadline)?;
+    Ok(changed.saturating_duration_since(sent))
+}
+
+// Color after the one of `colors` closest to the current color of the patch.
+fn next_color(colors: &[[u8; 3]], current: [f64; 3]) -> [u8; 3] {
+    let distance = |c: &[u8; 3]| difference(c.map(f64::from), current);
+    let closest = (0..colors.len()).min_by(|a, b| distance(&colors[*a]).total_cmp(&distance(&colors[*b])))
+        .unwrap_or(0);
+    colors[(closest + 1) % colors.len()]
+}
+
+// Image of the 32 bit ZPixmap data of a `width` x `height` area.
+fn pixels(data: &[u8], width: u32, height: u32, lsb_first: bool) -> Result<RgbaImage, String> {
+    if data.len() != (width * height * 4) as usize {
+        return Err(format!("unsupported pixel format, {} bytes for {}x{} pixels", data.len(), width, height));
+    }
+    Ok(RgbaImage::from_fn(width, height, |x, y| {
+        let i = ((y * width + x) * 4) as usize;
+        let p = &data[i..i + 4];
+        if lsb_first { Rgba([p[2], p[1], p[0], 255]) } else { Rgba([p[1], p[2], p[3], 255]) }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_are_cycled() {
+        let colors = [[0, 0, 0], [255, 255, 255], [255, 0, 0]];
+        assert_eq!(next_color(&colors, [10.0, 5.0, 0.0]), [255, 255, 255]);
+        assert_eq!(next_color(&colors, [240.0, 250.0, 250.0]), [255, 0, 0]);
+        assert_eq!(next_color(&colors, [200.0, 30.0, 20.0]), [0, 0, 0]);
+    }
+
+    #[test]
+    fn pixels_in_both_byte_orders() {
+        // one red and one blue pixel
+        let bgrx = [0, 0, 255, 0, 255, 0, 0, 0];
+        let image = pixels(&bgrx, 2, 1, true).unwrap();
+        assert_eq!(image.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(1, 0), &Rgba([0, 0, 255, 255]));
+        let xrgb = [0, 255, 0, 0, 0, 0, 0, 255];
+        assert_eq!(pixels(&xrgb, 2, 1, false).unwrap(), image);
+        assert!(pixels(&bgrx, 2, 2, true).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+// Input latency ("click to photon"): the local instance injects a key press or a click with XTest, the
+// remote flips a color patch once the input reaches its window and the local instance captures the
+// screen until it sees the patch change.
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use async_channel::Sender;
+use image::RgbaImage;
+use log::{debug, info, warn};
+use xcb::{x, xtest, BaseEvent};
+use crate::config::{Config, InputConfig, InputMethod, Region};
+use crate::screenshot::get_monitors;
+use crate::UpdateUI;
+
+// Connection to the X server of $DISPLAY, which works under Xvfb as well.
+struct Injector {
+    connection: xcb::Connection,
+    root: x::Window,
+}
+
+impl Injector {
+    fn connect() -> Result<Injector, String> {
+        let (connection, screen) = xcb::Connection::connect_with_extensions(None, &[xcb::Extension::Test], &[])
+            .map_err(|e| format!("can not connect to the X server: {}", e))?;
+        let root = connection.get_setup().roots().nth(screen as usize).ok_or("X screen not found")?.root();
+        Ok(Injector { connection, root })
+    }
+
+    // Injects the configured input and returns the instant the X server handled the press. Clicks go to
+    // `click` in root window coordinates, key presses to the focused window.
+    fn inject(&self, config: &InputConfig, click: (i32, i32)) -> Result<Instant, String> {
+        let (press, release, detail) = match config.method {
+            InputMethod::Key => (x::KeyPressEvent::NUMBER, x::KeyReleaseEvent::NUMBER, config.keycode),
+            InputMethod::Click => {
+                self.fake(x::MotionNotifyEvent::NUMBER, 0, click)?;
+                (x::ButtonPressEvent::NUMBER, x::ButtonReleaseEvent::NUMBER, 1)
+            }
+        };
+        self.fake(press, detail, click)?;
+        let pressed = Instant::now();
+        self.fake(release, detail, click)?;
+        Ok(pressed)
+    }
+
+    // Sends a fake event and waits until the X server handled it.
+    fn fake(&self, event: u32, detail: u8, (x, y): (i32, i32)) -> Result<(), String> {
+        self.connection.send_and_check_request(&xtest::FakeInput {
+            r#type: event as u8,
+            detail,
+            time: x::CURRENT_TIME,
+            root: self.root,
+            root_x: x as i16,
+            root_y: y as i16,
+            deviceid: 0,
+        }).map_err(|e| format!("error injecting input: {}", e))
+    }
+}
+
+// Instants of a measured input on the local clock.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputTiming {
+    // the X server handled the injected press
+    pub pressed: Instant,
+    // the frame showing the changed patch was taken
+    pub changed: Instant,
+}
+
+impl InputTiming {
+    pub fn latency(&self) -> Duration {
+        self.changed.saturating_duration_since(self.pressed)
+    }
+}
+
+// Measures the time from an injected input to the change of the color patch of the remote in `patch` of
+// the monitor `capture.monitor`. The remote has to be armed with `NetworkMessage::ArmInput` before.
+pub fn measure_input(sender: Arc<Sender<UpdateUI>>, patch: Region, config: Arc<Config>) {
+    thread::spawn(move || {
+        // the remote has to be armed before the input reaches it
+        thread::sleep(Duration::from_millis(config.input.arm_delay_ms));
+        let result = measure(patch, &config);
+        match &result {
+            Ok(timing) => info!(latency:? = timing.latency(); "input latency measured"),
+            Err(e) => warn!(error = e.as_str(); "input latency measurement failed"),
+        }
+        let _ = sender.send_blocking(UpdateUI::InputMeasured(result.ok()));
+    });
+}
+
+fn measure(patch: Region, config: &Config) -> Result<InputTiming, String> {
+    let monitors = get_monitors();
+    let monitor = monitors.iter().find(|m| m.name() == config.capture.monitor).or(monitors.first())
+        .ok_or("no monitor to capture")?;
+    let injector = Injector::connect()?;
+    let capture = || monitor.capture_image().map_err(|e| format!("error on image capture: {}", e));
+    let reference = mean_color(&capture()?, &patch).ok_or("input.patch is outside of the monitor")?;
+    let (x, y) = config.input.click
+        .unwrap_or((patch.x + patch.width as i32 / 2, patch.y + patch.height as i32 / 2));
+    let pressed = injector.inject(&config.input, (monitor.x() + x, monitor.y() + y))?;
+    let deadline = pressed + Duration::from_millis(config.input.timeout_ms);
+    let changed = wait_for_change(capture, &patch, reference, config.input.threshold, deadline)?;
+    Ok(InputTiming { pressed, changed })
+}
+
+// Captures until the mean color of `region` differs from `reference` by more than `threshold` and returns
+// the instant the changed frame was taken. The frame is taken during the capture, its middle is used.
+pub fn wait_for_change(mut capture: impl FnMut() -> Result<RgbaImage, String>, region: &Region, reference: [f64; 3],
+                   threshold: f64, deadline: Instant) -> Result<Instant, String> {
+    let mut frames = 0;
+    while Instant::now() < deadline {
+        let start = Instant::now();
+        let image = capture()?;
+        let taken = start + start.elapsed() / 2;
+        frames += 1;
+        let color = mean_color(&image, region).ok_or("input.patch is outside of the monitor")?;
+        if difference(color, reference) > threshold {
+            debug!(frames = frames; "patch changed");
+            return Ok(taken);
+        }
+    }
+    Err(format!("patch did not change within {} frames", frames))
+}
+
+// Mean color of `region`, None if it is not completely inside the image.
+pub fn mean_color(image: &RgbaImage, region: &Region) -> Option<[f64; 3]> {
+    let (x, y) = (u32::try_from(region.x).ok()?, u32::try_from(region.y).ok()?);
+    if region.width == 0 || region.height == 0
+        || x + region.width > image.width() || y + region.height > image.height() {
+        return None;
+    }
+    let mut sum = [0.0; 3];
+    for py in y..y + region.height {
+        for px in x..x + region.width {
+            let pixel = image.get_pixel(px, py);
+            for (channel, value) in sum.iter_mut().zip(pixel.0) {
+                *channel += value as f64;
+            }
+        }
+    }
+    let pixels = (region.width * region.height) as f64;
+    Some(sum.map(|channel| channel / pixels))
+}
+
+// Mean absolute difference of the color channels.
+pub fn difference(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum::<f64>() / 3.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const PATCH: Region = Region { x: 20, y: 10, width: 30, height: 20 };
+
+    // Frame with the patch in `color` on a gray background.
+    fn frame(color: [u8; 3]) -> RgbaImage {
+        RgbaImage::from_fn(100, 60, |x, y| {
+            let inside = (20..50).contains(&x) && (10..30).contains(&y);
+            if inside { Rgba([color[0], color[1], color[2], 255]) } else { Rgba([128, 128, 128, 255]) }
+        })
+    }
+
+    #[test]
+    fn mean_color_of_region() {
+        assert_eq!(mean_color(&frame([0, 255, 40]), &PATCH), Some([0.0, 255.0, 40.0]));
+        let half = Region { x: 10, ..PATCH };
+        // a third of the region is gray background
+        assert_eq!(mean_color(&frame([255, 255, 255]), &half), Some([2.0 / 3.0 * 255.0 + 128.0 / 3.0; 3]));
+        assert_eq!(mean_color(&frame([0, 0, 0]), &Region { x: 80, ..PATCH }), None);
+        assert_eq!(mean_color(&frame([0, 0, 0]), &Region { x: -1, ..PATCH }), None);
+    }
+
+    #[test]
+    fn change_is_found_in_first_changed_frame() {
+        let frames = [[0, 0, 0], [10, 10, 10], [0, 0, 0], [255, 255, 255]];
+        let mut captured = 0;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let start = Instant::now();
+        let changed = wait_for_change(|| {
+            captured += 1;
+            Ok(frame(frames[(captured - 1).min(frames.len() - 1)]))
+        }, &PATCH, [0.0; 3], 60.0, deadline).unwrap();
+        assert_eq!(captured, 4);
+        assert!(changed >= start && changed <= Instant::now());
+    }
+
+    #[test]
+    fn unchanged_patch_times_out() {
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let result = wait_for_change(|| Ok(frame([0, 0, 0])), &PATCH, [0.0; 3], 60.0, deadline);
+        assert!(result.unwrap_err().starts_with("patch did not change"));
+    }
+
+    #[test]
+    fn capture_errors_end_the_measurement() {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let result = wait_for_change(|| Err("no screen".to_string()), &PATCH, [0.0; 3], 60.0, deadline);
+        assert_eq!(result, Err("no screen".to_string()));
+    }
+}
@@ -0,0 +1,133 @@
+// Logger writing leveled records with their key-value pairs to a size rotated log file,
+// stderr and the log panel of the window.
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use async_channel::Sender;
+use chrono::Local;
+use log::{kv, LevelFilter, Log, Metadata, Record};
+use crate::config::OutputConfig;
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+struct Logger {
+    file: Mutex<Option<RotatingFile>>,
+    panel: Mutex<Option<Sender<String>>>,
+}
+
+struct RotatingFile {
+    path: String,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: usize,
+}
+
+pub fn init(config: &OutputConfig) {
+    let level = LevelFilter::from_str(&config.log_level).unwrap_or(LevelFilter::Info);
+    let file = if config.log_file.is_empty() {
+        None
+    } else {
+        match RotatingFile::open(&config.log_file, config.log_max_size, config.log_files) {
+            Ok(f) => Some(f),
+            Err(e) => {
+                eprintln!("error opening log file {:?}: {}", config.log_file, e);
+                None
+            }
+        }
+    };
+    let logger = LOGGER.get_or_init(|| Logger {
+        file: Mutex::new(file),
+        panel: Mutex::new(None),
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+// Forwards formatted records to the log panel of the window.
+pub fn set_panel(sender: Sender<String>) {
+    if let Some(logger) = LOGGER.get() {
+        *logger.panel.lock().unwrap() = Some(sender);
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut line = format!("{} {:<5} {}: {}", Local::now().format("%Y-%m-%dT%H:%M:%S%.3f"),
+                               record.level(), record.target(), record.args());
+        let _ = record.key_values().visit(&mut KeyValueWriter(&mut line));
+
+        eprintln!("{}", line);
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            if let Err(e) = file.write_line(&line) {
+                eprintln!("error writing log file: {}", e);
+            }
+        }
+        if let Some(panel) = self.panel.lock().unwrap().as_ref() {
+            let _ = panel.try_send(line);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+struct KeyValueWriter<'a>(&'a mut String);
+
+impl<'kvs> kv::VisitSource<'kvs> for KeyValueWriter<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push_str(&format!(" {}={}", key, value));
+        Ok(())
+    }
+}
+
+impl RotatingFile {
+    fn open(path: &str, max_size: u64, keep: usize) -> std::io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_string(),
+            file,
+            size,
+            max_size,
+            keep,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.size + line.len() as u64 + 1 > self.max_size && self.size > 0 {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    // latency_tester.log -> latency_tester.log.1 -> latency_tester.log.2 ...
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            let _ = fs::remove_file(format!("{}.{}", self.path, self.keep));
+            for i in (1..self.keep).rev() {
+                let _ = fs::rename(format!("{}.{}", self.path, i), format!("{}.{}", self.path, i + 1));
+            }
+            fs::rename(&self.path, format!("{}.1", self.path))?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
@@ -1,54 +1,157 @@
 #![windows_subsystem = "windows"]
 mod screenshot;
+mod artifacts;
 mod config;
+mod logging;
+mod digits;
+mod cli;
+mod results;
+mod stats;
+mod scenario;
+mod workload;
+mod metrics;
+mod sinks;
+mod report;
+mod compare;
+mod app;
+mod control;
+mod input;
+mod flash;
+mod calibration;
+#[cfg(test)]
+mod testing;
 
 mod network {
     pub mod networkmanager;
     pub mod messages;
+    pub mod impairment;
+    pub mod loopback;
+    pub mod clock;
 }
 
-use std::ops::Deref;
-use std::thread::sleep;
 use std::time::{Duration, Instant};
 use gtk::{glib, Label, ListBox, prelude::*};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use std::thread;
-use async_channel::Sender;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::cell::Cell;
+use std::rc::Rc;
 use gtk::gdk_pixbuf::{PixbufLoader};
-use gtk::{Align, Image, PolicyType, ScrolledWindow};
-use message_io::network::{ToRemoteAddr, Transport};
-use crate::network::messages::NetworkMessage;
-use crate::screenshot::{capture_screen, get_monitors, CaptureBox};
+use gtk::{Align, DrawingArea, Image, PolicyType, ScrolledWindow, TextView};
+use crate::app::{App, Command, Event, Role};
+use crate::screenshot::{get_monitors, CaptureBox, CaptureFailure, DelayReading, StageTimings, WindowRole};
+use crate::input::InputTiming;
 use chrono::Local;
-use crate::config::{read_config, write_config};
+use crate::config::{default_config_path, load_config, Config, ALL_MONITORS, MeasureMode, WorkloadConfig, WorkloadKind};
+use crate::scenario::PhaseResult;
+use crate::workload::LoadWindow;
+use crate::cli::Cli;
+use clap::Parser;
+use log::error;
 
 
 enum UpdateUI {
     SetTimer(String),
     StartTimer(Instant),
-    DelayMeasured(Option<Duration>),
+    // delay of the remote timer with the quality of the readings, None if no attempt succeeded
+    DelayMeasured(Option<DelayReading>),
     ResetTimer,
     Ping(Duration),
+    PingLost,
+    // result and stage timings of a single capture attempt
+    CaptureAttempt(Option<CaptureFailure>, StageTimings),
+    // a running scenario asks for the next measurement
+    Measure,
+    // scenario phase that starts now, empty once the scenario is over
+    Phase(String),
+    ScenarioFinished(String, Vec<PhaseResult>),
+    // load to render, requested by the local instance
+    Workload(WorkloadConfig),
+    // the remote flips its color patch on the next input
+    ArmInput,
+    // instants of the injected input and of the change of the color patch, None if no change was seen
+    InputMeasured(Option<InputTiming>),
+    // the input reached the window of the remote at this remote clock (`network::clock`)
+    InputArrived(u64),
+    // the remote fills its color patch with this color
+    Flash([u8; 3]),
+    // time from the flash command to the new color on the screen, None if the color did not change
+    FlashMeasured(Option<Duration>),
+    // result of the capture latency calibration, None if the timer could not be read from any screenshot
+    Calibrated(Option<calibration::CaptureCalibration>),
+    // refresh rate of the display showing the window of the remote instance in Hz
+    RemoteRefreshRate(f64),
+    // id and title of the window clicked to lock the capture to, None if no window was clicked
+    WindowPicked(WindowRole, Option<(u32, String)>),
 }
 
 
 pub static IMAGE_BYTES_SERVER: &'static [u8] = include_bytes!("resources/server.jpg");
 pub static IMAGE_BYTES_CLIENT: &'static [u8] = include_bytes!("resources/desktop.jpg");
 const APP_ID: &str = "de.uni-freiburg.rz.latency_test";
-const CONFIG_PATH: &str = "latency_reader.toml";
+
+// Attaches to the console of the parent process, e.g. the command prompt the program was started from, the
+// windows subsystem has none of its own.
+#[cfg(windows)]
+fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    // without a parent console the output is lost as before
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
 
 fn main() -> glib::ExitCode {
+    let cli = Cli::parse();
+    // the commands print their results
+    #[cfg(windows)]
+    if cli.command.is_some() {
+        attach_console();
+    }
+    let config_path = cli.config.clone().unwrap_or_else(default_config_path);
+    let config = match load_config(&config_path, &cli.config_overrides()) {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("error reading config {:?}: {}", config_path, e);
+            return glib::ExitCode::FAILURE;
+        }
+    };
+    logging::init(&config.output);
+    if let Some(cli::Command::Compare { baseline, candidate }) = &cli.command {
+        return match compare::compare_files(baseline, candidate, &config.compare) {
+            Ok(false) => glib::ExitCode::SUCCESS,
+            Ok(true) => glib::ExitCode::FAILURE,
+            Err(e) => {
+                eprintln!("error comparing results: {}", e);
+                glib::ExitCode::from(2)
+            }
+        };
+    }
+
+    if let Some(cli::Command::Loopback { seconds }) = &cli.command {
+        return match network::loopback::run_loopback(&config, Duration::from_secs(*seconds)) {
+            Ok(()) => glib::ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("error running loopback: {}", e);
+                glib::ExitCode::FAILURE
+            }
+        };
+    }
+
     let application = gtk::Application::builder()
         .application_id(APP_ID)
         .build();
-    application.connect_activate(build_ui);
-    application.run()
+    application.connect_activate(move |application| {
+        build_ui(application, Arc::clone(&config), config_path.clone());
+    });
+    // the arguments were already handled by clap
+    application.run_with_args::<&str>(&[])
 }
 
-fn build_ui(application: &gtk::Application) {
-    let config = read_config(CONFIG_PATH);
-    let config = Mutex::new(config.expect("error reading config"));
+fn build_ui(application: &gtk::Application, config: Arc<Config>, config_path: PathBuf) {
     let window = gtk::ApplicationWindow::new(application);
     window.set_title("Latency Tester");
     window.set_default_size(600, 300);
@@ -72,13 +175,8 @@ fn build_ui(application: &gtk::Application) {
     let start_button = gtk::Button::builder()
         .label("Start")
         .build();
-    let addr_setting;
-    {
-        let binding = config.lock().unwrap();
-        addr_setting = binding.address.clone();
-    }
     let addr = gtk::Entry::builder()
-        .text(addr_setting)
+        .text(config.network.address.as_str())
         .build();
     let button_connect = gtk::Button::builder()
         .label("Connect")
@@ -86,11 +184,11 @@ fn build_ui(application: &gtk::Application) {
     let button_listen = gtk::Button::builder()
         .label("Listen")
         .build();
-    let status_image = Arc::new(Image::builder()
+    let status_image = Image::builder()
         .width_request(32)
         .height_request(32)
         .pixel_size(1)
-        .build());
+        .build();
     let label_text_ping = Label::builder()
         .valign(Align::Start)
         .build();
@@ -103,124 +201,375 @@ fn build_ui(application: &gtk::Application) {
     let label_monitor = Label::builder()
         .label("Monitors:")
         .build();
-    let run_stopwatch = Arc::new(AtomicBool::new(false));
-
-    let (sender, receiver)
-        = async_channel::bounded::<UpdateUI>(10);
-
-    let sender = Arc::new(sender);
-    let network
-        = Arc::new(network::networkmanager::NetworkManager::new());
-
-    let network_connect = network.clone();
-    let network_client_connect = network.clone();
-    let network_ui_update = network.clone();
-    let sender_start = sender.clone();
-    let sender_capture = sender.clone();
-    let run_stopwatch_start = run_stopwatch.clone();
-    let start_t = move |inst: Instant| {
-        let run_stopwatch = Arc::clone(&run_stopwatch_start);
-        let sender_start = Arc::clone(&sender_start);
-        start_timer(run_stopwatch, sender_start, inst);
-    };
+    let combobox_profiles = gtk::ComboBoxText::with_entry();
+    for name in config.profiles.keys() {
+        combobox_profiles.append(Some(name), name);
+    }
+    combobox_profiles.set_active_id(Some(config.session.profile.as_str()));
+    let button_save_profile = gtk::Button::builder()
+        .label("Save Profile")
+        .build();
+    let button_report = gtk::Button::builder()
+        .label("Report")
+        .build();
+    let button_scenario = gtk::Button::builder()
+        .label("Run Scenario")
+        .build();
+    let button_calibrate = gtk::Button::builder()
+        .label("Calibrate")
+        .build();
+    // the capture is locked to the clicked windows
+    let label_windows = Label::builder()
+        .label("Windows:")
+        .build();
+    let button_pick_remote = gtk::Button::builder()
+        .label("Pick VDI Window")
+        .build();
+    let button_pick_local = gtk::Button::builder()
+        .label("Pick Own Window")
+        .build();
+    let label_workload = Label::builder()
+        .label("Remote load:")
+        .build();
+    let combobox_workload = gtk::ComboBoxText::new();
+    for kind in WorkloadKind::ALL {
+        combobox_workload.append(Some(kind.name()), kind.name());
+    }
+    combobox_workload.set_active_id(Some(config.workload.kind.name()));
+    let label_mode = Label::builder()
+        .label("Measure:")
+        .build();
+    let combobox_mode = gtk::ComboBoxText::new();
+    for mode in MeasureMode::ALL {
+        combobox_mode.append(Some(mode.name()), mode.name());
+    }
+    combobox_mode.set_active_id(Some(config.session.mode.name()));
+    // color patch of the remote, flipped between black and white by an input while armed or filled with the
+    // color of a flash
+    let patch = DrawingArea::builder()
+        .width_request(160)
+        .height_request(160)
+        .build();
+    let patch_color = Rc::new(Cell::new([0u8; 3]));
+    let input_armed = Rc::new(Cell::new(false));
+    let color = Rc::clone(&patch_color);
+    patch.connect_draw(move |_, context| {
+        let [r, g, b] = color.get().map(|c| c as f64 / 255.0);
+        context.set_source_rgb(r, g, b);
+        let _ = context.paint();
+        glib::Propagation::Stop
+    });
+    let patch_box = gtk::EventBox::new();
+    patch_box.add(&patch);
+    // load window of the remote instance
+    let mut load_window: Option<LoadWindow> = None;
+    let label_phase = Label::default();
+    learn_timer_glyphs();
+
+    let app = App::start((*config).clone(), config_path);
+    app.add_report_metadata("GTK", format!("{}.{}.{}", gtk::major_version(), gtk::minor_version(),
+                                           gtk::micro_version()));
+    let events = app.subscribe();
+
+    let combobox_monitors = gtk::ComboBoxText::builder()
+        .build();
+    for monitor in get_monitors() {
+        combobox_monitors.append(Some(monitor.name()), monitor.name());
+    }
+    combobox_monitors.append(Some(ALL_MONITORS), "All monitors");
+    if !combobox_monitors.set_active_id(Some(config.capture.monitor.as_str())) {
+        combobox_monitors.set_active(Some(0));
+    }
+    let app_monitor = Arc::clone(&app);
+    combobox_monitors.connect_changed(move |combobox| {
+        if let Some(name) = combobox.active_id() {
+            execute(&app_monitor, Command::SelectMonitor(name.to_string()));
+        }
+    });
 
-    let montiros = get_monitors();
-    let combobox_monitors= Arc::new(gtk::ComboBoxText::builder()
-        .build());
+    // the timer label is captured unless the config has a capture box
+    let app_capture_box = Arc::clone(&app);
+    label_timer.connect_size_allocate(move |label, _| {
+        app_capture_box.set_default_capture_box(CaptureBox::new(
+            (label.allocated_width() as f32 * 1.1) as i32,
+            (label.allocated_height() as f32 * 1.1) as i32,
+            -((label.allocated_width() as f32 + 32.0) * 1.05) as i32,
+            0));
+    });
 
-    for monitor in montiros {
-        combobox_monitors.deref().append_text(monitor.name());
-    }
-    combobox_monitors.deref().set_active(Some(0));
-
-    let start_2 = start_t.clone();
-
-    let label_timer_capture = label_timer.clone();
-    let combobox_monitors_clone = Arc::clone(&combobox_monitors);
-    start_button.connect_clicked(move |_| {
-        network.send(NetworkMessage::StartTimer);
-        start_2(Instant::now());
-        let sender_capture = Arc::clone(&sender_capture);
-
-        let capture_box = Arc::new(CaptureBox::new((label_timer_capture.allocated_width() as f32 * 1.1) as i32,
-                                                   (label_timer_capture.allocated_height() as f32 * 1.1) as i32,
-                                                   -((label_timer_capture.allocated_width() as f32 + 32.0) * 1.05) as i32,
-                                                   0));
-        capture_screen(sender_capture, capture_box,combobox_monitors_clone.active().unwrap() as usize);
+    let app_start = Arc::clone(&app);
+    start_button.connect_clicked(move |_| execute(&app_start, Command::Measure));
+
+    let window_scenario = window.clone();
+    let app_scenario = Arc::clone(&app);
+    button_scenario.connect_clicked(move |button| {
+        if app_scenario.scenario_running() {
+            execute(&app_scenario, Command::CancelScenario);
+            button.set_sensitive(false);
+            return;
+        }
+        let configured = app_scenario.config().session.scenario;
+        let path = if configured.is_empty() {
+            match choose_scenario_file(&window_scenario) {
+                Some(path) => path,
+                None => return,
+            }
+        } else {
+            PathBuf::from(configured)
+        };
+        execute(&app_scenario, Command::RunScenario(path));
     });
 
-    let sender_connect = sender.clone();
-    let addr2 = addr.clone();
-    let status_image_clone = Arc::clone(&status_image);
-    button_connect.connect_clicked(move |_| {
-        let sender = Arc::clone(&sender_connect);
-        let network_client_connect = Arc::clone(&network_client_connect);
-        network_client_connect.connect(true, Transport::Udp,
-                                       addr2.text().as_str().to_remote_addr().unwrap(), sender);
-        set_image(status_image_clone.deref(), IMAGE_BYTES_CLIENT);
+    let app_address = Arc::clone(&app);
+    addr.connect_changed(move |entry| execute(&app_address, Command::SetAddress(entry.text().to_string())));
+    let app_connect = Arc::clone(&app);
+    button_connect.connect_clicked(move |_| execute(&app_connect, Command::Connect));
+    let app_listen = Arc::clone(&app);
+    button_listen.connect_clicked(move |_| execute(&app_listen, Command::Listen));
+
+    let app_workload = Arc::clone(&app);
+    combobox_workload.connect_changed(move |combobox| {
+        let Some(kind) = WorkloadKind::ALL.into_iter()
+            .find(|k| combobox.active_id().is_some_and(|id| id == k.name())) else { return; };
+        execute(&app_workload, Command::SetWorkload(kind));
     });
 
-    let addr3 = addr.clone();
-    let status_image_3 = Arc::clone(&status_image);
+    let app_mode = Arc::clone(&app);
+    combobox_mode.connect_changed(move |combobox| {
+        let Some(mode) = MeasureMode::ALL.into_iter()
+            .find(|m| combobox.active_id().is_some_and(|id| id == m.name())) else { return; };
+        execute(&app_mode, Command::SetMode(mode));
+    });
+
+    // the injected input is taken away from the focused widget while armed
+    let (app_key, armed, color, area) = (Arc::clone(&app), Rc::clone(&input_armed), Rc::clone(&patch_color),
+                                         patch.clone());
+    window.connect_key_press_event(move |_, _| flip_patch(&app_key, Instant::now(), &armed, &color, &area));
+    let (app_click, armed, color, area) = (Arc::clone(&app), Rc::clone(&input_armed), Rc::clone(&patch_color),
+                                           patch.clone());
+    patch_box.connect_button_press_event(move |_, _| flip_patch(&app_click, Instant::now(), &armed, &color, &area));
+    // the patch of the remote is found next to its marker like the timer
+    let (app_flash, marker) = (Arc::clone(&app), status_image.clone());
+    patch.connect_size_allocate(move |patch, _| {
+        if let Some(region) = flash_region(patch, &marker) {
+            app_flash.set_default_flash_region(region);
+        }
+    });
+
+    let app_profile = Arc::clone(&app);
+    combobox_profiles.connect_changed(move |combobox| {
+        let Some(name) = combobox.active_id() else { return; };
+        execute(&app_profile, Command::SelectProfile(name.to_string()));
+    });
 
-    button_listen.connect_clicked(move |_| {
-        let sender = Arc::clone(&sender);
-        let network_connect = Arc::clone(&network_connect);
-        network_connect.connect(false, Transport::Udp,
-                                addr3.text().as_str().to_remote_addr().unwrap(), sender);
-        set_image(status_image_3.deref(), IMAGE_BYTES_SERVER);
+    let combobox_profiles_save = combobox_profiles.clone();
+    let app_save = Arc::clone(&app);
+    button_save_profile.connect_clicked(move |_| {
+        let Some(name) = combobox_profiles_save.active_text() else { return; };
+        execute(&app_save, Command::SaveProfile(name.to_string()));
     });
 
+    let app_report = Arc::clone(&app);
+    button_report.connect_clicked(move |_| execute(&app_report, Command::WriteReport));
+    let app_calibrate = Arc::clone(&app);
+    button_calibrate.connect_clicked(move |_| execute(&app_calibrate, Command::Calibrate));
+    let app_pick = Arc::clone(&app);
+    button_pick_remote.connect_clicked(move |_| execute(&app_pick, Command::PickWindow(WindowRole::Remote)));
+    let app_pick = Arc::clone(&app);
+    button_pick_local.connect_clicked(move |_| execute(&app_pick, Command::PickWindow(WindowRole::Local)));
+
 
     let (scrolled_window, list_box) = add_delay_listbox();
+    let (log_window, log_view) = add_log_panel();
+    let (log_sender, log_receiver) = async_channel::unbounded::<String>();
+    logging::set_panel(log_sender);
 
     grid.attach(&label_timer, 0, 0, 1, 1);
-    grid.attach(status_image.deref(), 1, 0, 1, 1);
+    grid.attach(&status_image, 1, 0, 1, 1);
+    grid.attach(&label_phase, 2, 0, 2, 1);
+    grid.attach(&button_scenario, 0, 1, 1, 1);
+    grid.attach(&button_report, 2, 1, 1, 1);
+    grid.attach(&button_calibrate, 3, 1, 1, 1);
     grid.attach(&start_button, 1, 1, 1, 1);
     grid.attach(&addr, 0, 2, 2, 1);
     grid.attach(&button_connect, 0, 3, 1, 1);
     grid.attach(&button_listen, 1, 3, 1, 1);
+    grid.attach(&combobox_profiles, 0, 4, 1, 1);
+    grid.attach(&button_save_profile, 1, 4, 1, 1);
     grid.attach(&scrolled_window, 4, 0, 3, 4);
     grid.attach(&label_text_ping, 0, 5, 1, 1);
     grid.attach(&label_ping, 1, 5, 1, 1);
     grid.attach(&label_monitor, 0, 6, 1, 1);
-    grid.attach(combobox_monitors.deref(), 1, 6, 4, 1);
+    grid.attach(&combobox_monitors, 1, 6, 4, 1);
+    grid.attach(&label_workload, 0, 7, 1, 1);
+    grid.attach(&combobox_workload, 1, 7, 4, 1);
+    grid.attach(&label_mode, 0, 8, 1, 1);
+    grid.attach(&combobox_mode, 1, 8, 4, 1);
+    grid.attach(&patch_box, 5, 4, 2, 5);
+    grid.attach(&label_windows, 0, 9, 1, 1);
+    grid.attach(&button_pick_remote, 1, 9, 1, 1);
+    grid.attach(&button_pick_local, 2, 9, 1, 1);
+    grid.attach(&log_window, 0, 10, 7, 1);
+    glib::MainContext::default().spawn_local(async move {
+        while let Ok(line) = log_receiver.recv().await {
+            append_log_line(&log_view, &line);
+        }
+    });
     // Spawn a future on main context and set the text buffer text from here
     glib::MainContext::default().spawn_local(async move {
-        while let Ok(message) = receiver.recv().await {
-            match message {
-                UpdateUI::SetTimer(text) => { label_timer.set_text(text.as_str()); }
-                UpdateUI::StartTimer(inst) => { start_t(inst); }
-                UpdateUI::ResetTimer => {
-                    run_stopwatch.store(false, Ordering::Relaxed);
+        while let Ok(event) = events.recv().await {
+            match event {
+                Event::Timer(text) => { label_timer.set_text(text.as_str()); }
+                Event::Role(Role::Client) => set_image(&status_image, IMAGE_BYTES_CLIENT),
+                Event::Role(Role::Server) => set_image(&status_image, IMAGE_BYTES_SERVER),
+                Event::Measured(measurement) => {
+                    let delay = Duration::from_secs_f64(measurement.delay_ms / 1000.0);
+                    let tags: Vec<&str> = [measurement.profile.as_str(), measurement.phase.as_str()]
+                        .into_iter().filter(|t| !t.is_empty()).collect();
+                    let text = if tags.is_empty() {
+                        format!("{}: {:?}", Local::now().format("%X"), delay)
+                    } else {
+                        format!("{} [{}]: {:?}", Local::now().format("%X"), tags.join(", "), delay)
+                    };
+                    let text = if measurement.excluded { format!("{} (excluded)", text) } else { text };
+                    let label = Label::new(Some(text.as_str()));
+
+                    list_box.prepend(&label);
+                    label.show();
+                }
+                Event::MeasurementFailed | Event::PingLost => {}
+                Event::CalibrationStarted => {
+                    if status_image.pixbuf().is_none() {
+                        set_image(&status_image, IMAGE_BYTES_CLIENT);
+                    }
+                }
+                Event::Calibrated(calibration) => {
+                    let text = match calibration {
+                        Some(c) => format!("Capture latency: median {:.1} ms, jitter {:.1} ms, uncertainty ±{:.1} ms",
+                                           c.latency_ms.median, c.latency_ms.stddev, c.uncertainty_ms),
+                        None => "Capture latency: calibration failed".to_string(),
+                    };
+                    let label = Label::new(Some(text.as_str()));
+                    list_box.prepend(&label);
+                    label.show();
+                }
+                Event::WindowPicked(role, title) => {
+                    let name = if role == WindowRole::Remote { "VDI window" } else { "Own window" };
+                    let text = match title {
+                        Some(title) => format!("{}: {}", name, title),
+                        None => format!("{}: no window picked", name),
+                    };
+                    let label = Label::new(Some(text.as_str()));
+                    list_box.prepend(&label);
+                    label.show();
+                }
+                Event::Ping(p) => label_ping.set_text(format!("{:?}", p).as_str()),
+                Event::Workload(workload) => {
+                    if let Some(window) = load_window.take() {
+                        window.close();
+                    }
+                    load_window = LoadWindow::open(&workload);
+                }
+                Event::InputArmed => input_armed.set(true),
+                Event::Flash(color) => {
+                    patch_color.set(color);
+                    patch.queue_draw();
+                }
+                Event::Phase(label) => {
+                    if label.is_empty() {
+                        label_phase.set_text("");
+                    } else {
+                        label_phase.set_text(format!("Phase: {}", label).as_str());
+                    }
+                }
+                Event::ScenarioStarted(name) => {
+                    button_scenario.set_label("Stop Scenario");
+                    label_phase.set_text(format!("Scenario: {}", name).as_str());
+                }
+                Event::ScenarioFinished(name, results) => {
+                    button_scenario.set_label("Run Scenario");
+                    button_scenario.set_sensitive(true);
+                    for result in &results {
+                        let text = match &result.delay_ms {
+                            Some(s) => format!("{}: median {:.1} ms, p95 {:.1} ms ({} ok, {} failed)",
+                                               result.label, s.median, s.p95, s.count, result.failures),
+                            None => format!("{}: no measurements ({} failed)", result.label, result.failures),
+                        };
+                        let label = Label::new(Some(text.as_str()));
+                        list_box.prepend(&label);
+                        label.show();
+                    }
+                    let label = Label::new(Some(format!("Scenario {}:", name).as_str()));
+                    list_box.prepend(&label);
+                    label.show();
                 }
-                UpdateUI::DelayMeasured(x) => {
-                    run_stopwatch.store(false, Ordering::Relaxed);
-                    network_ui_update.send(NetworkMessage::ResetTimer);
-                    match x {
-                        None => {}
-                        Some(d) => {
-                            let label = Label::new(Some(format!("{}: {:?}", Local::now().format("%X"), d).as_str()));
-
-                            list_box.prepend(&label);
-                            label.show();
-                        }
+                // also sent for changes made through the control API, setting the values the widgets
+                // already show emits no further changed signals
+                Event::ConfigChanged(config) => {
+                    // an address being typed is not replaced by an older one
+                    if !addr.has_focus() {
+                        addr.set_text(config.network.address.as_str());
+                    }
+                    combobox_monitors.set_active_id(Some(config.capture.monitor.as_str()));
+                    combobox_workload.set_active_id(Some(config.workload.kind.name()));
+                    combobox_mode.set_active_id(Some(config.session.mode.name()));
+                    if !config.session.profile.is_empty() {
+                        combobox_profiles.set_active_id(Some(config.session.profile.as_str()));
                     }
                 }
-                UpdateUI::Ping(p) => {
-                    label_ping.set_text(format!("{:?}", p).as_str());
+                Event::ProfileSaved(name) => {
+                    if !combobox_profiles.set_active_id(Some(name.as_str())) {
+                        combobox_profiles.append(Some(name.as_str()), name.as_str());
+                        combobox_profiles.set_active_id(Some(name.as_str()));
+                    }
                 }
             }
         }
     });
-    window.connect_destroy(move |_| {
-        let mut config = config.lock().unwrap();
-        config.address = String::from(addr.text());
-        let _ = write_config(&config, CONFIG_PATH);
+    // GDK knows the refresh rate of the monitor the window is on, also after it was moved to another one
+    let app_refresh = Arc::clone(&app);
+    window.connect_configure_event(move |window, _| {
+        let monitor = window.window().and_then(|w| window.display().monitor_at_window(&w));
+        if let Some(monitor) = monitor {
+            app_refresh.set_refresh_rate(monitor.refresh_rate() as f64 / 1000.0);
+        }
+        false
     });
+    window.connect_destroy(move |_| app.shutdown());
     window.show_all();
 }
 
+// Flips the color patch if an input latency measurement armed it and reports the input that `arrived`.
+fn flip_patch(app: &App, arrived: Instant, armed: &Cell<bool>, color: &Cell<[u8; 3]>, patch: &DrawingArea)
+              -> glib::Propagation {
+    if !armed.replace(false) {
+        return glib::Propagation::Proceed;
+    }
+    execute(app, Command::InputReceived(arrived));
+    color.set(if color.get() == [0; 3] { [255; 3] } else { [0; 3] });
+    patch.queue_draw();
+    glib::Propagation::Stop
+}
+
+// Inner part of the color patch relative to the marker image, the edges blend with the window background once
+// the remote display is scaled or compressed.
+fn flash_region(patch: &DrawingArea, marker: &Image) -> Option<CaptureBox> {
+    let (x, y) = patch.translate_coordinates(marker, 0, 0)?;
+    // the marker image is centered in its widget
+    let (width, height) = marker.pixbuf().map(|p| (p.width(), p.height()))
+        .unwrap_or((marker.allocated_width(), marker.allocated_height()));
+    let x = x - (marker.allocated_width() - width) / 2;
+    let y = y - (marker.allocated_height() - height) / 2;
+    let (width, height) = (patch.allocated_width(), patch.allocated_height());
+    Some(CaptureBox::new(width * 3 / 4, height * 3 / 4, x + width / 8, y + height / 8))
+}
+
+fn execute(app: &App, command: Command) {
+    if let Err(e) = app.execute(command) {
+        error!(error = e.as_str(); "command failed");
+    }
+}
+
 fn set_image(image: &Image, image_data: &[u8]) {
     let loader = PixbufLoader::with_type("jpeg").unwrap();
     loader.write(image_data).unwrap();
@@ -229,14 +578,48 @@ fn set_image(image: &Image, image_data: &[u8]) {
     image.set_from_pixbuf(Some(&pixbuf));
 }
 
-fn start_timer(run_stopwatch: Arc<AtomicBool>, sender: Arc<Sender<UpdateUI>>, inst: Instant) {
-    run_stopwatch.store(true, Ordering::Relaxed);
+// Renders the characters of the timer in the label font and learns them for the digit reader.
+fn learn_timer_glyphs() {
+    let offscreen = gtk::OffscreenWindow::new();
+    let label = Label::new(Some(digits::REFERENCE_TEXT));
+    offscreen.add(&label);
+    offscreen.show_all();
+    while gtk::events_pending() {
+        gtk::main_iteration();
+    }
+    let reference = offscreen.pixbuf().map(|p| pixbuf_to_image(&p));
+    offscreen.close();
+    match reference.map(|r| digits::GlyphSet::learn(&r, digits::REFERENCE_TEXT)) {
+        Some(Ok(glyphs)) => digits::set_glyphs(glyphs),
+        Some(Err(e)) => error!(error = e.as_str(); "error learning timer glyphs"),
+        None => error!("error rendering timer glyphs"),
+    }
+}
 
-    thread::spawn(move || {
-        timer_update(sender, run_stopwatch, inst);
-    });
+fn choose_scenario_file(window: &gtk::ApplicationWindow) -> Option<PathBuf> {
+    let dialog = gtk::FileChooserDialog::with_buttons(
+        Some("Scenario"), Some(window), gtk::FileChooserAction::Open,
+        &[("Cancel", gtk::ResponseType::Cancel), ("Run", gtk::ResponseType::Accept)]);
+    let filter = gtk::FileFilter::new();
+    filter.add_pattern("*.toml");
+    dialog.set_filter(&filter);
+    let path = match dialog.run() {
+        gtk::ResponseType::Accept => dialog.filename(),
+        _ => None,
+    };
+    dialog.close();
+    path
 }
 
+fn pixbuf_to_image(pixbuf: &gtk::gdk_pixbuf::Pixbuf) -> image::RgbImage {
+    let bytes = pixbuf.read_pixel_bytes();
+    let channels = pixbuf.n_channels() as usize;
+    let rowstride = pixbuf.rowstride() as usize;
+    image::RgbImage::from_fn(pixbuf.width() as u32, pixbuf.height() as u32, |x, y| {
+        let i = y as usize * rowstride + x as usize * channels;
+        image::Rgb([bytes[i], bytes[i + 1], bytes[i + 2]])
+    })
+}
 
 fn add_delay_listbox() -> (ScrolledWindow, ListBox) {
     let listbox = ListBox::builder()
@@ -253,22 +636,42 @@ fn add_delay_listbox() -> (ScrolledWindow, ListBox) {
     (scrolled_window, listbox)
 }
 
+fn add_log_panel() -> (ScrolledWindow, TextView) {
+    let text_view = TextView::builder()
+        .editable(false)
+        .cursor_visible(false)
+        .monospace(true)
+        .build();
 
-fn timer_update(sender: Arc<Sender<UpdateUI>>, run: Arc<AtomicBool>, inst: Instant) {
-    loop {
-        if !run.load(Ordering::Relaxed) {
-            let _ = sender.deref().send_blocking(UpdateUI::SetTimer(elapsed_to_string(&Instant::now())))
-                .expect("timer channel closed");
-            break;
-        }
-        let _ = sender.deref().send_blocking(UpdateUI::SetTimer(elapsed_to_string(&inst)))
-            .expect("timer channel closed");
-        sleep(Duration::from_millis(4));
+    let scrolled_window = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(PolicyType::Automatic)
+        .min_content_height(120)
+        .min_content_width(600)
+        .build();
+    scrolled_window.add(&text_view);
+    (scrolled_window, text_view)
+}
+
+fn append_log_line(text_view: &TextView, line: &str) {
+    const MAX_LINES: i32 = 500;
+    let Some(buffer) = text_view.buffer() else { return; };
+    buffer.insert(&mut buffer.end_iter(), &format!("{}\n", line));
+    if buffer.line_count() > MAX_LINES {
+        let mut start = buffer.start_iter();
+        let mut end = buffer.iter_at_line(buffer.line_count() - MAX_LINES);
+        buffer.delete(&mut start, &mut end);
     }
+    text_view.scroll_to_iter(&mut buffer.end_iter(), 0.0, false, 0.0, 1.0);
 }
 
+
 fn elapsed_to_string(instant: &Instant) -> String {
-    let seconds = instant.elapsed().as_secs();
+    // read the clock once, otherwise the seconds and milliseconds can be from different sides of a rollover
+    duration_to_string(instant.elapsed())
+}
+
+fn duration_to_string(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
     const MINUTE: u64 = 60;
     const HOUR: u64 = 60 * MINUTE;
     format!(
@@ -276,6 +679,26 @@ fn elapsed_to_string(instant: &Instant) -> String {
         seconds / HOUR,
         (seconds % HOUR) / MINUTE,
         seconds % MINUTE,
-        instant.elapsed().subsec_millis(),
+        elapsed.subsec_millis(),
     )
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timer_text_rolls_over_minutes_and_hours() {
+        let at = |s: u64, ms: u64| duration_to_string(Duration::from_secs(s) + Duration::from_millis(ms));
+        assert_eq!(at(0, 0), "00:00:00.000");
+        assert_eq!(at(59, 999), "00:00:59.999");
+        assert_eq!(at(60, 0), "00:01:00.000");
+        assert_eq!(at(3599, 999), "00:59:59.999");
+        assert_eq!(at(3600, 0), "01:00:00.000");
+        assert_eq!(at(86_399, 5), "23:59:59.005");
+    }
+
+    #[test]
+    fn timer_text_ignores_sub_millisecond_part() {
+        assert_eq!(duration_to_string(Duration::from_nanos(1_999_999_999)), "00:00:01.999");
+    }
+}
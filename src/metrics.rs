@@ -0,0 +1,225 @@
+// Prometheus metrics of the session, served in the text exposition format on `/metrics`.
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use log::{error, info, warn};
+use crate::results::Measurement;
+use crate::screenshot::{CaptureFailure, StageTimings};
+
+// upper bounds of the histogram buckets in seconds
+const DELAY_BUCKETS: [f64; 12] = [0.005, 0.01, 0.02, 0.03, 0.05, 0.075, 0.1, 0.15, 0.2, 0.3, 0.5, 1.0];
+const OCR_BUCKETS: [f64; 8] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25];
+
+#[derive(Default)]
+pub struct Metrics {
+    state: Mutex<MetricsState>,
+}
+
+#[derive(Default)]
+struct MetricsState {
+    // delay histogram per profile and mode
+    delays: BTreeMap<(String, &'static str), Histogram>,
+    ocr: Histogram,
+    capture_failures: BTreeMap<&'static str, u64>,
+    rtt: Option<f64>,
+    // smoothed RTT variation as in RFC 3550
+    jitter: f64,
+    pongs: u64,
+    pings_lost: u64,
+}
+
+#[derive(Default)]
+struct Histogram {
+    // cumulative counts per bucket
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, bounds: &[f64], value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; bounds.len()];
+        }
+        for (bucket, bound) in self.buckets.iter_mut().zip(bounds) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, labels: &str, bounds: &[f64]) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        for (i, bound) in bounds.iter().enumerate() {
+            let count = self.buckets.get(i).copied().unwrap_or(0);
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, separator, bound, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, separator, self.count);
+        let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+impl Metrics {
+    pub fn record_measurement(&self, measurement: &Measurement) {
+        let mut state = self.state.lock().unwrap();
+        state.delays.entry((measurement.profile.clone(), measurement.mode.name())).or_default()
+            .observe(&DELAY_BUCKETS, measurement.delay_ms / 1000.0);
+    }
+
+    pub fn record_attempt(&self, failure: Option<CaptureFailure>, timings: &StageTimings) {
+        let mut state = self.state.lock().unwrap();
+        if !timings.ocr.is_zero() {
+            state.ocr.observe(&OCR_BUCKETS, timings.ocr.as_secs_f64());
+        }
+        if let Some(failure) = failure {
+            *state.capture_failures.entry(failure.name()).or_default() += 1;
+        }
+    }
+
+    pub fn record_ping(&self, rtt: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let rtt = rtt.as_secs_f64();
+        if let Some(last) = state.rtt {
+            state.jitter += ((rtt - last).abs() - state.jitter) / 16.0;
+        }
+        state.rtt = Some(rtt);
+        state.pongs += 1;
+    }
+
+    pub fn record_ping_lost(&self) {
+        self.state.lock().unwrap().pings_lost += 1;
+    }
+
+    pub fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+        out.push_str("# HELP latency_tester_delay_seconds Measured display delay or input latency.\n");
+        out.push_str("# TYPE latency_tester_delay_seconds histogram\n");
+        for ((profile, mode), histogram) in &state.delays {
+            let labels = format!("profile=\"{}\",mode=\"{}\"", escape_label(profile), mode);
+            histogram.write(&mut out, "latency_tester_delay_seconds", &labels, &DELAY_BUCKETS);
+        }
+        out.push_str("# HELP latency_tester_ocr_seconds Time spent reading the timers of one screenshot.\n");
+        out.push_str("# TYPE latency_tester_ocr_seconds histogram\n");
+        state.ocr.write(&mut out, "latency_tester_ocr_seconds", "", &OCR_BUCKETS);
+        out.push_str("# HELP latency_tester_capture_failures_total Failed capture attempts by reason.\n");
+        out.push_str("# TYPE latency_tester_capture_failures_total counter\n");
+        for (reason, count) in &state.capture_failures {
+            let _ = writeln!(out, "latency_tester_capture_failures_total{{reason=\"{}\"}} {}", reason, count);
+        }
+        if let Some(rtt) = state.rtt {
+            out.push_str("# HELP latency_tester_rtt_seconds Round trip time of the last ping.\n");
+            out.push_str("# TYPE latency_tester_rtt_seconds gauge\n");
+            let _ = writeln!(out, "latency_tester_rtt_seconds {}", rtt);
+            out.push_str("# HELP latency_tester_jitter_seconds Smoothed variation of the round trip time.\n");
+            out.push_str("# TYPE latency_tester_jitter_seconds gauge\n");
+            let _ = writeln!(out, "latency_tester_jitter_seconds {}", state.jitter);
+        }
+        out.push_str("# HELP latency_tester_pings_total Pings that were answered or lost.\n");
+        out.push_str("# TYPE latency_tester_pings_total counter\n");
+        let _ = writeln!(out, "latency_tester_pings_total {}", state.pongs + state.pings_lost);
+        out.push_str("# HELP latency_tester_pings_lost_total Pings without an answer before the next ping.\n");
+        out.push_str("# TYPE latency_tester_pings_lost_total counter\n");
+        let _ = writeln!(out, "latency_tester_pings_lost_total {}", state.pings_lost);
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// Serves `GET /metrics` on `address` from its own thread.
+pub fn serve(metrics: Arc<Metrics>, address: &str) {
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(address = address, error:% = e; "can not serve metrics");
+            return;
+        }
+    };
+    info!(address = address; "serving metrics");
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = handle(stream, &metrics) {
+                warn!(error:% = e; "error answering metrics request");
+            }
+        }
+    });
+}
+
+fn handle(stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // the headers are not needed but have to be read before answering
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+    let mut stream = &stream;
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\
+                    Connection: close\r\n\r\n{}", status, body.len(), body)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MeasureMode;
+
+    #[test]
+    fn exposition_has_histogram_buckets_and_failure_counters() {
+        let metrics = Metrics::default();
+        for delay_ms in [25, 120, 2000] {
+            metrics.record_measurement(&Measurement::new(MeasureMode::Display, Duration::from_millis(delay_ms),
+                                                         "lab \"1\"", ""));
+        }
+        metrics.record_measurement(&Measurement::new(MeasureMode::Input, Duration::from_millis(40), "", ""));
+        let timings = StageTimings { ocr: Duration::from_millis(3), ..StageTimings::default() };
+        metrics.record_attempt(Some(CaptureFailure::Ocr), &timings);
+        metrics.record_attempt(Some(CaptureFailure::Ocr), &timings);
+        // attempts that did not get to reading the timers are not observed in the OCR histogram
+        metrics.record_attempt(Some(CaptureFailure::WindowNotFound), &StageTimings::default());
+        metrics.record_attempt(None, &timings);
+        let text = metrics.render();
+        let lines: Vec<&str> = text.lines().collect();
+        let labels = "profile=\"lab \\\"1\\\"\",mode=\"display\"";
+        for expected in [
+            format!("latency_tester_delay_seconds_bucket{{{},le=\"0.02\"}} 0", labels),
+            format!("latency_tester_delay_seconds_bucket{{{},le=\"0.03\"}} 1", labels),
+            format!("latency_tester_delay_seconds_bucket{{{},le=\"0.1\"}} 1", labels),
+            format!("latency_tester_delay_seconds_bucket{{{},le=\"0.15\"}} 2", labels),
+            format!("latency_tester_delay_seconds_bucket{{{},le=\"1\"}} 2", labels),
+            format!("latency_tester_delay_seconds_bucket{{{},le=\"+Inf\"}} 3", labels),
+            format!("latency_tester_delay_seconds_sum{{{}}} 2.145", labels),
+            format!("latency_tester_delay_seconds_count{{{}}} 3", labels),
+            "latency_tester_delay_seconds_bucket{profile=\"\",mode=\"input\",le=\"0.05\"} 1".to_string(),
+            "latency_tester_ocr_seconds_bucket{le=\"0.0025\"} 0".to_string(),
+            "latency_tester_ocr_seconds_bucket{le=\"0.005\"} 3".to_string(),
+            "latency_tester_ocr_seconds_count 3".to_string(),
+            "latency_tester_capture_failures_total{reason=\"ocr\"} 2".to_string(),
+            "latency_tester_capture_failures_total{reason=\"window_not_found\"} 1".to_string(),
+            "latency_tester_pings_total 0".to_string(),
+        ] {
+            assert!(lines.contains(&expected.as_str()), "{} missing in\n{}", expected, text);
+        }
+        assert!(!text.contains("latency_tester_rtt_seconds "), "{}", text);
+        assert!(text.contains("# TYPE latency_tester_delay_seconds histogram\n"));
+        assert!(text.contains("# TYPE latency_tester_capture_failures_total counter\n"));
+    }
+}
@@ -0,0 +1,84 @@
+// Clock of the timestamps exchanged between the instances and the offset between the clocks of both
+// instances. The offset is estimated from the pings: the remote reads its clock while answering, which is
+// assumed to happen halfway through the round trip. The error is at most half of the round trip, so the
+// sample with the shortest round trip among the recent ones is used.
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+// recent pings the offset is estimated from, older ones are forgotten in case a clock drifts
+const SAMPLES: usize = 16;
+
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+// Microseconds of `instant` since the clock was first used in this process, 0 for earlier instants.
+pub fn micros(instant: Instant) -> u64 {
+    instant.saturating_duration_since(*EPOCH.get_or_init(Instant::now)).as_micros() as u64
+}
+
+#[derive(Default, Debug)]
+pub struct ClockOffset {
+    // round trip time and offset of the recent pings
+    samples: VecDeque<(u64, i64)>,
+}
+
+impl ClockOffset {
+    // Adds a ping sent at `sent` and answered at `received` on the local clock, read `remote` on the remote
+    // clock while answering.
+    pub fn add(&mut self, sent: u64, received: u64, remote: u64) {
+        let rtt = received.saturating_sub(sent);
+        let offset = remote as i64 - (sent + rtt / 2) as i64;
+        if self.samples.len() == SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((rtt, offset));
+    }
+
+    // Remote clock minus local clock in microseconds, None before the first answered ping.
+    pub fn offset(&self) -> Option<i64> {
+        self.samples.iter().min_by_key(|(rtt, _)| *rtt).map(|(_, offset)| *offset)
+    }
+
+    // Local clock at remote clock `remote`.
+    pub fn to_local(&self, remote: u64) -> Option<u64> {
+        self.offset().map(|offset| (remote as i64 - offset).max(0) as u64)
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symmetric_round_trip_gives_exact_offset() {
+        let mut clock = ClockOffset::default();
+        assert_eq!(clock.offset(), None);
+        // the remote clock is 5 s ahead, both legs take 2 ms
+        clock.add(1_000_000, 1_004_000, 6_002_000);
+        assert_eq!(clock.offset(), Some(5_000_000));
+        assert_eq!(clock.to_local(6_010_000), Some(1_010_000));
+    }
+
+    #[test]
+    fn shortest_round_trip_is_used() {
+        let mut clock = ClockOffset::default();
+        // the answer of the first ping was held back on the way back, the estimate is off by 20 ms
+        clock.add(5_000_000, 5_044_000, 4_002_000);
+        clock.add(5_100_000, 5_104_000, 4_102_000);
+        assert_eq!(clock.offset(), Some(-1_000_000));
+    }
+
+    #[test]
+    fn old_samples_are_forgotten() {
+        let mut clock = ClockOffset::default();
+        clock.add(0, 1_000, 500);
+        for i in 1..=SAMPLES as u64 {
+            clock.add(i * 100_000, i * 100_000 + 2_000, i * 100_000 + 1_000 + 300);
+        }
+        assert_eq!(clock.offset(), Some(300));
+    }
+}
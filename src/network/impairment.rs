@@ -0,0 +1,147 @@
+// Simulated network impairment between message_io and the protocol: every received message can be
+// dropped, duplicated, delayed with jitter or held back so that later messages overtake it.
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::config::ImpairmentConfig;
+
+pub struct Impairment {
+    config: ImpairmentConfig,
+    state: u64,
+}
+
+impl Impairment {
+    // None if the config does not impair anything.
+    pub fn new(config: &ImpairmentConfig) -> Option<Impairment> {
+        let active = config.delay_ms > 0 || config.jitter_ms > 0 || config.loss > 0.0
+            || config.duplicate > 0.0 || config.reorder > 0.0;
+        active.then(|| Impairment { config: *config, state: config.seed.max(1) })
+    }
+
+    // Delays after which the copies of a received message are handled, empty if the message is lost.
+    pub fn deliveries(&mut self) -> Vec<Duration> {
+        if self.chance(self.config.loss) {
+            return vec![];
+        }
+        let copies = if self.chance(self.config.duplicate) { 2 } else { 1 };
+        (0..copies).map(|_| {
+            let jitter = self.config.jitter_ms as i64;
+            let mut delay = self.config.delay_ms as i64 + self.between(-jitter, jitter);
+            if self.chance(self.config.reorder) {
+                delay += self.config.reorder_delay_ms as i64;
+            }
+            Duration::from_millis(delay.max(0) as u64)
+        }).collect()
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        // no random number is drawn for unused impairments, so enabling one does not change the others
+        if probability <= 0.0 {
+            return false;
+        }
+        let uniform = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        uniform < probability
+    }
+
+    fn between(&mut self, low: i64, high: i64) -> i64 {
+        if low >= high {
+            return low;
+        }
+        low + (self.next() % (high - low + 1) as u64) as i64
+    }
+}
+
+// Hands items back after their delay on its own thread. The timers of message_io are not used
+// because new timers are only noticed when the node wakes up, which can take 50 ms.
+pub struct DelayQueue<T> {
+    sender: Sender<(Instant, T)>,
+}
+
+impl<T: Send + 'static> DelayQueue<T> {
+    // The thread ends once the queue is dropped, pending items are not delivered then.
+    pub fn start(deliver: impl Fn(T) + Send + 'static) -> DelayQueue<T> {
+        let (sender, receiver) = mpsc::channel::<(Instant, T)>();
+        thread::spawn(move || {
+            let mut pending: Vec<(Instant, T)> = vec![];
+            loop {
+                let received = match pending.iter().map(|(at, _)| *at).min() {
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                    Some(at) => receiver.recv_timeout(at.saturating_duration_since(Instant::now())),
+                };
+                match received {
+                    Ok(item) => pending.push(item),
+                    Err(RecvTimeoutError::Disconnected) => return,
+                    Err(RecvTimeoutError::Timeout) => {}
+                }
+                let now = Instant::now();
+                // items due at the same instant keep their order
+                while let Some(i) = pending.iter().enumerate().filter(|(_, (at, _))| *at <= now)
+                    .min_by_key(|(_, (at, _))| *at).map(|(i, _)| i) {
+                    deliver(pending.remove(i).1);
+                }
+            }
+        });
+        DelayQueue { sender }
+    }
+
+    pub fn push(&self, delay: Duration, item: T) {
+        let _ = self.sender.send((Instant::now() + delay, item));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn impairment(config: ImpairmentConfig) -> Impairment {
+        Impairment::new(&config).expect("impairment is active")
+    }
+
+    #[test]
+    fn default_config_is_inactive() {
+        assert!(Impairment::new(&ImpairmentConfig::default()).is_none());
+    }
+
+    #[test]
+    fn delay_stays_within_jitter() {
+        let mut impairment = impairment(ImpairmentConfig { delay_ms: 50, jitter_ms: 10, ..Default::default() });
+        let delays: Vec<Duration> = (0..1000).flat_map(|_| impairment.deliveries()).collect();
+        assert_eq!(delays.len(), 1000);
+        assert!(delays.iter().all(|d| (40..=60).contains(&d.as_millis())));
+        assert!(delays.iter().any(|d| d.as_millis() < 45) && delays.iter().any(|d| d.as_millis() > 55));
+    }
+
+    #[test]
+    fn loss_and_duplication_follow_their_probabilities() {
+        let mut impairment = impairment(ImpairmentConfig { loss: 0.2, duplicate: 0.1, ..Default::default() });
+        let counts: Vec<usize> = (0..10000).map(|_| impairment.deliveries().len()).collect();
+        let lost = counts.iter().filter(|&&c| c == 0).count();
+        let duplicated = counts.iter().filter(|&&c| c == 2).count();
+        assert!((1800..2200).contains(&lost), "lost {}", lost);
+        // duplication is only decided for the messages that were not lost
+        assert!((650..950).contains(&duplicated), "duplicated {}", duplicated);
+    }
+
+    #[test]
+    fn reordered_messages_are_held_back() {
+        let config = ImpairmentConfig { delay_ms: 5, reorder: 1.0, reorder_delay_ms: 40, ..Default::default() };
+        assert_eq!(impairment(config).deliveries(), vec![Duration::from_millis(45)]);
+    }
+
+    #[test]
+    fn same_seed_gives_same_decisions() {
+        let config = ImpairmentConfig { jitter_ms: 20, loss: 0.3, duplicate: 0.3, reorder: 0.3, seed: 7,
+                                        ..Default::default() };
+        let (mut a, mut b) = (impairment(config), impairment(config));
+        for _ in 0..100 {
+            assert_eq!(a.deliveries(), b.deliveries());
+        }
+    }
+}
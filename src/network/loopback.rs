@@ -0,0 +1,243 @@
+// Local and remote instance in one process, connected over localhost. Used by the loopback command
+// and the tests to check the protocol under the configured impairment.
+use std::error::Error;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use async_channel::{Receiver, Sender};
+use message_io::network::ToRemoteAddr;
+use crate::config::Config;
+use crate::stats::Summary;
+use crate::UpdateUI;
+use super::networkmanager::NetworkManager;
+
+pub struct Loopback {
+    pub server: Arc<NetworkManager>,
+    pub client: Arc<NetworkManager>,
+    pub server_events: Receiver<UpdateUI>,
+    pub client_events: Receiver<UpdateUI>,
+    client_sender: Arc<Sender<UpdateUI>>,
+    config: Config,
+}
+
+// Ping results of one side.
+#[derive(Default, Debug)]
+pub struct PingStats {
+    pub rtts_ms: Vec<f64>,
+    pub lost: usize,
+}
+
+impl PingStats {
+    // Adds the ping results among the events received so far, other events are dropped.
+    pub fn collect(&mut self, events: &Receiver<UpdateUI>) {
+        while let Ok(event) = events.try_recv() {
+            match event {
+                UpdateUI::Ping(rtt) => self.rtts_ms.push(rtt.as_secs_f64() * 1000.0),
+                UpdateUI::PingLost => self.lost += 1,
+                _ => {}
+            }
+        }
+    }
+
+    // pings that were answered or lost
+    pub fn pings(&self) -> usize {
+        self.rtts_ms.len() + self.lost
+    }
+
+    pub fn loss(&self) -> f64 {
+        if self.pings() == 0 { 0.0 } else { self.lost as f64 / self.pings() as f64 }
+    }
+}
+
+impl Loopback {
+    // Starts the server on `network.address` and connects the client to it. With port 0 the client connects to
+    // the port chosen by the system.
+    pub fn start(config: &Config) -> Result<Loopback, Box<dyn Error>> {
+        let ping_interval = Duration::from_millis(config.network.ping_interval_ms);
+        let (server_sender, server_events) = async_channel::unbounded();
+        let (client_sender, client_events) = async_channel::unbounded();
+        let server = Arc::new(NetworkManager::new(ping_interval, &config.impairment));
+        let client = Arc::new(NetworkManager::new(ping_interval, &config.impairment));
+        let address = config.network.address.as_str().to_remote_addr()?;
+        Arc::clone(&server).connect(false, config.network.transport.into(), address, Arc::new(server_sender));
+        let mut config = config.clone();
+        if let Some(address) = server.listening_address() {
+            config.network.address = address.to_string();
+        }
+        let loopback = Loopback {
+            server,
+            client,
+            server_events,
+            client_events,
+            client_sender: Arc::new(client_sender),
+            config,
+        };
+        loopback.connect_client()?;
+        Ok(loopback)
+    }
+
+    // Connects the client, replacing its previous connection.
+    pub fn connect_client(&self) -> Result<(), Box<dyn Error>> {
+        let address = self.config.network.address.as_str().to_remote_addr()?;
+        Arc::clone(&self.client).connect(true, self.config.network.transport.into(), address,
+                                         Arc::clone(&self.client_sender));
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.client.disconnect();
+        self.server.disconnect();
+    }
+}
+
+// Runs the loopback for `duration` and prints the ping statistics of both sides.
+pub fn run_loopback(config: &Config, duration: Duration) -> Result<(), Box<dyn Error>> {
+    let loopback = Loopback::start(config)?;
+    thread::sleep(duration);
+    loopback.stop();
+    for (name, events) in [("client", &loopback.client_events), ("server", &loopback.server_events)] {
+        let mut stats = PingStats::default();
+        stats.collect(events);
+        print!("{:<8} {:>5} pings {:>5} lost ({:.1}%)", name, stats.pings(), stats.lost,
+               stats.loss() * 100.0);
+        match Summary::from_samples(&stats.rtts_ms) {
+            Some(s) => println!("  rtt median {:.1} ms  p95 {:.1} ms  min {:.1} ms  max {:.1} ms",
+                                s.median, s.p95, s.min, s.max),
+            None => println!(),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use crate::config::{ImpairmentConfig, TransportKind};
+
+    // time the expected pings may take, the tests usually finish far earlier
+    const DEADLINE: Duration = Duration::from_secs(10);
+
+    // every test listens on a port chosen by the system, the tests run in parallel
+    fn config(transport: TransportKind, ping_interval_ms: u64, impairment: ImpairmentConfig) -> Config {
+        let mut config = Config::default();
+        config.network.address = "127.0.0.1:0".to_string();
+        config.network.transport = transport;
+        config.network.ping_interval_ms = ping_interval_ms;
+        config.impairment = impairment;
+        config
+    }
+
+    // Collects the ping results of the client and the server until `done` holds for them or the deadline passes.
+    fn collect_until(loopback: &Loopback, done: impl Fn(&PingStats, &PingStats) -> bool) -> (PingStats, PingStats) {
+        let deadline = Instant::now() + DEADLINE;
+        let (mut client, mut server) = (PingStats::default(), PingStats::default());
+        loop {
+            client.collect(&loopback.client_events);
+            server.collect(&loopback.server_events);
+            if done(&client, &server) || Instant::now() > deadline {
+                return (client, server);
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // Runs the loopback until both sides have `pings` ping results and returns those of the client and the server.
+    fn run(config: &Config, pings: usize) -> (PingStats, PingStats) {
+        let loopback = Loopback::start(config).unwrap();
+        let stats = collect_until(&loopback, |client, server| client.pings() >= pings && server.pings() >= pings);
+        loopback.stop();
+        stats
+    }
+
+    fn median(samples: &[f64]) -> f64 {
+        Summary::from_samples(samples).unwrap().median
+    }
+
+    #[test]
+    fn pings_are_answered_without_impairment() {
+        let config = config(TransportKind::Udp, 20, ImpairmentConfig::default());
+        let (client, server) = run(&config, 10);
+        for stats in [&client, &server] {
+            assert!(stats.rtts_ms.len() >= 10, "{:?}", stats);
+            assert_eq!(stats.lost, 0);
+            assert!(stats.rtts_ms.iter().all(|&rtt| rtt < 20.0), "{:?}", stats);
+        }
+    }
+
+    #[test]
+    fn delay_and_jitter_add_to_round_trip_time() {
+        // both sides delay what they receive, so a ping and its pong are delayed once each
+        let impairment = ImpairmentConfig { delay_ms: 15, jitter_ms: 5, ..Default::default() };
+        let config = config(TransportKind::Udp, 100, impairment);
+        let (client, server) = run(&config, 8);
+        for stats in [&client, &server] {
+            assert!(stats.rtts_ms.len() >= 8, "{:?}", stats);
+            assert_eq!(stats.lost, 0);
+            assert!(stats.rtts_ms.iter().all(|&rtt| rtt >= 20.0), "{:?}", stats);
+            assert!((25.0..45.0).contains(&median(&stats.rtts_ms)), "{:?}", stats);
+        }
+    }
+
+    #[test]
+    fn lost_pings_are_counted() {
+        // a ping survives if neither the ping nor the pong is lost, 0.7 * 0.7 = 49%
+        let impairment = ImpairmentConfig { loss: 0.3, seed: 3, ..Default::default() };
+        let config = config(TransportKind::Udp, 20, impairment);
+        // the server does not ping if the Connect message of the client is lost
+        let loopback = Loopback::start(&config).unwrap();
+        let (client, _) = collect_until(&loopback, |client, _| client.pings() >= 50);
+        loopback.stop();
+        assert!(client.pings() >= 50, "{:?}", client);
+        assert!((0.3..0.7).contains(&client.loss()), "loss {}", client.loss());
+    }
+
+    #[test]
+    fn duplicated_and_reordered_pongs_are_counted_once() {
+        let impairment = ImpairmentConfig { duplicate: 1.0, reorder: 0.5, reorder_delay_ms: 30, seed: 5,
+                                            ..Default::default() };
+        let config = config(TransportKind::Udp, 50, impairment);
+        let start = Instant::now();
+        let (client, _) = run(&config, 20);
+        let max_pings = (start.elapsed().as_millis() / 50 + 1) as usize;
+        assert!(!client.rtts_ms.is_empty());
+        assert!(client.pings() <= max_pings, "{:?}", client);
+        // pongs arriving after the next ping would give round trip times above the ping interval
+        assert!(client.rtts_ms.iter().all(|&rtt| rtt < 50.0), "{:?}", client);
+    }
+
+    #[test]
+    fn late_pongs_are_lost() {
+        let impairment = ImpairmentConfig { delay_ms: 40, ..Default::default() };
+        let config = config(TransportKind::Udp, 50, impairment);
+        let (client, _) = run(&config, 5);
+        assert!(client.rtts_ms.is_empty(), "{:?}", client);
+        assert!(client.lost >= 5, "{:?}", client);
+    }
+
+    #[test]
+    fn client_reconnects_to_running_server() {
+        // over UDP the server notices the new client only with its Connect message, the pings sent to the
+        // old client until then are lost
+        for (transport, max_lost) in [(TransportKind::Tcp, 1), (TransportKind::Udp, 3)] {
+            // both sides ping at the same time, over TCP a pong can wait 40 ms for the delayed acknowledgement
+            // of the ping sent before it as message_io does not disable Nagle's algorithm
+            let config = config(transport, 100, ImpairmentConfig::default());
+            let loopback = Loopback::start(&config).unwrap();
+            let (first, _) = collect_until(&loopback, |client, _| !client.rtts_ms.is_empty());
+            assert!(!first.rtts_ms.is_empty(), "{:?}: {:?}", transport, first);
+
+            loopback.client.disconnect();
+            loopback.connect_client().unwrap();
+            let (client, server) = collect_until(&loopback, |client, server| {
+                client.rtts_ms.len() >= 5 && server.rtts_ms.len() >= 5
+            });
+            loopback.stop();
+
+            assert!(client.rtts_ms.len() >= 5, "{:?}: {:?}", transport, client);
+            assert_eq!(client.lost, 0, "{:?}", transport);
+            assert!(server.rtts_ms.len() >= 5, "{:?}: {:?}", transport, server);
+            assert!(server.lost <= max_lost, "{:?}: {:?}", transport, server);
+        }
+    }
+}
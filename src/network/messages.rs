@@ -1,11 +1,26 @@
 use serde::{Serialize, Deserialize};
+use crate::config::WorkloadConfig;
 
 #[derive(Serialize, Deserialize)]
 pub enum NetworkMessage {
     StartTimer,
     StopTimer,
     ResetTimer,
-    Ping,
-    Pong,
+    // sequence number of the ping, answered with the same number and the clock of the answering instance
+    // (`clock::micros`) for the clock offset
+    Ping(u64),
+    Pong(u64, u64),
     Connect,
+    // label of the scenario phase that starts now
+    Phase(String),
+    // load the remote renders from now on
+    Workload(WorkloadConfig),
+    // flip the color patch on the next input, sent before an input latency measurement
+    ArmInput,
+    // clock of the remote when the input reached its window
+    InputArrived(u64),
+    // fill the color patch with this RGB color
+    Flash([u8; 3]),
+    // refresh rate of the display showing the window of the sending instance in Hz
+    RefreshRate(f64),
 }
\ No newline at end of file
@@ -1,14 +1,21 @@
+use std::net::SocketAddr;
 use std::ops::Deref;
 use std::thread;
 use message_io::network::{NetEvent, Transport, RemoteAddr};
 use message_io::node::{self, NodeEvent, NodeHandler};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 use async_channel::Sender;
+use log::{debug, error, info, warn};
+use message_io::network::Endpoint;
 
 
+use super::clock::{self, ClockOffset};
+use super::impairment::{DelayQueue, Impairment};
 use super::messages::NetworkMessage;
 use crate::UpdateUI;
+use crate::config::{ImpairmentConfig, TransportKind};
 
 #[derive(Copy, Clone)]
 enum ConnectionState {
@@ -27,10 +34,38 @@ pub struct NetworkManager {
     mode: Arc<Mutex<Mode>>,
     handler: Mutex<Option<NodeHandler<Signal>>>,
     last_ping: Mutex<Instant>,
+    // set while a ping has not been answered, a ping still unanswered at the next one is lost
+    awaiting_pong: AtomicBool,
+    // sequence number of the last ping, pongs of older pings are late and ignored
+    ping_sequence: AtomicU64,
+    // set once the pings of the current connection are scheduled
+    pinging: AtomicBool,
+    ping_interval: Duration,
+    // offset of the remote clock, estimated from the pongs of the current connection
+    clock_offset: Mutex<ClockOffset>,
+    impairment: Mutex<Option<Impairment>>,
+    // received messages held back by the impairment, handed to the node as `Signal::Deliver`
+    delayed: Mutex<Option<DelayQueue<DelayedMessage>>>,
+    // address the server listens on, the port is chosen by the system if it was 0
+    listening: Mutex<Option<SocketAddr>>,
 }
 
+type DelayedMessage = (Endpoint, Vec<u8>);
+
 enum Signal {
     Greet,
+    // received message that was held back by the impairment
+    Deliver(Endpoint, Vec<u8>),
+}
+
+impl From<TransportKind> for Transport {
+    fn from(kind: TransportKind) -> Self {
+        match kind {
+            TransportKind::Udp => Transport::Udp,
+            TransportKind::Tcp => Transport::FramedTcp,
+            TransportKind::Ws => Transport::Ws,
+        }
+    }
 }
 
 impl NetworkManager {
@@ -39,30 +74,74 @@ impl NetworkManager {
         let mut_mode = self.mode.lock().unwrap();
         let endpoint = {
             match *mut_mode {
-                Mode::Server(h) => h.expect("No Endpoint"),
-                Mode::Client(h) => h.expect("No Endpoint"),
+                Mode::Server(Some(h)) | Mode::Client(Some(h)) => h,
                 _ => return,
             }
         };
         let mut_handler = self.handler.lock().unwrap();
-        mut_handler.deref().as_ref().unwrap()
-            .network().send(endpoint, &output_data);
+        if let Some(handler) = mut_handler.deref() {
+            handler.network().send(endpoint, &output_data);
+        }
     }
-    pub fn new() -> NetworkManager {
+    pub fn new(ping_interval: Duration, impairment: &ImpairmentConfig) -> NetworkManager {
+        let impairment = Impairment::new(impairment);
+        if impairment.is_some() {
+            warn!("received network messages are impaired");
+        }
+        // starts the clock, timestamps are relative to its first use
+        clock::micros(Instant::now());
         NetworkManager {
             mode: Arc::new(Mutex::from(Mode::Unknown)),
             handler: Mutex::from(None),
             last_ping: Mutex::from(Instant::now()),
+            awaiting_pong: AtomicBool::new(false),
+            ping_sequence: AtomicU64::new(0),
+            pinging: AtomicBool::new(false),
+            ping_interval,
+            clock_offset: Mutex::new(ClockOffset::default()),
+            impairment: Mutex::new(impairment),
+            delayed: Mutex::new(None),
+            listening: Mutex::new(None),
+        }
+    }
+
+    // Stops the connection or the server, connect can be called again afterwards.
+    pub fn disconnect(&self) {
+        if let Some(handler) = self.handler.lock().unwrap().take() {
+            handler.stop();
         }
+        *self.mode.lock().unwrap() = Mode::Unknown;
+        self.awaiting_pong.store(false, Ordering::Relaxed);
+        self.pinging.store(false, Ordering::Relaxed);
+        self.clock_offset.lock().unwrap().clear();
+        *self.listening.lock().unwrap() = None;
+    }
+
+    // Address the server listens on, None for clients and if listening failed.
+    pub fn listening_address(&self) -> Option<SocketAddr> {
+        *self.listening.lock().unwrap()
+    }
+
+    // Local clock (`clock::micros`) at remote clock `remote`, None before the first answered ping.
+    pub fn to_local_clock(&self, remote: u64) -> Option<u64> {
+        self.clock_offset.lock().unwrap().to_local(remote)
     }
 
     pub fn connect(self: Arc<Self>, is_client: bool, transport: Transport, remote_addr: RemoteAddr,
                    sender_ui_channel: Arc<Sender<UpdateUI>>) -> () {
+        // a previous connection is replaced
+        self.disconnect();
         let (handler, listener) = node::split();
         {
             let mut mut_handler = self.handler.lock().unwrap();
             *mut_handler = Some(handler.clone());
         }
+        if self.impairment.lock().unwrap().is_some() {
+            let handler = handler.clone();
+            *self.delayed.lock().unwrap() = Some(DelayQueue::start(move |(endpoint, data)| {
+                handler.signals().send(Signal::Deliver(endpoint, data));
+            }));
+        }
         if is_client {
             let (server_id, _) =
                 handler.network().connect(transport, remote_addr.clone()).unwrap();
@@ -72,10 +151,12 @@ impl NetworkManager {
             }
         } else {
             match handler.network().listen(transport, remote_addr.clone()) {
-                Ok((_id, real_addr)) => println!("Server running at {} by {}",
-                                                 real_addr, transport),
+                Ok((_id, real_addr)) => {
+                    info!(address:% = real_addr, transport:% = transport; "server running");
+                    *self.listening.lock().unwrap() = Some(real_addr);
+                }
                 Err(_) => {
-                    println!("Can not listening at {} by {}", remote_addr, transport);
+                    error!(address:% = remote_addr, transport:% = transport; "can not listen");
                     return;
                 }
             }
@@ -84,65 +165,69 @@ impl NetworkManager {
                 *mut_mode = Mode::Server(None)
             }
         }
-        let mode = self.mode.clone();
         let _ = thread::spawn({
             let handler = handler.clone();
             move || {
                 listener.for_each(move |event| {
                     match event {
                         NodeEvent::Network(net_event) => match net_event {
-                            NetEvent::Connected(_, _) => {
-                                if matches!(mode.deref().lock().unwrap().deref(), Mode::Client(_)) {
-                                    {
-                                        handler.signals().send(Signal::Greet);
-                                        self.send(NetworkMessage::Connect);
-                                    }
+                            NetEvent::Connected(_, established) => {
+                                if !established {
+                                    error!(address:% = remote_addr; "can not connect");
+                                } else if matches!(self.mode.lock().unwrap().deref(), Mode::Client(_)) {
+                                    self.start_pinging(&handler);
+                                    self.send(NetworkMessage::Connect);
                                 }
                             }
                             NetEvent::Accepted(_, _) => {}
                             NetEvent::Message(e, input_data) => {
-                                let message: NetworkMessage = bincode::deserialize(&input_data).unwrap();
-
-                                match message {
-                                    NetworkMessage::StartTimer => {
-                                        sender_ui_channel.deref().send_blocking(UpdateUI::StartTimer(
-                                            std::time::Instant::now())).unwrap();
-                                    }
-                                    NetworkMessage::Connect => {
-                                        if matches!(mode.deref().lock().unwrap().deref(), Mode::Server(_)) {
-                                            {
-                                                let mut mut_mode = mode.lock().unwrap();
-                                                *mut_mode = Mode::Server(Some(e));
-                                                handler.signals().send(Signal::Greet);
-                                            }
-                                        }
-                                    }
-                                    NetworkMessage::Ping => {
-                                        let output_data = bincode::serialize(&NetworkMessage::Pong)
-                                            .unwrap();
-                                        handler.network().send(e, &output_data);
+                                let delays = match self.impairment.lock().unwrap().as_mut() {
+                                    Some(impairment) => impairment.deliveries(),
+                                    None => vec![Duration::ZERO],
+                                };
+                                for delay in delays {
+                                    if delay.is_zero() {
+                                        self.handle_message(e, input_data, &handler, &sender_ui_channel);
+                                    } else if let Some(delayed) = self.delayed.lock().unwrap().as_ref() {
+                                        delayed.push(delay, (e, input_data.to_vec()));
                                     }
-                                    NetworkMessage::Pong => {
-                                        sender_ui_channel.deref().send_blocking(UpdateUI::Ping(self.last_ping.lock().unwrap().elapsed())).unwrap();
+                                }
+                            }
+                            NetEvent::Disconnected(e) => {
+                                let mut mode = self.mode.lock().unwrap();
+                                match *mode {
+                                    // the server keeps listening for the next client
+                                    Mode::Server(Some(client)) if client == e => {
+                                        info!("client is disconnected");
+                                        *mode = Mode::Server(None);
                                     }
-                                    NetworkMessage::ResetTimer => {
-                                        sender_ui_channel.deref().send_blocking(UpdateUI::ResetTimer).unwrap()
+                                    Mode::Server(_) => {}
+                                    _ => {
+                                        info!("server is disconnected");
+                                        handler.stop();
                                     }
-                                    _ => {}
                                 }
                             }
-                            NetEvent::Disconnected(_) => {
-                                println!("Server is disconnected");
-                                handler.stop();
-                            }
                         },
                         NodeEvent::Signal(signal) => match signal {
                             Signal::Greet => {
-                                let message = NetworkMessage::Ping;
-                                self.send(message);
-                                *self.last_ping.lock().unwrap() = Instant::now();
+                                let connected = matches!(self.mode.lock().unwrap().deref(),
+                                                         Mode::Server(Some(_)) | Mode::Client(Some(_)));
+                                if !connected {
+                                    self.awaiting_pong.store(false, Ordering::Relaxed);
+                                } else {
+                                    if self.awaiting_pong.swap(true, Ordering::Relaxed) {
+                                        let _ = sender_ui_channel.send_blocking(UpdateUI::PingLost);
+                                    }
+                                    let sequence = self.ping_sequence.fetch_add(1, Ordering::Relaxed) + 1;
+                                    self.send(NetworkMessage::Ping(sequence));
+                                    *self.last_ping.lock().unwrap() = Instant::now();
+                                }
 
-                                handler.signals().send_with_timer(Signal::Greet, Duration::from_secs(1));
+                                handler.signals().send_with_timer(Signal::Greet, self.ping_interval);
+                            }
+                            Signal::Deliver(e, input_data) => {
+                                self.handle_message(e, &input_data, &handler, &sender_ui_channel);
                             }
                         },
                     }
@@ -150,4 +235,72 @@ impl NetworkManager {
             }
         });
     }
-}
\ No newline at end of file
+
+    // Starts sending pings, once per connection.
+    fn start_pinging(&self, handler: &NodeHandler<Signal>) {
+        if !self.pinging.swap(true, Ordering::Relaxed) {
+            handler.signals().send(Signal::Greet);
+        }
+    }
+
+    fn handle_message(&self, e: Endpoint, input_data: &[u8], handler: &NodeHandler<Signal>,
+                      sender_ui_channel: &Sender<UpdateUI>) {
+        let message: NetworkMessage = match bincode::deserialize(input_data) {
+            Ok(message) => message,
+            Err(e) => {
+                error!(error:% = e; "invalid message");
+                return;
+            }
+        };
+
+        match message {
+            NetworkMessage::StartTimer => {
+                sender_ui_channel.send_blocking(UpdateUI::StartTimer(
+                    std::time::Instant::now())).unwrap();
+            }
+            NetworkMessage::Connect => {
+                let mut mode = self.mode.lock().unwrap();
+                if matches!(mode.deref(), Mode::Server(_)) {
+                    *mode = Mode::Server(Some(e));
+                    drop(mode);
+                    self.start_pinging(handler);
+                }
+            }
+            NetworkMessage::Ping(sequence) => {
+                let output_data = bincode::serialize(&NetworkMessage::Pong(sequence, clock::micros(Instant::now())))
+                    .unwrap();
+                handler.network().send(e, &output_data);
+            }
+            NetworkMessage::Pong(sequence, remote_clock) => {
+                // duplicated pongs and pongs arriving after the next ping was sent are not counted
+                if sequence == self.ping_sequence.load(Ordering::Relaxed)
+                    && self.awaiting_pong.swap(false, Ordering::Relaxed) {
+                    let sent = *self.last_ping.lock().unwrap();
+                    self.clock_offset.lock().unwrap().add(clock::micros(sent), clock::micros(Instant::now()),
+                                                          remote_clock);
+                    sender_ui_channel.send_blocking(UpdateUI::Ping(sent.elapsed())).unwrap();
+                } else {
+                    debug!(sequence = sequence; "ignoring late or duplicated pong");
+                }
+            }
+            NetworkMessage::ResetTimer => {
+                sender_ui_channel.send_blocking(UpdateUI::ResetTimer).unwrap()
+            }
+            NetworkMessage::Phase(label) => {
+                sender_ui_channel.send_blocking(UpdateUI::Phase(label)).unwrap()
+            }
+            NetworkMessage::Workload(workload) => {
+                sender_ui_channel.send_blocking(UpdateUI::Workload(workload)).unwrap()
+            }
+            NetworkMessage::ArmInput => sender_ui_channel.send_blocking(UpdateUI::ArmInput).unwrap(),
+            NetworkMessage::Flash(color) => sender_ui_channel.send_blocking(UpdateUI::Flash(color)).unwrap(),
+            NetworkMessage::RefreshRate(hz) => {
+                sender_ui_channel.send_blocking(UpdateUI::RemoteRefreshRate(hz)).unwrap()
+            }
+            NetworkMessage::InputArrived(remote_clock) => {
+                sender_ui_channel.send_blocking(UpdateUI::InputArrived(remote_clock)).unwrap()
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,401 @@
+// Self-contained HTML report of a session with statistics, SVG charts and embedded thumbnails
+// of annotated debug screenshots.
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use chrono::DateTime;
+use image::ImageFormat;
+use log::warn;
+use crate::config::MeasureMode;
+use crate::results::Measurement;
+use crate::stats::{refresh_period_ms, Summary, VsyncFold};
+
+const CHART_WIDTH: f64 = 720.0;
+const CHART_HEIGHT: f64 = 240.0;
+const CHART_MARGIN: f64 = 40.0;
+const THUMBNAIL_WIDTH: u32 = 480;
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}table{border-collapse:collapse;margin-bottom:1em}\
+td,th{border:1px solid #ccc;padding:4px 10px;text-align:right}th{background:#eee}td:first-child,th:first-child{text-align:left}\
+svg{display:block;margin-bottom:1em;background:#fafafa;border:1px solid #ddd}img{margin:4px;border:1px solid #ddd}";
+
+pub struct SessionReport<'a> {
+    // label and value pairs shown at the top, e.g. profile, transport and versions
+    pub metadata: Vec<(&'static str, String)>,
+    pub measurements: &'a [Measurement],
+    pub pings: &'a [f64],
+    pub pings_lost: u64,
+    // annotated screenshots embedded as thumbnails
+    pub screenshots: Vec<PathBuf>,
+}
+
+impl SessionReport<'_> {
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.render())?;
+        Ok(())
+    }
+
+    pub fn render(&self) -> String {
+        let mut html = String::new();
+        let _ = write!(html, "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Latency report</title>\
+                              <style>{}</style></head><body>\n<h1>Latency report</h1>\n", STYLE);
+
+        html.push_str("<h2>Session</h2>\n<table>\n");
+        for (label, value) in &self.metadata {
+            let _ = writeln!(html, "<tr><th>{}</th><td>{}</td></tr>", label, escape(value));
+        }
+        html.push_str("</table>\n");
+
+        for mode in MeasureMode::ALL {
+            let (measurements, excluded): (Vec<Measurement>, Vec<Measurement>) = self.measurements.iter()
+                .filter(|m| m.mode == mode).cloned()
+                .partition(|m| !m.excluded);
+            // input latencies and flash delays are only shown if there are some
+            if measurements.is_empty() && excluded.is_empty() && mode != MeasureMode::Display {
+                continue;
+            }
+            html.push_str(match mode {
+                MeasureMode::Display => "<h2>Delay</h2>\n",
+                MeasureMode::Input => "<h2>Input latency</h2>\n",
+                MeasureMode::Flash => "<h2>Color flash delay</h2>\n",
+            });
+            html.push_str(&summary_table(&measurements));
+            let uncertainties: Vec<f64> = measurements.iter().filter_map(|m| m.uncertainty_ms).collect();
+            if let Some(s) = Summary::from_samples(&uncertainties) {
+                let _ = writeln!(html, "<p>Uncertainty of a delay: median ±{:.1} ms, max ±{:.1} ms</p>", s.median,
+                                 s.max);
+            }
+            if !excluded.is_empty() {
+                let _ = writeln!(html, "<p>{} measurements below the quality limits are not included.</p>",
+                                 excluded.len());
+            }
+            if mode == MeasureMode::Input {
+                html.push_str(&leg_table(&measurements));
+            }
+            html.push_str(&vsync_table(&measurements));
+            let delays: Vec<f64> = measurements.iter().map(|m| m.delay_ms).collect();
+            if !delays.is_empty() {
+                html.push_str(&delay_chart(&measurements));
+                html.push_str(&histogram_chart(&delays));
+            }
+        }
+
+        html.push_str("<h2>Ping</h2>\n<table>\n<tr><th>Pings</th><th>Lost</th><th>Min</th><th>Median</th>\
+                       <th>Max</th><th>Jitter</th></tr>\n");
+        let total = self.pings.len() as u64 + self.pings_lost;
+        let _ = write!(html, "<tr><td>{}</td><td>{} ({:.1} %)</td>", total, self.pings_lost,
+                       if total == 0 { 0.0 } else { self.pings_lost as f64 * 100.0 / total as f64 });
+        match Summary::from_samples(self.pings) {
+            Some(s) => {
+                let _ = writeln!(html, "<td>{:.1} ms</td><td>{:.1} ms</td><td>{:.1} ms</td><td>{:.1} ms</td></tr>",
+                                 s.min, s.median, s.max, jitter(self.pings));
+            }
+            None => html.push_str("<td>-</td><td>-</td><td>-</td><td>-</td></tr>\n"),
+        }
+        html.push_str("</table>\n");
+
+        if !self.screenshots.is_empty() {
+            html.push_str("<h2>Screenshots</h2>\n");
+            for path in &self.screenshots {
+                match thumbnail(path) {
+                    Ok(data) => {
+                        let _ = writeln!(html, "<img src=\"data:image/jpeg;base64,{}\" title=\"{}\">", base64(&data),
+                                         escape(&path.display().to_string()));
+                    }
+                    Err(e) => warn!(path:? = path, error:% = e; "error embedding screenshot"),
+                }
+            }
+        }
+        html.push_str("</body></html>\n");
+        html
+    }
+}
+
+// One row for all measurements and one per profile and phase if there are several.
+fn summary_table(measurements: &[Measurement]) -> String {
+    let mut groups: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for m in measurements {
+        let name = [m.profile.as_str(), m.phase.as_str()].iter()
+            .filter(|s| !s.is_empty()).copied().collect::<Vec<_>>().join(" / ");
+        groups.entry(name).or_default().push(m.delay_ms);
+    }
+    let mut rows = vec![("all".to_string(), measurements.iter().map(|m| m.delay_ms).collect::<Vec<_>>())];
+    if groups.len() > 1 {
+        rows.extend(groups);
+    }
+    let mut table = String::from("<table>\n<tr><th></th><th>Samples</th><th>Min</th><th>Median</th><th>Mean</th>\
+                                  <th>P95</th><th>Max</th><th>Std dev</th></tr>\n");
+    for (name, delays) in rows {
+        let _ = write!(table, "<tr><td>{}</td>", escape(if name.is_empty() { "-" } else { &name }));
+        match Summary::from_samples(&delays) {
+            Some(s) => {
+                let _ = writeln!(table, "<td>{}</td><td>{:.1} ms</td><td>{:.1} ms</td><td>{:.1} ms</td>\
+                                         <td>{:.1} ms</td><td>{:.1} ms</td><td>{:.1} ms</td></tr>",
+                                 s.count, s.min, s.median, s.mean, s.p95, s.max, s.stddev);
+            }
+            None => table.push_str("<td>0</td><td colspan=\"6\">no measurements</td></tr>\n"),
+        }
+    }
+    table.push_str("</table>\n");
+    table
+}
+
+// Input latencies split at the arrival of the input in the remote window, empty if none were split.
+fn leg_table(measurements: &[Measurement]) -> String {
+    let (input, display): (Vec<f64>, Vec<f64>) = measurements.iter()
+        .filter_map(|m| m.arrival_ms.map(|arrival| (arrival, m.delay_ms - arrival)))
+        .unzip();
+    let (Some(input), Some(display)) = (Summary::from_samples(&input), Summary::from_samples(&display)) else {
+        return String::new();
+    };
+    let mut table = String::from("<table>\n<tr><th></th><th>Samples</th><th>Min</th><th>Median</th><th>Mean</th>\
+                                  <th>P95</th><th>Max</th></tr>\n");
+    for (name, s) in [("input to remote", input), ("remote to screen", display)] {
+        let _ = writeln!(table, "<tr><td>{}</td><td>{}</td><td>{:.1} ms</td><td>{:.1} ms</td><td>{:.1} ms</td>\
+                                 <td>{:.1} ms</td><td>{:.1} ms</td></tr>",
+                         name, s.count, s.min, s.median, s.mean, s.p95, s.max);
+    }
+    table.push_str("</table>\n");
+    table
+}
+
+// Delays folded modulo the refresh period of the local and the remote display, empty if neither is known.
+fn vsync_table(measurements: &[Measurement]) -> String {
+    let delays: Vec<f64> = measurements.iter().map(|m| m.delay_ms).collect();
+    let fold = |refresh_hz: fn(&Measurement) -> Option<f64>| {
+        VsyncFold::from_samples(&delays, refresh_period_ms(measurements.iter().filter_map(refresh_hz))?)
+    };
+    let folds: Vec<(&str, VsyncFold)> = [("local display", fold(|m| m.refresh_hz)),
+                                         ("remote display", fold(|m| m.remote_refresh_hz))]
+        .into_iter()
+        .filter_map(|(name, fold)| fold.map(|f| (name, f)))
+        .collect();
+    if folds.is_empty() {
+        return String::new();
+    }
+    let mut table = String::from("<p>Delays folded modulo the refresh period: without synchronization the wait \
+                                  for the refresh adds half a period on average, the rest is the pipeline. A \
+                                  concentration near 1 means the delays are locked to the refresh.</p>\n<table>\n\
+                                  <tr><th></th><th>Refresh</th><th>Phases</th><th>Mean phase</th>\
+                                  <th>Concentration</th><th>Refresh wait</th><th>Pipeline</th></tr>\n");
+    for (name, f) in folds {
+        let phases = f.phases.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(" ");
+        let _ = writeln!(table, "<tr><td>{}</td><td>{:.2} Hz</td><td>{}</td><td>{:.1} ms</td><td>{:.2}</td>\
+                                 <td>{:.1} ms ± {:.1} ms</td><td>{:.1} ms ± {:.1} ms</td></tr>",
+                         name, 1000.0 / f.period_ms, phases, f.mean_phase_ms, f.concentration, f.quantization_ms,
+                         f.quantization_stddev_ms, f.pipeline_ms, f.pipeline_stddev_ms);
+    }
+    table.push_str("</table>\n");
+    table
+}
+
+// Delay over the seconds since the first measurement.
+fn delay_chart(measurements: &[Measurement]) -> String {
+    let times: Vec<f64> = measurements.iter()
+        .map(|m| DateTime::parse_from_rfc3339(&m.timestamp).map(|t| t.timestamp_millis() as f64 / 1000.0)
+            .unwrap_or(f64::NAN))
+        .collect();
+    let start = times.iter().copied().fold(f64::INFINITY, f64::min);
+    let points: Vec<(f64, f64)> = times.iter().zip(measurements)
+        .map(|(t, m)| (if t.is_finite() { t - start } else { 0.0 }, m.delay_ms))
+        .collect();
+    let max_x = points.iter().map(|p| p.0).fold(1.0, f64::max);
+    let max_y = points.iter().map(|p| p.1).fold(1.0, f64::max) * 1.1;
+    let mut svg = chart_frame("Delay over time", "s", max_x, "ms", max_y);
+    let coordinates: Vec<String> = points.iter()
+        .map(|&(x, y)| format!("{:.1},{:.1}", scale_x(x, max_x), scale_y(y, max_y)))
+        .collect();
+    let _ = writeln!(svg, "<polyline fill=\"none\" stroke=\"#36c\" stroke-width=\"1.5\" points=\"{}\"/>",
+                     coordinates.join(" "));
+    for c in &coordinates {
+        let (x, y) = c.split_once(',').unwrap();
+        let _ = writeln!(svg, "<circle cx=\"{}\" cy=\"{}\" r=\"2.5\" fill=\"#36c\"/>", x, y);
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn histogram_chart(delays: &[f64]) -> String {
+    let min = delays.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = delays.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let bins = ((delays.len() as f64).sqrt().ceil() as usize).clamp(5, 30);
+    let width = ((max - min) / bins as f64).max(0.1);
+    let mut counts = vec![0usize; bins];
+    for d in delays {
+        counts[(((d - min) / width) as usize).min(bins - 1)] += 1;
+    }
+    let max_count = *counts.iter().max().unwrap_or(&1) as f64;
+    let mut svg = chart_frame("Histogram", "ms", 0.0, "count", max_count);
+    let bar_width = (CHART_WIDTH - 2.0 * CHART_MARGIN) / bins as f64;
+    for (i, count) in counts.iter().enumerate() {
+        let x = CHART_MARGIN + i as f64 * bar_width;
+        let y = scale_y(*count as f64, max_count);
+        let _ = writeln!(svg, "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"#36c\">\
+                               <title>{:.1} - {:.1} ms: {}</title></rect>",
+                         x + 1.0, y, bar_width - 2.0, CHART_HEIGHT - CHART_MARGIN - y,
+                         min + i as f64 * width, min + (i + 1) as f64 * width, count);
+    }
+    let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" font-size=\"11\">{:.1}</text>", CHART_MARGIN,
+                     CHART_HEIGHT - CHART_MARGIN + 14.0, min);
+    let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" font-size=\"11\" text-anchor=\"end\">{:.1}</text>",
+                     CHART_WIDTH - CHART_MARGIN, CHART_HEIGHT - CHART_MARGIN + 14.0, min + bins as f64 * width);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// Opening svg tag with title and axes, the x axis is only labeled if `max_x` is not 0.
+fn chart_frame(title: &str, x_unit: &str, max_x: f64, y_unit: &str, max_y: f64) -> String {
+    let bottom = CHART_HEIGHT - CHART_MARGIN;
+    let right = CHART_WIDTH - CHART_MARGIN;
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+                           viewBox=\"0 0 {w} {h}\">\n", w = CHART_WIDTH, h = CHART_HEIGHT);
+    let _ = writeln!(svg, "<text x=\"{}\" y=\"20\" font-size=\"14\" text-anchor=\"middle\">{}</text>",
+                     CHART_WIDTH / 2.0, title);
+    let _ = writeln!(svg, "<path d=\"M{m} {t} V{b} H{r}\" stroke=\"#444\" fill=\"none\"/>",
+                     m = CHART_MARGIN, t = CHART_MARGIN / 2.0, b = bottom, r = right);
+    let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" font-size=\"11\" text-anchor=\"end\">{:.0} {}</text>",
+                     CHART_MARGIN - 4.0, CHART_MARGIN, max_y, y_unit);
+    let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" font-size=\"11\" text-anchor=\"end\">0</text>",
+                     CHART_MARGIN - 4.0, bottom);
+    if max_x > 0.0 {
+        let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" font-size=\"11\" text-anchor=\"end\">{:.0} {}</text>",
+                         right, bottom + 14.0, max_x, x_unit);
+    } else {
+        let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" font-size=\"11\" text-anchor=\"middle\">{}</text>",
+                         CHART_WIDTH / 2.0, bottom + 14.0, x_unit);
+    }
+    svg
+}
+
+fn scale_x(x: f64, max_x: f64) -> f64 {
+    CHART_MARGIN + x / max_x * (CHART_WIDTH - 2.0 * CHART_MARGIN)
+}
+
+fn scale_y(y: f64, max_y: f64) -> f64 {
+    CHART_HEIGHT - CHART_MARGIN - y / max_y * (CHART_HEIGHT - 1.5 * CHART_MARGIN)
+}
+
+// Mean absolute difference of consecutive round trip times.
+fn jitter(pings: &[f64]) -> f64 {
+    if pings.len() < 2 {
+        return 0.0;
+    }
+    pings.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f64>() / (pings.len() - 1) as f64
+}
+
+fn thumbnail(path: &Path) -> Result<Vec<u8>, image::ImageError> {
+    let image = image::open(path)?;
+    let height = image.height() * THUMBNAIL_WIDTH / image.width().max(1);
+    let thumbnail = image.thumbnail(THUMBNAIL_WIDTH, height.max(1)).into_rgb8();
+    let mut data = Cursor::new(vec![]);
+    thumbnail.write_to(&mut data, ImageFormat::Jpeg)?;
+    Ok(data.into_inner())
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    fn measurement(delay_ms: u64, profile: &str, phase: &str) -> Measurement {
+        Measurement::new(MeasureMode::Display, Duration::from_millis(delay_ms), profile, phase)
+    }
+
+    fn report(measurements: &[Measurement]) -> SessionReport<'_> {
+        SessionReport {
+            metadata: vec![("Profile", "lan".to_string())],
+            measurements,
+            pings: &[],
+            pings_lost: 0,
+            screenshots: vec![],
+        }
+    }
+
+    #[test]
+    fn base64_matches_rfc_4648() {
+        for (data, encoded) in [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foob", "Zm9vYg=="),
+                                ("fooba", "Zm9vYmE="), ("foobar", "Zm9vYmFy")] {
+            assert_eq!(base64(data.as_bytes()), encoded);
+        }
+    }
+
+    #[test]
+    fn excluded_measurements_are_counted_but_not_shown() {
+        let mut measurements = vec![measurement(80, "", ""), measurement(90, "", ""), measurement(500, "", "")];
+        measurements[2].excluded = true;
+        let html = report(&measurements).render();
+        assert!(html.contains("<tr><td>all</td><td>2</td><td>80.0 ms</td>"));
+        assert!(html.contains("<p>1 measurements below the quality limits are not included.</p>"));
+        assert!(!html.contains("500.0"));
+        assert!(html.ends_with("</body></html>\n"));
+    }
+
+    #[test]
+    fn only_excluded_measurements() {
+        let mut measurements = vec![measurement(80, "", "")];
+        measurements[0].excluded = true;
+        let html = report(&measurements).render();
+        assert!(html.contains("<td>0</td><td colspan=\"6\">no measurements</td>"));
+        assert!(!html.contains("Histogram"));
+    }
+
+    #[test]
+    fn single_measurement() {
+        let measurements = [measurement(42, "", "")];
+        let html = report(&measurements).render();
+        assert!(html.contains("<tr><td>all</td><td>1</td><td>42.0 ms</td><td>42.0 ms</td><td>42.0 ms</td>"));
+        assert!(html.contains("Delay over time"));
+        // all bins have the minimal width, the one sample is in the first
+        assert!(html.contains("<title>42.0 - 42.1 ms: 1</title>"));
+        assert!(!html.contains("NaN"));
+    }
+
+    #[test]
+    fn group_names_are_escaped() {
+        let measurements = [measurement(80, "<lan>", "a & b"), measurement(90, "wifi \"5 GHz\"", "")];
+        let table = summary_table(&measurements);
+        assert!(table.contains("<tr><td>&lt;lan&gt; / a &amp; b</td><td>1</td>"));
+        assert!(table.contains("<tr><td>wifi &quot;5 GHz&quot;</td><td>1</td>"));
+        assert!(!table.contains("<lan>"));
+    }
+
+    #[test]
+    fn histogram_puts_the_maximum_in_the_last_bin() {
+        let svg = histogram_chart(&[10.0, 10.0, 20.0]);
+        assert!(svg.contains("<title>10.0 - 12.0 ms: 2</title>"));
+        assert!(svg.contains("<title>18.0 - 20.0 ms: 1</title>"));
+        assert_eq!(svg.matches("<rect").count(), 5);
+    }
+
+    #[test]
+    fn jitter_of_round_trip_times() {
+        assert_eq!(jitter(&[]), 0.0);
+        assert_eq!(jitter(&[5.0]), 0.0);
+        assert_eq!(jitter(&[10.0, 14.0, 12.0]), 3.0);
+    }
+}
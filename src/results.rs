@@ -0,0 +1,156 @@
+// Measurement records of a session, written to a CSV and a JSON lines file as they arrive.
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::Local;
+use log::error;
+use serde::{Deserialize, Serialize};
+use crate::config::MeasureMode;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Measurement {
+    // RFC 3339 timestamp of the moment the delay was measured
+    pub timestamp: String,
+    // profile the measurement was taken with, empty if none
+    pub profile: String,
+    // scenario phase the measurement was taken in, empty outside of scenarios
+    #[serde(default)]
+    pub phase: String,
+    // display delay or input latency, results of older versions only have display delays
+    #[serde(default)]
+    pub mode: MeasureMode,
+    pub delay_ms: f64,
+    // input latencies: time until the input reached the remote window, the rest of `delay_ms` is the way
+    // back to the screen. None for display delays and if the remote clock was not known
+    #[serde(default)]
+    pub arrival_ms: Option<f64>,
+    // display delays: estimated error from the timer tick, the refresh of the captured monitors and the time
+    // between capturing different monitors
+    #[serde(default)]
+    pub uncertainty_ms: Option<f64>,
+    // refresh rates of the local monitor showing the remote window and of the display of the remote instance,
+    // None if they are not known
+    #[serde(default)]
+    pub refresh_hz: Option<f64>,
+    #[serde(default)]
+    pub remote_refresh_hz: Option<f64>,
+    // display delays: geometry and refresh rate of the monitors the local and the remote timer were found on
+    #[serde(default)]
+    pub local_monitor: Option<String>,
+    #[serde(default)]
+    pub remote_monitor: Option<String>,
+    // display delays: lower match confidence of the two markers and lower OCR confidence of the two timers
+    #[serde(default)]
+    pub match_confidence: Option<f32>,
+    #[serde(default)]
+    pub ocr_confidence: Option<f32>,
+    // display delays: interval the timers were updated in
+    #[serde(default)]
+    pub tick_ms: Option<u64>,
+    // the remote timer showed the same value as in the previous measurement
+    #[serde(default)]
+    pub stale: bool,
+    // below the limits of the `[quality]` section, recorded but left out of the statistics
+    #[serde(default)]
+    pub excluded: bool,
+}
+
+impl Measurement {
+    pub fn new(mode: MeasureMode, delay: Duration, profile: &str, phase: &str) -> Measurement {
+        Measurement {
+            timestamp: Local::now().to_rfc3339(),
+            profile: profile.to_string(),
+            phase: phase.to_string(),
+            mode,
+            delay_ms: delay.as_secs_f64() * 1000.0,
+            arrival_ms: None,
+            uncertainty_ms: None,
+            refresh_hz: None,
+            remote_refresh_hz: None,
+            local_monitor: None,
+            remote_monitor: None,
+            match_confidence: None,
+            ocr_confidence: None,
+            tick_ms: None,
+            stale: false,
+            excluded: false,
+        }
+    }
+}
+
+pub struct ResultWriter {
+    // path of the files without extension
+    base: PathBuf,
+    csv: csv::Writer<File>,
+    json: File,
+}
+
+impl ResultWriter {
+    // Creates `<dir>/results_<timestamp>.csv` and `.jsonl` for a new session.
+    pub fn create(dir: &Path) -> Result<ResultWriter, Box<dyn std::error::Error>> {
+        fs::create_dir_all(dir)?;
+        let base = dir.join(format!("results_{}", Local::now().format("%Y%m%d-%H%M%S")));
+        Ok(ResultWriter {
+            csv: csv::Writer::from_path(base.with_extension("csv"))?,
+            json: File::create(base.with_extension("jsonl"))?,
+            base,
+        })
+    }
+
+    // Path next to the CSV and JSON files with the given extension.
+    pub fn sibling(&self, extension: &str) -> PathBuf {
+        self.base.with_extension(extension)
+    }
+
+    pub fn write(&mut self, measurement: &Measurement) -> Result<(), Box<dyn std::error::Error>> {
+        self.csv.serialize(measurement)?;
+        self.csv.flush()?;
+        writeln!(self.json, "{}", serde_json::to_string(measurement)?)?;
+        Ok(())
+    }
+}
+
+// Results of the running session, exported as they arrive and kept for the report.
+#[derive(Default)]
+pub struct SessionResults {
+    writer: Option<ResultWriter>,
+    pub measurements: Vec<Measurement>,
+    // round trip times in milliseconds
+    pub pings: Vec<f64>,
+    pub pings_lost: u64,
+}
+
+impl SessionResults {
+    // Writes the measurement to the session files in `dir`, which are created with the first one.
+    pub fn record(&mut self, measurement: Measurement, dir: &Path) {
+        if self.writer.is_none() {
+            match ResultWriter::create(dir) {
+                Ok(writer) => self.writer = Some(writer),
+                Err(e) => error!(error:% = e; "error creating result files"),
+            }
+        }
+        if let Some(Err(e)) = self.writer.as_mut().map(|w| w.write(&measurement)) {
+            error!(error:% = e; "error writing result");
+        }
+        self.measurements.push(measurement);
+    }
+
+    // Report path next to the exported files, none before the first measurement.
+    pub fn report_path(&self) -> Option<PathBuf> {
+        self.writer.as_ref().map(|w| w.sibling("html"))
+    }
+}
+
+// Reads the measurements of an exported `.csv` or `.jsonl` file.
+pub fn read_results(path: &Path) -> Result<Vec<Measurement>, Box<dyn std::error::Error>> {
+    if path.extension().is_some_and(|e| e == "csv") {
+        let mut reader = csv::Reader::from_path(path)?;
+        Ok(reader.deserialize().collect::<Result<_, _>>()?)
+    } else {
+        fs::read_to_string(path)?.lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| Ok(serde_json::from_str(l)?))
+            .collect()
+    }
+}
@@ -0,0 +1,139 @@
+// Scripted scenarios: a list of phases that are measured one after another, e.g. idle, video
+// playback and typing, with statistics for every phase.
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use async_channel::{Receiver, Sender};
+use chrono::Local;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use crate::network::messages::NetworkMessage;
+use crate::network::networkmanager::NetworkManager;
+use crate::config::{WorkloadConfig, WorkloadKind};
+use crate::stats::Summary;
+use crate::UpdateUI;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Scenario {
+    pub name: String,
+    // pause between two measurements, can be overridden per phase
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    pub phases: Vec<Phase>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Phase {
+    pub label: String,
+    // number of measurements, 0 to measure until the duration is over
+    #[serde(default)]
+    pub samples: usize,
+    // maximum length of the phase in seconds, 0 for no limit
+    #[serde(default)]
+    pub duration_s: u64,
+    pub interval_ms: Option<u64>,
+    // load the remote renders during the phase, `workload.kind` of the config if not set
+    pub workload: Option<WorkloadKind>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PhaseResult {
+    pub label: String,
+    pub workload: WorkloadKind,
+    pub failures: usize,
+    // statistics of the measured delays in milliseconds, none if every measurement failed
+    pub delay_ms: Option<Summary>,
+}
+
+fn default_interval_ms() -> u64 {
+    1000
+}
+
+pub fn load_scenario(path: &Path) -> Result<Scenario, Box<dyn std::error::Error>> {
+    let scenario: Scenario = toml::from_str(&fs::read_to_string(path)?)?;
+    scenario.validate()?;
+    Ok(scenario)
+}
+
+impl Scenario {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.phases.is_empty() {
+            return Err(format!("scenario {:?} has no phases", self.name));
+        }
+        for phase in &self.phases {
+            if phase.samples == 0 && phase.duration_s == 0 {
+                return Err(format!("phase {:?} needs samples or duration_s", phase.label));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Runs the scenario on its own thread. Every measurement is requested with `UpdateUI::Measure`
+// and its result is expected on `results`; the scenario stops early once that channel is closed.
+// The remote gets `workload` back once the scenario is over.
+pub fn run_scenario(scenario: Scenario, sender: Arc<Sender<UpdateUI>>, results: Receiver<Option<Duration>>,
+                    network: Arc<NetworkManager>, workload: WorkloadConfig) {
+    thread::spawn(move || {
+        info!(scenario = scenario.name.as_str(), phases = scenario.phases.len(); "scenario started");
+        let mut phase_results = vec![];
+        for phase in &scenario.phases {
+            let kind = phase.workload.unwrap_or(workload.kind);
+            network.send(NetworkMessage::Workload(WorkloadConfig { kind, ..workload }));
+            network.send(NetworkMessage::Phase(phase.label.clone()));
+            let _ = sender.send_blocking(UpdateUI::Phase(phase.label.clone()));
+            info!(phase = phase.label.as_str(); "phase started");
+            let interval = Duration::from_millis(phase.interval_ms.unwrap_or(scenario.interval_ms));
+            let start = Instant::now();
+            let mut delays = vec![];
+            let mut failures = 0;
+            let mut cancelled = false;
+            while (phase.samples == 0 || delays.len() + failures < phase.samples)
+                && (phase.duration_s == 0 || start.elapsed() < Duration::from_secs(phase.duration_s)) {
+                if sender.send_blocking(UpdateUI::Measure).is_err() {
+                    cancelled = true;
+                    break;
+                }
+                match results.recv_blocking() {
+                    Ok(Some(delay)) => delays.push(delay.as_secs_f64() * 1000.0),
+                    Ok(None) => failures += 1,
+                    Err(_) => {
+                        cancelled = true;
+                        break;
+                    }
+                }
+                thread::sleep(interval);
+            }
+            let result = PhaseResult {
+                label: phase.label.clone(),
+                workload: kind,
+                failures,
+                delay_ms: Summary::from_samples(&delays),
+            };
+            match &result.delay_ms {
+                Some(s) => info!(phase = phase.label.as_str(), samples = s.count, failures = failures,
+                                 median_ms = s.median, p95_ms = s.p95; "phase finished"),
+                None => warn!(phase = phase.label.as_str(), failures = failures; "phase without measurements"),
+            }
+            phase_results.push(result);
+            if cancelled {
+                warn!(scenario = scenario.name.as_str(); "scenario cancelled");
+                break;
+            }
+        }
+        network.send(NetworkMessage::Workload(workload));
+        network.send(NetworkMessage::Phase(String::new()));
+        let _ = sender.send_blocking(UpdateUI::ScenarioFinished(scenario.name.clone(), phase_results));
+    });
+}
+
+// Writes the per phase statistics to `<dir>/scenario_<name>_<timestamp>.json`.
+pub fn write_phase_results(dir: &Path, name: &str, results: &[PhaseResult])
+                           -> Result<PathBuf, Box<dyn std::error::Error>> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("scenario_{}_{}.json", name, Local::now().format("%Y%m%d-%H%M%S")));
+    serde_json::to_writer_pretty(File::create(&path)?, results)?;
+    Ok(path)
+}
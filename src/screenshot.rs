@@ -1,22 +1,155 @@
-use std::io::Cursor;
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "tesseract")]
+use std::ffi::CString;
+#[cfg(feature = "tesseract")]
+use std::os::raw::c_int;
 use std::thread;
 use std::time::{Duration, Instant};
 use async_channel::Sender;
 use xcap;
 use image;
-use image::{DynamicImage, GenericImage, ImageBuffer, ImageFormat, Rgb, RgbImage};
-use leptess::{LepTess, Variable};
+use image::{imageops, DynamicImage, GenericImage, ImageBuffer, ImageFormat, Rgb, RgbImage, RgbaImage};
+#[cfg(feature = "tesseract")]
+use leptess::{tesseract::TessApi, Variable};
 use regex::Regex;
-use xcap::Monitor;
+use log::{debug, error, info, warn};
+use xcap::{Monitor, Window, XCapResult};
+use serde::{Deserialize, Serialize};
+use xcb::{x, Xid};
 use crate::UpdateUI;
+use crate::config::{Config, DetectionConfig, OcrEngine, Region, ALL_MONITORS};
+use crate::artifacts::{AttemptBundle, DebugArtifacts, MarkerArtifact};
+use crate::digits;
 #[path = "spectrust.rs"] mod spectrust;
 
-const MAX_TRIES:i32 = 3;
-const SCREENSHOT_DELAY_NS:u32 = 2_000_000_000; // 2 sec
-const CREATE_DEBUG_IMAGE:bool = true;
+#[cfg(feature = "tesseract")]
+const PAGE_SEG_MODE_SINGLE_LINE: &str = "7";
 
+// time to click on the window to pick
+const PICK_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Initialized Tesseract engines, each capture thread takes one out while reading and puts it back afterwards.
+#[cfg(feature = "tesseract")]
+static OCR_ENGINES: Mutex<Vec<TessApi>> = Mutex::new(Vec::new());
+// Markers last located in the locked windows, they are not located again while the windows keep their size.
+static WINDOW_MARKERS: Mutex<Option<WindowMarkers>> = Mutex::new(None);
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct StageTimings {
+    pub capture: Duration,
+    pub locate: Duration,
+    pub ocr: Duration,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureFailure {
+    ScreenCapture,
+    WindowNotFound,
+    Ocr,
+    ZeroReading,
+    // a timer reading does not fit the time since the stopwatch was started
+    Implausible,
+    // the remote timer is ahead of the local one, it started later
+    RemoteAhead,
+    // a timer reading is lower than in the previous attempt
+    NotMonotonic,
+}
+
+impl CaptureFailure {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CaptureFailure::ScreenCapture => "screen_capture",
+            CaptureFailure::WindowNotFound => "window_not_found",
+            CaptureFailure::Ocr => "ocr",
+            CaptureFailure::ZeroReading => "zero_reading",
+            CaptureFailure::Implausible => "implausible",
+            CaptureFailure::RemoteAhead => "remote_ahead",
+            CaptureFailure::NotMonotonic => "not_monotonic",
+        }
+    }
+}
+
+// A measured delay and the quality of the readings it was taken from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DelayReading {
+    pub delay: Duration,
+    pub local: Duration,
+    // timer of the remote instance, a remote screen that stopped updating shows the same value again
+    pub remote: Duration,
+    // lower match confidence of the two markers
+    pub match_confidence: f32,
+    // lower OCR confidence of the two timers, in [0, 1]
+    pub ocr_confidence: f32,
+    // refresh period of the captured monitor in milliseconds, 0 if it is unknown, the longer one of both
+    // monitors if the timers are on different monitors
+    pub frame_ms: f64,
+    // refresh rate of the monitor showing the remote timer, 0 if it is unknown
+    pub refresh_hz: f32,
+    // markers located in the screenshot
+    pub local_at: Marker,
+    pub remote_at: Marker,
+    // time between the captures of the monitors showing the two timers in milliseconds
+    pub skew_ms: f64,
+    // geometry and refresh rate of the monitors showing the timers, see `MonitorGeometry::describe`
+    pub local_monitor: String,
+    pub remote_monitor: String,
+}
+
+// Marker located in a screenshot: position, size and match confidence.
+pub type Marker = (u32, u32, u32, u32, f32);
+
+// Frames the timers of an accepted reading show, as instants of the local clock. The timers restart with every
+// sample, later readings are compared with these instants and must not go back before them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadingFrames {
+    pub local: Instant,
+    pub remote: Instant,
+}
+
+impl ReadingFrames {
+    // Frames of `reading` taken from the stopwatch started at `timer_start`.
+    pub fn of(reading: &DelayReading, timer_start: Instant) -> ReadingFrames {
+        ReadingFrames { local: timer_start + reading.local, remote: timer_start + reading.remote }
+    }
+}
+
+// Position, size and refresh rate of a captured monitor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorGeometry {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub frequency: f32,
+}
+
+impl MonitorGeometry {
+    pub fn of(monitor: &Monitor) -> MonitorGeometry {
+        MonitorGeometry {
+            name: monitor.name().to_string(),
+            x: monitor.x(),
+            y: monitor.y(),
+            width: monitor.width(),
+            height: monitor.height(),
+            frequency: monitor.frequency(),
+        }
+    }
+
+    // Refresh period in milliseconds, 0 if it is unknown.
+    pub fn frame_ms(&self) -> f64 {
+        if self.frequency > 0.0 { 1000.0 / self.frequency as f64 } else { 0.0 }
+    }
+
+    // e.g. `DP-1 1920x1080+0+0@60Hz`
+    pub fn describe(&self) -> String {
+        format!("{} {}x{}{:+}{:+}@{}Hz", self.name, self.width, self.height, self.x, self.y, self.frequency)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct CaptureBox {
     width:i32,
     height:i32,
@@ -33,29 +166,205 @@ impl CaptureBox {
             y_offset,
         }
     }
+
+    // Area of the box around a marker located at `(x, y)`.
+    pub fn at(&self, (x, y): (u32, u32)) -> Region {
+        Region {
+            x: x as i32 + self.x_offset,
+            y: y as i32 + self.y_offset,
+            width: self.width.max(0) as u32,
+            height: self.height.max(0) as u32,
+        }
+    }
+
+    // Area of the box around a marker located at `(x, y)`, None if it is not completely inside an image of
+    // `width` x `height` pixels.
+    pub fn within(&self, marker: (u32, u32), (width, height): (u32, u32)) -> Option<Region> {
+        let area = self.at(marker);
+        (area.x >= 0 && area.y >= 0 && area.x as u32 + area.width <= width && area.y as u32 + area.height <= height)
+            .then_some(area)
+    }
+}
+
+// Window captured instead of the monitors, picked by clicking or found by its title.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowSelector {
+    Id(u32),
+    Title(String),
+}
+
+impl WindowSelector {
+    // Index of the selected one of the `(id, title)` of `windows`, a window with the exact title before one
+    // containing it. The window `other` is skipped so that both locked windows are different.
+    fn find(&self, windows: &[(u32, &str)], other: Option<u32>) -> Option<usize> {
+        let candidates = || windows.iter().enumerate().filter(|(_, (id, _))| Some(*id) != other);
+        match self {
+            WindowSelector::Id(selected) => candidates().find(|(_, (id, _))| id == selected),
+            WindowSelector::Title(title) => candidates().find(|(_, (_, t))| t == title)
+                .or_else(|| candidates().find(|(_, (_, t))| t.contains(title.as_str()))),
+        }.map(|(i, _)| i)
+    }
+}
+
+// The VDI client window showing the remote instance and the window of the local instance, captured instead of
+// the monitors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockedWindows {
+    pub remote: WindowSelector,
+    pub local: WindowSelector,
+}
+
+// One of the two locked windows.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowRole {
+    Remote,
+    Local,
+}
+
+// Lets the user click on a window and sends its id and title, the capture is locked to it afterwards.
+pub fn pick_window(sender: Arc<Sender<UpdateUI>>, role: WindowRole) {
+    thread::spawn(move || {
+        let picked = wait_for_click().and_then(|(click, frame)| window_at(click, frame));
+        match &picked {
+            Ok((id, title)) => info!(role:? = role, id = id, title = title.as_str(); "window picked"),
+            Err(e) => warn!(role:? = role, error = e.as_str(); "no window picked"),
+        }
+        let _ = sender.send_blocking(UpdateUI::WindowPicked(role, picked.ok()));
+    });
+}
+
+// Grabs the pointer until the next click, which does not reach any window, and returns its position and the
+// area of the clicked top level window in root window coordinates.
+fn wait_for_click() -> Result<((i32, i32), Option<Region>), String> {
+    let (connection, screen) = xcb::Connection::connect(None)
+        .map_err(|e| format!("can not connect to the X server: {}", e))?;
+    let root = connection.get_setup().roots().nth(screen as usize).ok_or("X screen not found")?.root();
+    let cookie = connection.send_request(&x::GrabPointer {
+        owner_events: false,
+        grab_window: root,
+        event_mask: x::EventMask::BUTTON_PRESS,
+        pointer_mode: x::GrabMode::Async,
+        keyboard_mode: x::GrabMode::Async,
+        confine_to: x::Window::none(),
+        cursor: x::Cursor::none(),
+        time: x::CURRENT_TIME,
+    });
+    let grab = connection.wait_for_reply(cookie).map_err(|e| format!("error grabbing the pointer: {}", e))?;
+    if grab.status() != x::GrabStatus::Success {
+        return Err(format!("could not grab the pointer: {:?}", grab.status()));
+    }
+    let deadline = Instant::now() + PICK_TIMEOUT;
+    let click = loop {
+        match connection.poll_for_event() {
+            Ok(Some(xcb::Event::X(x::Event::ButtonPress(event)))) => break Ok(event),
+            Ok(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            Ok(_) => break Err("no window was clicked".to_string()),
+            Err(e) => break Err(format!("error waiting for the click: {}", e)),
+        }
+    };
+    connection.send_request(&x::UngrabPointer { time: x::CURRENT_TIME });
+    let _ = connection.flush();
+    let click = click?;
+    // the window manager puts the top level windows into frames, the clicked child of the root is one of them
+    let frame = (!click.child().is_none()).then(|| {
+        let cookie = connection.send_request(&x::GetGeometry { drawable: x::Drawable::Window(click.child()) });
+        connection.wait_for_reply(cookie).ok().map(|g| Region {
+            x: g.x() as i32,
+            y: g.y() as i32,
+            width: g.width() as u32,
+            height: g.height() as u32,
+        })
+    }).flatten();
+    Ok(((click.root_x() as i32, click.root_y() as i32), frame))
+}
+
+// Id and title of the window at `click`.
+fn window_at(click: (i32, i32), frame: Option<Region>) -> Result<(u32, String), String> {
+    let windows = Window::all().map_err(|e| format!("error listing windows: {}", e))?;
+    let areas: Vec<Region> = windows.iter()
+        .map(|w| Region { x: w.x(), y: w.y(), width: w.width(), height: w.height() })
+        .collect();
+    let index = clicked_window(&areas, click, frame.as_ref()).ok_or("no window at the clicked position")?;
+    Ok((windows[index].id(), windows[index].title().to_string()))
+}
+
+// Index of the area containing `(x, y)`: one inside the clicked `frame` if there is one, otherwise the smallest,
+// which is most likely on top.
+fn clicked_window(areas: &[Region], (x, y): (i32, i32), frame: Option<&Region>) -> Option<usize> {
+    areas.iter().enumerate()
+        .filter(|(_, area)| area.contains(x, y))
+        .min_by_key(|(_, area)| (!frame.is_some_and(|f| area.inside(f)), area.width as u64 * area.height as u64))
+        .map(|(i, _)| i)
 }
 
 pub fn get_monitors() -> Vec<Monitor> {
-     xcap::Monitor::all().unwrap()
+    xcap::Monitor::all().unwrap_or_else(|e| {
+        error!(error:% = e; "error listing monitors");
+        vec![]
+    })
+}
+
+// Monitors to capture: all of them for `ALL_MONITORS`, otherwise the one named `capture.monitor` or the first
+// one if it is empty or does not exist.
+pub fn selected_monitors(config: &Config) -> Vec<Monitor> {
+    let monitors = get_monitors();
+    if config.capture.monitor == ALL_MONITORS {
+        return monitors;
+    }
+    let index = monitors.iter().position(|m| m.name() == config.capture.monitor).unwrap_or(0);
+    monitors.into_iter().skip(index).take(1).collect()
 }
 
-pub fn capture_screen(sender_capture: Arc<Sender<UpdateUI>>, capture_box:Arc<CaptureBox>, monitor_num: usize) {
+// Captures the locked windows, or the selected monitors if there are none, and measures the delay. The readings
+// are checked against the stopwatch started at `timer_start` and the frames of the `previous` accepted reading,
+// attempts failing the checks are repeated.
+pub fn capture_screen(sender_capture: Arc<Sender<UpdateUI>>, capture_box:Arc<CaptureBox>, config: Arc<Config>,
+                      timer_start: Instant, windows: Option<LockedWindows>, previous: Option<ReadingFrames>) {
     let _ = thread::spawn({
         move || {
-            thread::sleep(Duration::new(0, SCREENSHOT_DELAY_NS));
-            let binding = get_monitors();
-            let monitor = &binding.get(monitor_num).unwrap();
-            for _i in 0.. MAX_TRIES+1 {
+            thread::sleep(Duration::from_millis(config.capture.screenshot_delay_ms));
+            let monitors = selected_monitors(&config);
+            if windows.is_none() && monitors.is_empty() {
+                error!("no monitor to capture");
+                let _ = sender_capture.send_blocking(UpdateUI::DelayMeasured(None));
+                return;
+            };
+            let debug_artifacts = DebugArtifacts::from_config(&config.output);
+            for i in 0.. config.capture.max_tries+1 {
                 let start = Instant::now();
-                match capture(monitor, capture_box.deref()) {
-                    None => {println!("capture failed");}
-                    Some(delay) => {
-                        println!("Delay: {:?}",delay);
-                        let _ = sender_capture.send_blocking(UpdateUI::DelayMeasured(Some(delay)));
-                        break;
+                let mut timings = StageTimings::default();
+                let result = match &windows {
+                    Some(windows) => capture_windows(windows, capture_box.deref(), i, &config,
+                                                     debug_artifacts.as_ref(), &mut timings),
+                    None => capture(&monitors, capture_box.deref(), i, &config, debug_artifacts.as_ref(),
+                                    &mut timings),
+                };
+                // stopwatch time at the start and the end of the capture call
+                let window = (start.saturating_duration_since(timer_start),
+                              (start + timings.capture).saturating_duration_since(timer_start));
+                // rejected readings are not compared with, a misread must not reject the next attempt
+                let result = result.and_then(|reading| {
+                    check_reading(&reading, window, timer_start, previous.as_ref(), &config).map(|_| reading)
+                });
+                if windows.is_some() && result.is_err() {
+                    // the markers moved in their windows or were located wrongly
+                    WINDOW_MARKERS.lock().unwrap().take();
+                }
+                let _ = sender_capture.send_blocking(UpdateUI::CaptureAttempt(result.as_ref().err().copied(),
+                                                                              timings));
+                match result {
+                    Err(reason) => {warn!(attempt = i, reason:? = reason; "capture failed");}
+                    Ok(reading) => {
+                        info!(delay:? = reading.delay, match_confidence = reading.match_confidence,
+                              ocr_confidence = reading.ocr_confidence, local_monitor:% = reading.local_monitor,
+                              remote_monitor:% = reading.remote_monitor, skew_ms = reading.skew_ms;
+                              "delay measured");
+                        let _ = sender_capture.send_blocking(UpdateUI::DelayMeasured(Some(reading)));
+                        return;
                     }
                 }
-                println!("screenshot to time: {:?}", start.elapsed());
+                debug!(elapsed:? = start.elapsed(); "screenshot to time");
             }
             let _ = sender_capture.send_blocking(UpdateUI::DelayMeasured(None));
         }
@@ -63,101 +372,427 @@ pub fn capture_screen(sender_capture: Arc<Sender<UpdateUI>>, capture_box:Arc<Cap
 }
 
 
-fn capture(monitor: &Monitor, capture_box:&CaptureBox) -> Option<Duration> {
+// Screenshot of a monitor or a window.
+struct Shot {
+    name: String,
+    image: RgbaImage,
+    // position in the searched image made by `arrange`
+    at: (i32, i32),
+    // middle of the capture call
+    taken: Instant,
+    // monitor showing the captured area
+    monitor: MonitorGeometry,
+}
+
+impl Shot {
+    fn take(name: &str, capture: impl FnOnce() -> XCapResult<RgbaImage>, at: (i32, i32), monitor: MonitorGeometry)
+            -> Result<Shot, CaptureFailure> {
+        let start = Instant::now();
+        let image = capture().map_err(|e| {
+            error!(name = name, error:% = e; "error on image capture");
+            CaptureFailure::ScreenCapture
+        })?;
+        Ok(Shot { name: name.to_string(), image, at, taken: start + start.elapsed() / 2, monitor })
+    }
+
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.at.0 && y >= self.at.1 && x < self.at.0 + self.image.width() as i32
+            && y < self.at.1 + self.image.height() as i32
+    }
+}
+
+// Captures the monitors one after another and searches the screenshots arranged as the monitors are.
+fn capture(monitors: &[Monitor], capture_box:&CaptureBox, attempt: i32, config: &Config,
+           debug_artifacts: Option<&DebugArtifacts>, timings: &mut StageTimings)
+           -> Result<DelayReading, CaptureFailure> {
     let start = Instant::now();
-    let image = match monitor.capture_image(){
-        Ok(x) => x,
-        Err(_) =>{
-            println!("Error on image capture");
-            return None},
+    let mut shots = vec![];
+    for monitor in monitors {
+        let geometry = MonitorGeometry::of(monitor);
+        shots.push(Shot::take(monitor.name(), || monitor.capture_image(), (geometry.x, geometry.y), geometry)?);
+    }
+    timings.capture = start.elapsed();
+    measure_shots(shots, capture_box, attempt, None, config, debug_artifacts, timings)
+}
+
+// Markers located in the locked windows, relative to their windows, and the windows they were located in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct WindowMarkers {
+    // id, width and height of the remote and the local window
+    windows: [(u32, u32, u32); 2],
+    // remote and local marker
+    markers: [Marker; 2],
+}
+
+impl WindowMarkers {
+    // Markers of `reading` in `windows` placed side by side by `capture_windows`, None if a marker is not in its
+    // window.
+    fn of(windows: [(u32, u32, u32); 2], reading: &DelayReading) -> Option<WindowMarkers> {
+        let (remote, mut local) = (reading.remote_at, reading.local_at);
+        if remote.0 >= windows[0].1 || local.0 < windows[0].1 {
+            return None;
+        }
+        local.0 -= windows[0].1;
+        Some(WindowMarkers { windows, markers: [remote, local] })
+    }
+
+    // Markers in `windows` placed side by side, None if they are other windows or their size changed.
+    fn placed(&self, windows: [(u32, u32, u32); 2]) -> Option<[Marker; 2]> {
+        let [remote, mut local] = self.markers;
+        local.0 += windows[0].1;
+        (self.windows == windows).then_some([remote, local])
+    }
+}
+
+// Captures only the locked windows and searches them placed side by side. The windows are looked up again for
+// every capture, so they are followed when they move to another place or monitor. The markers are located once
+// and read at the same place in their windows until a window changes its size or a reading fails.
+fn capture_windows(windows: &LockedWindows, capture_box:&CaptureBox, attempt: i32, config: &Config,
+                   debug_artifacts: Option<&DebugArtifacts>, timings: &mut StageTimings)
+                   -> Result<DelayReading, CaptureFailure> {
+    let start = Instant::now();
+    let all = Window::all().map_err(|e| {
+        error!(error:% = e; "error listing windows");
+        CaptureFailure::ScreenCapture
+    })?;
+    let titles: Vec<(u32, &str)> = all.iter().map(|w| (w.id(), w.title())).collect();
+    let remote = windows.remote.find(&titles, None);
+    let local = remote.and_then(|r| windows.local.find(&titles, Some(all[r].id())));
+    let (Some(remote), Some(local)) = (remote, local) else {
+        warn!(remote:? = windows.remote, local:? = windows.local; "could not find the locked windows");
+        return Err(CaptureFailure::WindowNotFound);
     };
-    println!("Time To Capture: {:?}", start.elapsed());
+    let mut shots = vec![];
+    let mut x = 0;
+    let key = [&all[remote], &all[local]].map(|w| (w.id(), w.width(), w.height()));
+    for window in [&all[remote], &all[local]] {
+        if window.is_minimized() {
+            warn!(title = window.title(); "locked window is minimized");
+            return Err(CaptureFailure::WindowNotFound);
+        }
+        debug!(title = window.title(), x = window.x(), y = window.y(), width = window.width(),
+               height = window.height(); "capturing window");
+        let monitor = MonitorGeometry::of(&window.current_monitor());
+        shots.push(Shot::take(window.title(), || window.capture_image(), (x, 0), monitor)?);
+        x += window.width() as i32;
+    }
+    timings.capture = start.elapsed();
+    let markers = WINDOW_MARKERS.lock().unwrap().and_then(|m| m.placed(key));
+    if markers.is_none() {
+        debug!("locating the markers in the locked windows");
+    }
+    let reading = measure_shots(shots, capture_box, attempt, markers, config, debug_artifacts, timings)?;
+    *WINDOW_MARKERS.lock().unwrap() = WindowMarkers::of(key, &reading);
+    Ok(reading)
+}
+
+// Measures the delay in the screenshots and adds the monitors the timers were found on. `markers` are the remote
+// and the local marker if they are known already.
+fn measure_shots(mut shots: Vec<Shot>, capture_box:&CaptureBox, attempt: i32, markers: Option<[Marker; 2]>,
+                 config: &Config, debug_artifacts: Option<&DebugArtifacts>, timings: &mut StageTimings)
+                 -> Result<DelayReading, CaptureFailure> {
+    let name = shots.iter().map(|s| s.name.as_str()).collect::<Vec<_>>().join("+");
+    // a single screenshot is searched as it is, it shows both timers
+    let image = if shots.len() == 1 { std::mem::take(&mut shots[0].image) } else { arrange(&shots) };
+    let attempt = Attempt { number: attempt, name: &name, markers };
+    let reading = analyze(DynamicImage::ImageRgba8(image), &attempt, capture_box, config, debug_artifacts,
+                          timings)?;
+    let local = &shots[shot_at(&shots, (reading.local_at.0, reading.local_at.1))];
+    let remote = &shots[shot_at(&shots, (reading.remote_at.0, reading.remote_at.1))];
+    let (first, last) = (local.taken.min(remote.taken), local.taken.max(remote.taken));
+    Ok(DelayReading {
+        frame_ms: local.monitor.frame_ms().max(remote.monitor.frame_ms()),
+        refresh_hz: remote.monitor.frequency,
+        skew_ms: last.duration_since(first).as_secs_f64() * 1000.0,
+        local_monitor: local.monitor.describe(),
+        remote_monitor: remote.monitor.describe(),
+        ..reading
+    })
+}
+
+// Screenshots placed on one image covering all of them, areas no screenshot shows stay black.
+fn arrange(shots: &[Shot]) -> RgbaImage {
+    let (left, top) = origin(shots);
+    let right = shots.iter().map(|s| s.at.0 + s.image.width() as i32).max().unwrap_or(0);
+    let bottom = shots.iter().map(|s| s.at.1 + s.image.height() as i32).max().unwrap_or(0);
+    let mut canvas = RgbaImage::new((right - left).max(0) as u32, (bottom - top).max(0) as u32);
+    for shot in shots {
+        imageops::replace(&mut canvas, &shot.image, (shot.at.0 - left) as i64, (shot.at.1 - top) as i64);
+    }
+    canvas
+}
+
+// Top left corner of the area covered by `shots`.
+fn origin(shots: &[Shot]) -> (i32, i32) {
+    (shots.iter().map(|s| s.at.0).min().unwrap_or(0), shots.iter().map(|s| s.at.1).min().unwrap_or(0))
+}
+
+// Index of the screenshot showing the point `(x, y)` of the image made by `arrange`.
+fn shot_at(shots: &[Shot], (x, y): (u32, u32)) -> usize {
+    let (left, top) = origin(shots);
+    shots.iter().position(|s| s.contains(left + x as i32, top + y as i32)).unwrap_or(0)
+}
+
+// A capture attempt: its number, the name of the captured screenshot and the remote and the local marker in it
+// if they are known already.
+struct Attempt<'a> {
+    number: i32,
+    name: &'a str,
+    markers: Option<[Marker; 2]>,
+}
+
+// Locates both timers in the screenshot, unless the attempt knows them, reads them and returns their difference.
+fn analyze(image: DynamicImage, attempt: &Attempt, capture_box: &CaptureBox, config: &Config,
+           debug_artifacts: Option<&DebugArtifacts>, timings: &mut StageTimings)
+           -> Result<DelayReading, CaptureFailure> {
     let out_file = String::from("debug.jpg");
-    let image = DynamicImage::ImageRgba8(image);
     let mut output_image = image.clone().into_rgb8();
+    let mut bundle = debug_artifacts.map(|_| AttemptBundle::new(attempt.number, attempt.name, &output_image));
     let mut results = vec![];
-    let mut ok = true;
-    for p in [crate::IMAGE_BYTES_SERVER, crate::IMAGE_BYTES_CLIENT]
+    let mut marker_boxes = vec![];
+    let mut failure = None;
+    let (mut match_confidence, mut ocr_confidence) = (1.0f32, 1.0f32);
+    for (i, (name, p)) in [("server", crate::IMAGE_BYTES_SERVER), ("client", crate::IMAGE_BYTES_CLIENT)]
+        .into_iter().enumerate()
     {
-        let res = find_timer_spect(&image,p);
+        let res = match attempt.markers {
+            Some(markers) => Some(markers[i]),
+            None => {
+                let start = Instant::now();
+                let res = find_timer_spect(&image,p, &config.detection);
+                timings.locate += start.elapsed();
+                res
+            }
+        };
         match res {
-            Some((x, y, _w, _h, confidence)) => {
-                println!("Image found at {}, {} with confidence {}", x, y, confidence);
-
-                let x = (x as i32 + capture_box.x_offset) as u32;
-                let y = (y as i32 + capture_box.y_offset) as u32;
-                let duration =
-                    ocr(output_image.sub_image(x, y, capture_box.width as u32, capture_box.height as u32).to_image());
-
-                match duration {
-                    Ok(d) => {results.push((Some(d),x,y));}
-                    Err(e) => {
-                        results.push((None,x,y));
-                        println!("Error ocr: {:?}",e);
-                        ok = false;
+            Some((x, y, w, h, confidence)) => {
+                debug!(x = x, y = y, confidence = confidence; "image found");
+                marker_boxes.push((x,y,w,h,confidence));
+
+                // the timer of a marker at the edge of the screenshot or window is cut off
+                let Some(area) = capture_box.within((x, y), output_image.dimensions()) else {
+                    warn!(x = x, y = y; "capture box outside of the screenshot");
+                    failure.get_or_insert(CaptureFailure::WindowNotFound);
+                    if let Some(bundle) = bundle.as_mut() {
+                        bundle.add_marker(MarkerArtifact {
+                            error: Some("capture box outside of the screenshot".to_string()),
+                            found: true,
+                            x,
+                            y,
+                            width: w,
+                            height: h,
+                            confidence,
+                            ..MarkerArtifact::not_found(name)
+                        });
                     }
+                    continue;
+                };
+                let (crop_x, crop_y) = (area.x as u32, area.y as u32);
+                let crop = output_image.sub_image(crop_x, crop_y, area.width, area.height).to_image();
+                let start = Instant::now();
+                let text = ocr(crop.clone(), config.ocr.engine);
+                timings.ocr += start.elapsed();
+                let duration = text.clone().and_then(|(t, _)| parse_timer(t.as_str()));
+                match_confidence = match_confidence.min(confidence);
+                ocr_confidence = ocr_confidence.min(text.as_ref().map_or(0.0, |(_, c)| *c));
+
+                if let Err(e) = &duration {
+                    warn!(error = e.as_str(); "error ocr");
+                    failure.get_or_insert(CaptureFailure::Ocr);
+                }
+                if let Some(bundle) = bundle.as_mut() {
+                    bundle.add_marker(MarkerArtifact {
+                        name,
+                        found: true,
+                        x,
+                        y,
+                        width: w,
+                        height: h,
+                        confidence,
+                        ocr_text: text.as_ref().ok().map(|(t, _)| t.clone()),
+                        reading_ms: duration.as_ref().ok().map(|d| d.as_millis()),
+                        error: duration.as_ref().err().cloned(),
+                        crop: Some(crop),
+                    });
                 }
+                results.push((duration.ok(),crop_x,crop_y,text.as_ref().map_or(0.0, |(_, c)| *c)));
             }
             None => {
-                println!("Could not locate program window");
-                ok = false;
+                warn!("could not locate program window");
+                failure.get_or_insert(CaptureFailure::WindowNotFound);
+                if let Some(bundle) = bundle.as_mut() {
+                    bundle.add_marker(MarkerArtifact::not_found(name));
+                }
             }
         }
     }
-    save_debug_image(&mut output_image, out_file, MAX_TRIES, &results, &capture_box);
-    if !ok {
-        return None;
+    info!(capture:? = timings.capture, locate:? = timings.locate, ocr:? = timings.ocr; "stage timings");
+    // the annotations are only drawn for someone to look at them
+    if config.output.debug_image || bundle.is_some() {
+        save_debug_image(&mut output_image, out_file, &results, &marker_boxes, &capture_box,
+                         config.output.debug_image);
+    }
+
+    if failure.is_none()
+        && (results[0].0.unwrap().as_nanos() == 0 || results[1].0.unwrap().as_nanos() == 0) {
+        failure = Some(CaptureFailure::ZeroReading);
+    }
+    if let Some(reason) = failure {
+        if let (Some(bundle), Some(settings)) = (bundle, debug_artifacts) {
+            bundle.save(settings, &output_image, reason, timings);
+        }
+        return Err(reason);
     }
+    // the remote timer lags behind the local one, a remote ahead within the slack of `check_reading` is no delay
+    let delay = results[1].0.unwrap().saturating_sub(results[0].0.unwrap());
+    Ok(DelayReading {
+        delay,
+        local: results[1].0.unwrap(),
+        remote: results[0].0.unwrap(),
+        match_confidence,
+        ocr_confidence,
+        frame_ms: 0.0,
+        refresh_hz: 0.0,
+        local_at: marker_boxes[1],
+        remote_at: marker_boxes[0],
+        skew_ms: 0.0,
+        local_monitor: String::new(),
+        remote_monitor: String::new(),
+    })
+}
+
+// Reads the timer next to the marker of the local instance, used to calibrate the capture latency.
+pub fn read_local_timer(image: &DynamicImage, capture_box: &CaptureBox, config: &Config)
+                        -> Result<Duration, CaptureFailure> {
+    let (x, y, _, _, _) = find_timer_spect(image, crate::IMAGE_BYTES_CLIENT, &config.detection)
+        .ok_or(CaptureFailure::WindowNotFound)?;
+    let crop = capture_box.within((x, y), (image.width(), image.height())).ok_or(CaptureFailure::WindowNotFound)?;
+    let crop = image.crop_imm(crop.x as u32, crop.y as u32, crop.width, crop.height).into_rgb8();
+    ocr(crop, config.ocr.engine).and_then(|(text, _)| parse_timer(&text)).map_err(|e| {
+        warn!(error = e.as_str(); "error ocr");
+        CaptureFailure::Ocr
+    })
+}
 
-    if results[0].0.unwrap().as_nanos() == 0 || results[1].0.unwrap().as_nanos() == 0 {
-        return None;
+// Checks that the readings fit the stopwatch time `window` of the capture call, that the remote timer does
+// not run ahead of the local one or behind it by more than `quality.max_delay_ms` and that neither went back
+// since the `previous` attempt.
+fn check_reading(reading: &DelayReading, (start, end): (Duration, Duration), timer_start: Instant,
+                 previous: Option<&ReadingFrames>, config: &Config) -> Result<(), CaptureFailure> {
+    let tolerance = Duration::from_millis(config.quality.reading_tolerance_ms);
+    // the local timer shows the frame drawn shortly before the capture, the remote one lags behind it
+    if reading.local + tolerance < start || reading.local > end + tolerance || reading.remote > end + tolerance {
+        warn!(local:? = reading.local, remote:? = reading.remote, start:? = start, end:? = end;
+              "timer reading does not fit the elapsed time");
+        return Err(CaptureFailure::Implausible);
+    }
+    // a digit of the remote timer misread as a lower one gives a huge delay
+    if reading.remote + Duration::from_millis(config.quality.max_delay_ms) < reading.local {
+        warn!(local:? = reading.local, remote:? = reading.remote; "remote timer too far behind the local one");
+        return Err(CaptureFailure::Implausible);
+    }
+    // both timers can be a tick and a frame old when they are captured
+    let slack = Duration::from_millis(config.session.timer_tick_ms)
+        + Duration::from_secs_f64(reading.frame_ms / 1000.0);
+    if reading.remote > reading.local + slack {
+        warn!(local:? = reading.local, remote:? = reading.remote; "remote timer ahead of the local one");
+        return Err(CaptureFailure::RemoteAhead);
     }
-    let delay = duration_sub(results[0].0.unwrap(),results[1].0.unwrap());
-    Some(delay)
+    let frames = ReadingFrames::of(reading, timer_start);
+    if previous.is_some_and(|p| frames.local < p.local || frames.remote < p.remote) {
+        warn!(local:? = reading.local, remote:? = reading.remote; "timer reading went back");
+        return Err(CaptureFailure::NotMonotonic);
+    }
+    Ok(())
 }
 
-fn save_debug_image(image: &mut RgbImage, path:String, tries:i32, results:&Vec<(Option<Duration>,u32,u32)>, capture_box:& CaptureBox){
+// Draws the capture boxes with the OCR confidences in red and the markers with the match confidences in green
+// below them, and saves the image to `path` if `save` is set.
+fn save_debug_image(image: &mut RgbImage, path:String, results:&Vec<(Option<Duration>,u32,u32,f32)>,
+                    marker_boxes: &[Marker], capture_box:& CaptureBox, save: bool){
+    const LABEL_GAP: u32 = 6;
     for i in results{
         draw_rectangle_on(
             image,
             (i.1 , i.2 ),
             (capture_box.width as u32, capture_box.height as u32),
+            Rgb([255u8, 0, 0]),
         );
+        draw_text_on(image, (i.1, i.2 + capture_box.height as u32 + LABEL_GAP), &format!("{:.2}", i.3),
+                     Rgb([255u8, 0, 0]));
+    }
+    for &(x, y, w, h, confidence) in marker_boxes {
+        draw_rectangle_on(image, (x, y), (w, h), Rgb([0, 255u8, 0]));
+        draw_text_on(image, (x, y + h + LABEL_GAP), &format!("{:.2}", confidence), Rgb([0, 255u8, 0]));
     }
 
-    if CREATE_DEBUG_IMAGE && tries >= MAX_TRIES {
-        image.save(path).unwrap();
+    if save {
+        if let Err(e) = image.save(&path) {
+            warn!(path = path.as_str(), error:% = e; "error saving debug image");
+        }
     }
 }
-fn duration_sub(a:Duration, b:Duration) -> Duration{
-    let delay;
-    if a > b{
-        delay = a.saturating_sub(b);
-    } else {
-        delay = b.saturating_sub(a);
+
+
+// Text in the image and the confidence of the engine in [0, 1].
+fn ocr(image: ImageBuffer<Rgb<u8>, Vec<u8>>, engine: OcrEngine) -> Result<(String, f32),String> {
+    match engine {
+        OcrEngine::Builtin => ocr_builtin(image),
+        #[cfg(feature = "tesseract")]
+        OcrEngine::Tesseract => ocr_tesseract(image),
+        #[cfg(not(feature = "tesseract"))]
+        OcrEngine::Tesseract => Err("built without tesseract support".to_string()),
     }
-    delay
 }
 
+#[cfg(feature = "tesseract")]
+fn ocr_tesseract(image: ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<(String, f32),String> {
+    let engine = OCR_ENGINES.lock().unwrap().pop();
+    let mut engine = match engine {
+        Some(e) => e,
+        None => new_ocr_engine()?,
+    };
+    let res_str = ocr_with(&mut engine, &image);
+    OCR_ENGINES.lock().unwrap().push(engine);
+    let (res_str, confidence) = res_str?;
+    debug!(text = res_str.as_str(), confidence = confidence; "ocr");
+    Ok((res_str, confidence))
+}
+
+#[cfg(feature = "tesseract")]
+fn new_ocr_engine() -> Result<TessApi,String> {
+    let mut engine = TessApi::new(None, "eng").map_err(|e| e.to_string())?;
+    for (variable, value) in [(Variable::TesseditCharWhitelist, "0123456789.:"),
+                              (Variable::TesseditPagesegMode, PAGE_SEG_MODE_SINGLE_LINE)] {
+        engine.raw.set_variable(variable.as_cstr(), &CString::new(value).unwrap())
+            .map_err(|_| format!("error setting tesseract variable {:?}", variable.as_cstr()))?;
+    }
+    Ok(engine)
+}
 
-fn ocr(image: ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Duration,String> {
-    let mut lt = LepTess::new(None, "eng").unwrap();
+// Hands the pixels directly to tesseract instead of encoding them into an image file first.
+#[cfg(feature = "tesseract")]
+fn ocr_with(engine: &mut TessApi, image: &RgbImage) -> Result<(String, f32),String> {
+    let (width, height) = (image.width() as c_int, image.height() as c_int);
+    engine.raw.set_image(image.as_raw(), width, height, 3, width * 3)
+        .map_err(|e| format!("{:?}", e))?;
+    let text = engine.get_utf8_text().map_err(|e| e.to_string())?;
+    // the mean confidence of the recognized words is in percent
+    Ok((text, engine.mean_text_conf().clamp(0, 100) as f32 / 100.0))
+}
 
-    let mut tiff_buffer = Vec::new();
-    image.write_to(
-        &mut Cursor::new(&mut tiff_buffer),
-        image::ImageFormat::Tiff,
-    )
-        .unwrap();
-    lt.set_variable(Variable::TesseditCharWhitelist,"0123456789.:").expect("error setting tesseract whitelist");
-    lt.set_image_from_mem(&tiff_buffer).unwrap();
+fn ocr_builtin(image: ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<(String, f32),String> {
+    let glyphs = digits::glyphs().ok_or("timer glyphs not learned")?;
+    let (res_str, confidence) = glyphs.read(&image)?;
+    debug!(text = res_str.as_str(), confidence = confidence; "ocr");
+    Ok((res_str, confidence))
+}
 
-    let res_str = lt.get_utf8_text().unwrap();
-    println!("Ocr: {}", res_str);
+fn parse_timer(text: &str) -> Result<Duration,String> {
     let re =
         Regex::new(r"(?<hour>\d{2}):(?<minutes>\d{2}):(?<seconds>\d{2}).(?<milliseconds>\d{3})")
         .unwrap();
-    let Some(caps) = re.captures(res_str.as_str()) else {
+    let Some(caps) = re.captures(text) else {
         return Err("Could not match Regex Pattern".to_string());
     };
 
@@ -175,14 +810,20 @@ fn ocr(image: ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<Duration,String> {
         millis;
 
     let time = Duration::from_millis(millis);
-    println!("parsed: {:?}", time);
+    debug!(time:? = time; "parsed");
     Ok(time)
 }
 
-fn find_timer_spect(screenshot: &DynamicImage, pattern: &[u8]) -> Option<(u32, u32, u32, u32, f32)> {
+// Position and size of the marker of the remote instance in the screenshot.
+pub fn locate_remote_marker(screenshot: &DynamicImage, detection: &DetectionConfig)
+                            -> Option<(u32, u32, u32, u32, f32)> {
+    find_timer_spect(screenshot, crate::IMAGE_BYTES_SERVER, detection)
+}
+
+fn find_timer_spect(screenshot: &DynamicImage, pattern: &[u8], detection: &DetectionConfig) -> Option<(u32, u32, u32, u32, f32)> {
     let img = image::load_from_memory_with_format(pattern, ImageFormat::Jpeg).unwrap();
-    let min_confidence = Some(0.9);
-    let tolerance = Some(10);
+    let min_confidence = Some(detection.min_confidence as f32);
+    let tolerance = Some(detection.tolerance);
     let res = spectrust::locate_image(&screenshot,&img, min_confidence, tolerance);
     return res;
 }
@@ -191,32 +832,342 @@ fn draw_rectangle_on(
     img: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
     (x, y): (u32, u32),
     (w, h): (u32, u32),
+    border_col: Rgb<u8>,
 ) {
-    let border_col = Rgb([255u8, 0, 0]);
 
     const LINE_THICKNESS: u32 = 4;
-    // Vertical line at (x,y)
-    for off_x in 0..LINE_THICKNESS {
-        for off_y in 0..h {
-            *img.get_pixel_mut(x + off_x, y + off_y) = border_col;
+    // the lines start at (x,y) and (x+w,y+h), pixels outside of the image are left out
+    for off_y in 0..h + LINE_THICKNESS {
+        for off_x in 0..w + LINE_THICKNESS {
+            if off_x < LINE_THICKNESS || off_y < LINE_THICKNESS || off_x >= w || off_y >= h {
+                if let Some(pixel) = img.get_pixel_mut_checked(x + off_x, y + off_y) {
+                    *pixel = border_col;
+                }
+            }
+        }
+    }
+}
+
+// Writes `text` in the bitmap font of `digits` with its top left corner at `(x, y)`, pixels outside of the image
+// are left out.
+fn draw_text_on(img: &mut RgbImage, (x, y): (u32, u32), text: &str, color: Rgb<u8>) {
+    const SCALE: u32 = 2;
+    let mut left = x;
+    for rows in text.chars().filter_map(digits::font_glyph) {
+        for (row, line) in rows.iter().enumerate() {
+            for (column, _) in line.chars().enumerate().filter(|(_, pixel)| *pixel == '#') {
+                for (dx, dy) in (0..SCALE).flat_map(|dx| (0..SCALE).map(move |dy| (dx, dy))) {
+                    let (px, py) = (left + column as u32 * SCALE + dx, y + row as u32 * SCALE + dy);
+                    if let Some(pixel) = img.get_pixel_mut_checked(px, py) {
+                        *pixel = color;
+                    }
+                }
+            }
+        }
+        left += (rows[0].len() as u32 + digits::LETTER_SPACING) * SCALE;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{learn_test_glyphs, render_text, SyntheticScreen};
+
+    fn test_config() -> Config {
+        let mut config = Config::default();
+        config.ocr.engine = OcrEngine::Builtin;
+        config.output.debug_image = false;
+        config
+    }
+
+    fn attempt(markers: Option<[Marker; 2]>) -> Attempt<'static> {
+        Attempt { number: 0, name: "synthetic", markers }
+    }
+
+    fn measure(screen: &SyntheticScreen, config: &Config) -> Result<Duration, CaptureFailure> {
+        learn_test_glyphs();
+        analyze(screen.render(), &attempt(None), &screen.capture_box(), config, None, &mut StageTimings::default())
+            .map(|reading| reading.delay)
+    }
+
+    #[test]
+    fn measures_delay_between_timers() {
+        let screen = SyntheticScreen::new("00:00:01.100", "00:00:01.250");
+        assert_eq!(measure(&screen, &test_config()), Ok(Duration::from_millis(150)));
+    }
+
+    #[test]
+    fn reading_carries_its_quality() {
+        learn_test_glyphs();
+        let screen = SyntheticScreen::new("00:00:01.100", "00:00:01.250");
+        let config = test_config();
+        let reading = analyze(screen.render(), &attempt(None), &screen.capture_box(), &config, None,
+                              &mut StageTimings::default()).unwrap();
+        assert_eq!(reading.remote, Duration::from_millis(1100));
+        assert_eq!(reading.delay, Duration::from_millis(150));
+        assert!(reading.match_confidence >= config.detection.min_confidence as f32);
+        assert!(reading.ocr_confidence > 0.7, "{}", reading.ocr_confidence);
+    }
+
+    #[test]
+    fn timer_cut_off_at_the_edge_is_not_read() {
+        let mut screen = SyntheticScreen::new("00:00:01.100", "00:00:01.250");
+        // closer to the left edge than the timer is wide
+        screen.server_position.0 = (-screen.capture_box().x_offset - 10) as u32;
+        assert_eq!(measure(&screen, &test_config()), Err(CaptureFailure::WindowNotFound));
+    }
+
+    #[test]
+    fn annotations_are_clipped_to_the_image() {
+        let mut image = RgbImage::new(60, 40);
+        draw_rectangle_on(&mut image, (50, 30), (20, 20), Rgb([0, 255, 0]));
+        draw_text_on(&mut image, (55, 35), "0.97", Rgb([255, 0, 0]));
+        assert_eq!(*image.get_pixel(50, 39), Rgb([0, 255, 0]));
+        assert_eq!(*image.get_pixel(58, 38), Rgb([0, 0, 0]));
+        // first row of the 0 is drawn, the rest of the text is outside
+        assert_eq!(*image.get_pixel(57, 35), Rgb([255, 0, 0]));
+    }
+
+    #[test]
+    fn known_markers_are_read_without_locating_them() {
+        learn_test_glyphs();
+        let screen = SyntheticScreen::new("00:00:01.100", "00:00:01.250");
+        let config = test_config();
+        let located = analyze(screen.render(), &attempt(None), &screen.capture_box(), &config, None,
+                              &mut StageTimings::default()).unwrap();
+        let mut timings = StageTimings::default();
+        let markers = Some([located.remote_at, located.local_at]);
+        let reading = analyze(screen.render(), &attempt(markers), &screen.capture_box(), &config, None, &mut timings)
+            .unwrap();
+        assert_eq!(reading.delay, Duration::from_millis(150));
+        assert_eq!(timings.locate, Duration::ZERO);
+        // markers that moved are not found at the known place, the reading fails and they are located again
+        let (x, y, w, h, c) = located.remote_at;
+        let moved = Some([(x + 40, y + 30, w, h, c), located.local_at]);
+        assert!(analyze(screen.render(), &attempt(moved), &screen.capture_box(), &config, None,
+                        &mut StageTimings::default()).is_err());
+    }
+
+    #[test]
+    fn window_markers_are_kept_while_the_windows_keep_their_size() {
+        let mut reading = reading(1250, 1100);
+        reading.remote_at = (10, 20, 40, 40, 0.9);
+        reading.local_at = (830, 50, 40, 40, 0.95);
+        let windows = [(1, 800, 600), (2, 400, 300)];
+        let markers = WindowMarkers::of(windows, &reading).unwrap();
+        assert_eq!(markers.markers, [(10, 20, 40, 40, 0.9), (30, 50, 40, 40, 0.95)]);
+        assert_eq!(markers.placed(windows), Some([reading.remote_at, reading.local_at]));
+        assert_eq!(markers.placed([(1, 800, 600), (2, 400, 320)]), None);
+        assert_eq!(markers.placed([(3, 800, 600), (2, 400, 300)]), None);
+        // both markers found in the remote window are not kept
+        reading.local_at.0 = 300;
+        assert_eq!(WindowMarkers::of(windows, &reading), None);
+    }
+
+    #[test]
+    fn monitors_are_arranged_as_on_the_screen() {
+        let shot = |name: &str, x: i32, y: i32, frequency: f32, color: [u8; 4]| Shot {
+            name: name.to_string(),
+            image: RgbaImage::from_pixel(4, 2, image::Rgba(color)),
+            at: (x, y),
+            taken: Instant::now(),
+            monitor: MonitorGeometry { name: name.to_string(), x, y, width: 4, height: 2, frequency },
+        };
+        // the second monitor is left of and lower than the first one
+        let shots = [shot("DP-1", 0, 0, 60.0, [255, 0, 0, 255]), shot("HDMI-1", -4, 1, 0.0, [0, 0, 255, 255])];
+        let image = arrange(&shots);
+        assert_eq!(image.dimensions(), (8, 3));
+        assert_eq!(image.get_pixel(4, 0), &image::Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(0, 0), &image::Rgba([0, 0, 0, 0]));
+        assert_eq!(image.get_pixel(3, 2), &image::Rgba([0, 0, 255, 255]));
+        assert_eq!(shot_at(&shots, (5, 1)), 0);
+        assert_eq!(shot_at(&shots, (1, 2)), 1);
+        assert_eq!(shots[0].monitor.describe(), "DP-1 4x2+0+0@60Hz");
+        assert_eq!(shots[1].monitor.describe(), "HDMI-1 4x2-4+1@0Hz");
+        assert_eq!(shots[1].monitor.frame_ms(), 0.0);
+    }
+
+    #[test]
+    fn clicked_window_is_the_one_in_the_clicked_frame() {
+        let region = |x: i32, y: i32, width: u32, height: u32| Region { x, y, width, height };
+        // a small window partly covered by a large one in front of it
+        let areas = [region(0, 0, 200, 100), region(150, 50, 1000, 800), region(2000, 0, 10, 10)];
+        assert_eq!(clicked_window(&areas, (160, 60), None), Some(0));
+        assert_eq!(clicked_window(&areas, (160, 60), Some(&region(145, 30, 1010, 825))), Some(1));
+        assert_eq!(clicked_window(&areas, (500, 500), None), Some(1));
+        assert_eq!(clicked_window(&areas, (1500, 500), None), None);
+    }
+
+    #[test]
+    fn locked_windows_are_found_by_id_or_title() {
+        let windows = [(10, "Latency Tester - notes.txt"), (11, "Remote Desktop - vdi01"), (12, "Latency Tester")];
+        let title = |t: &str| WindowSelector::Title(t.to_string());
+        assert_eq!(title("vdi01").find(&windows, None), Some(1));
+        // the exact title wins over one containing it, the other locked window is skipped
+        assert_eq!(title("Latency Tester").find(&windows, None), Some(2));
+        assert_eq!(title("Latency Tester").find(&windows, Some(12)), Some(0));
+        assert_eq!(WindowSelector::Id(10).find(&windows, None), Some(0));
+        assert_eq!(WindowSelector::Id(10).find(&windows, Some(10)), None);
+        assert_eq!(title("Viewer").find(&windows, None), None);
+    }
+
+    // Reading of the local and the remote timer in milliseconds.
+    fn reading(local: u64, remote: u64) -> DelayReading {
+        let ms = Duration::from_millis;
+        DelayReading {
+            delay: ms(local.saturating_sub(remote)),
+            local: ms(local),
+            remote: ms(remote),
+            match_confidence: 1.0,
+            ocr_confidence: 1.0,
+            frame_ms: 1000.0 / 60.0,
+            refresh_hz: 60.0,
+            local_at: (0, 0, 0, 0, 1.0),
+            remote_at: (0, 0, 0, 0, 1.0),
+            skew_ms: 0.0,
+            local_monitor: String::new(),
+            remote_monitor: String::new(),
         }
     }
-    // Horizontal line at (x,y)
-    for off_y in 0..LINE_THICKNESS {
-        for off_x in 0..w {
-            *img.get_pixel_mut(x + off_x, y + off_y) = border_col;
+
+    #[test]
+    fn readings_are_checked_against_the_stopwatch() {
+        let config = test_config();
+        let ms = Duration::from_millis;
+        let window = (ms(2000), ms(2030));
+        let start = Instant::now();
+        let check = |reading: &DelayReading, previous: Option<&ReadingFrames>| {
+            check_reading(reading, window, start, previous, &config)
+        };
+        assert_eq!(check(&reading(2010, 1900), None), Ok(()));
+        // a misread digit in one of the timers
+        assert_eq!(check(&reading(2010, 7900), None), Err(CaptureFailure::Implausible));
+        assert_eq!(check(&reading(7010, 1900), None), Err(CaptureFailure::Implausible));
+        assert_eq!(check(&reading(1010, 900), None), Err(CaptureFailure::Implausible));
+        // an 8 in the seconds of the remote timer read as a 3
+        let window = (ms(9000), ms(9030));
+        assert_eq!(check_reading(&reading(9010, 8880), window, start, None, &config), Ok(()));
+        assert_eq!(check_reading(&reading(9010, 3880), window, start, None, &config),
+                   Err(CaptureFailure::Implausible));
+        // the remote can only appear ahead by a tick and a frame
+        assert_eq!(check(&reading(2010, 2025), None), Ok(()));
+        assert_eq!(check(&reading(1950, 2025), None), Err(CaptureFailure::RemoteAhead));
+        // the previous sample started its stopwatch 1 s earlier
+        let previous = ReadingFrames::of(&reading(2990, 2880), start - ms(1000));
+        assert_eq!(check(&reading(2010, 1900), Some(&previous)), Ok(()));
+        assert_eq!(check(&reading(2010, 1870), Some(&previous)), Err(CaptureFailure::NotMonotonic));
+    }
+
+    #[test]
+    fn misread_does_not_reject_the_next_attempt() {
+        let config = test_config();
+        let ms = Duration::from_millis;
+        let start = Instant::now();
+        let previous = Some(ReadingFrames::of(&reading(2010, 1900), start - ms(3000)));
+        // the first attempt misreads an 8 for a 3 and is rejected, the next one reads correctly
+        let misread = reading(2040, 8930);
+        assert_eq!(check_reading(&misread, (ms(2000), ms(2060)), start, previous.as_ref(), &config),
+                   Err(CaptureFailure::Implausible));
+        let good = reading(2540, 2430);
+        assert_eq!(check_reading(&good, (ms(2500), ms(2560)), start, previous.as_ref(), &config), Ok(()));
+    }
+
+    #[test]
+    fn reads_the_local_timer_alone() {
+        learn_test_glyphs();
+        let screen = SyntheticScreen::new("00:00:01.250", "00:00:01.100");
+        let reading = read_local_timer(&screen.render(), &screen.capture_box(), &test_config());
+        assert_eq!(reading, Ok(Duration::from_millis(1100)));
+    }
+
+    #[test]
+    fn remote_timer_ahead_is_no_delay() {
+        let screen = SyntheticScreen::new("00:00:04.387", "00:00:04.380");
+        assert_eq!(measure(&screen, &test_config()), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn measures_delay_at_other_positions_and_text_scales() {
+        for (scale, server, client) in [(2, (200, 10), (600, 300)), (3, (560, 300), (260, 20)), (4, (300, 120), (700, 20))] {
+            let mut screen = SyntheticScreen::new("00:12:59.990", "00:13:00.045");
+            screen.width = 800;
+            screen.height = 400;
+            screen.text_scale = scale;
+            screen.server_position = server;
+            screen.client_position = client;
+            assert_eq!(measure(&screen, &test_config()), Ok(Duration::from_millis(55)), "scale {}", scale);
         }
     }
-    // Vertical line at (x+w,y)
-    for off_x in 0..LINE_THICKNESS {
-        for off_y in 0..(h + 1) {
-            *img.get_pixel_mut(x + off_x + w, y + off_y) = border_col;
+
+    #[test]
+    fn measures_delay_after_jpeg_recompression() {
+        let mut config = test_config();
+        // a loose tolerance alone lets the client marker match next to the server marker
+        config.detection.tolerance = 40;
+        config.detection.min_confidence = 0.98;
+        for quality in [95, 85, 75] {
+            let mut screen = SyntheticScreen::new("01:59:59.999", "02:00:00.120");
+            screen.jpeg_quality = Some(quality);
+            assert_eq!(measure(&screen, &config), Ok(Duration::from_millis(121)), "quality {}", quality);
+            let reading = analyze(screen.render(), &attempt(None), &screen.capture_box(), &config, None,
+                                  &mut StageTimings::default()).unwrap();
+            // readable timers are not left out of the statistics
+            assert!(reading.ocr_confidence >= config.quality.min_ocr_confidence, "quality {}: {}", quality,
+                    reading.ocr_confidence);
         }
     }
-    // Horizontal line at (x,y+h)
-    for off_y in 0..LINE_THICKNESS {
-        for off_x in 0..(w + 1) {
-            *img.get_pixel_mut(x + off_x, y + off_y + h) = border_col;
+
+    #[test]
+    fn measures_delay_with_chroma_subsampling_and_noise() {
+        let mut config = test_config();
+        config.detection.tolerance = 30;
+        config.detection.min_confidence = 0.98;
+        let mut screen = SyntheticScreen::new("00:00:10.000", "00:00:10.500");
+        screen.chroma_subsampling = true;
+        screen.noise = 8;
+        assert_eq!(measure(&screen, &config), Ok(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn noise_above_tolerance_hides_markers() {
+        let mut screen = SyntheticScreen::new("00:00:10.500", "00:00:10.000");
+        screen.noise = 40;
+        assert_eq!(measure(&screen, &test_config()), Err(CaptureFailure::WindowNotFound));
+    }
+
+    #[test]
+    fn scaled_markers_are_not_found() {
+        let mut screen = SyntheticScreen::new("00:00:10.500", "00:00:10.000");
+        screen.marker_scale = 1.5;
+        assert_eq!(measure(&screen, &test_config()), Err(CaptureFailure::WindowNotFound));
+    }
+
+    #[test]
+    fn zero_reading_is_rejected() {
+        let screen = SyntheticScreen::new("00:00:00.000", "00:00:00.080");
+        assert_eq!(measure(&screen, &test_config()), Err(CaptureFailure::ZeroReading));
+    }
+
+    #[test]
+    fn timer_text_round_trips_through_ocr() {
+        learn_test_glyphs();
+        let seconds = |s: u64, ms: u64| Duration::from_secs(s) + Duration::from_millis(ms);
+        for elapsed in [seconds(0, 1), seconds(59, 999), seconds(60, 0), seconds(3599, 999), seconds(3600, 0),
+                        seconds(36_000 + 61, 7), seconds(359_999, 999)] {
+            let text = crate::duration_to_string(elapsed);
+            for scale in [2, 3, 5] {
+                let (read, confidence) = ocr(render_text(&text, scale), OcrEngine::Builtin).unwrap();
+                assert_eq!(read, text, "scale {}", scale);
+                assert!(confidence > 0.7, "confidence {} at scale {}", confidence, scale);
+                assert_eq!(parse_timer(&read), Ok(elapsed), "{}", text);
+            }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parse_timer_rejects_incomplete_text() {
+        assert!(parse_timer("00:00:1.000").is_err());
+        assert!(parse_timer("??:00:01.000").is_err());
+        assert_eq!(parse_timer("x 00:01:02.003 y"), Ok(Duration::from_millis(62_003)));
+    }
+}
@@ -0,0 +1,419 @@
+// Sinks that push every measurement to a remote service as it happens. Each sink runs on its own
+// thread and buffers measurements while the service is unreachable.
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use chrono::DateTime;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use crate::results::Measurement;
+
+const MAX_BATCH: usize = 500;
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+const INFLUX_MEASUREMENT: &str = "latency";
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SinkKind {
+    // InfluxDB line protocol posted to a write endpoint, e.g. `/api/v2/write?org=o&bucket=b&precision=ns`
+    InfluxHttp {
+        url: String,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        token: String,
+    },
+    // InfluxDB line protocol sent to a UDP listener
+    InfluxUdp {
+        address: String,
+    },
+    // measurements posted as a JSON array
+    Webhook {
+        url: String,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct SinkConfig {
+    #[serde(flatten)]
+    pub kind: SinkKind,
+    // tags added to every measurement, e.g. the name of the test machine
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    // measurements kept while the sink is unreachable, the oldest are dropped first
+    #[serde(default = "default_buffer_size")]
+    pub buffer_size: usize,
+    // first retry interval, doubled after every failed retry
+    #[serde(default = "default_retry_interval_ms")]
+    pub retry_interval_ms: u64,
+}
+
+fn default_buffer_size() -> usize {
+    10000
+}
+
+fn default_retry_interval_ms() -> u64 {
+    1000
+}
+
+// The service refused the measurements, sending them again would be refused again.
+#[derive(Debug)]
+struct Rejected(String);
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for Rejected {}
+
+trait ResultSink: Send {
+    fn name(&self) -> String;
+    fn send(&mut self, batch: &[Measurement]) -> Result<(), Box<dyn Error>>;
+}
+
+struct InfluxHttp {
+    url: String,
+    token: String,
+    tags: BTreeMap<String, String>,
+}
+
+struct InfluxUdp {
+    address: String,
+    socket: Option<UdpSocket>,
+    tags: BTreeMap<String, String>,
+}
+
+struct Webhook {
+    url: String,
+    tags: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct WebhookMeasurement<'a> {
+    #[serde(flatten)]
+    measurement: &'a Measurement,
+    tags: &'a BTreeMap<String, String>,
+}
+
+impl ResultSink for InfluxHttp {
+    fn name(&self) -> String {
+        format!("influx {}", self.url)
+    }
+
+    fn send(&mut self, batch: &[Measurement]) -> Result<(), Box<dyn Error>> {
+        let body = batch.iter().map(|m| line_protocol(m, &self.tags)).collect::<Vec<_>>().join("\n");
+        let authorization = format!("Token {}", self.token);
+        let headers: &[(&str, &str)] = if self.token.is_empty() { &[] } else { &[("Authorization", &authorization)] };
+        http_post(&self.url, "text/plain; charset=utf-8", headers, body.as_bytes())
+    }
+}
+
+impl ResultSink for InfluxUdp {
+    fn name(&self) -> String {
+        format!("influx udp://{}", self.address)
+    }
+
+    fn send(&mut self, batch: &[Measurement]) -> Result<(), Box<dyn Error>> {
+        if self.socket.is_none() {
+            let socket = UdpSocket::bind("0.0.0.0:0")?;
+            socket.connect(&self.address)?;
+            self.socket = Some(socket);
+        }
+        let socket = self.socket.as_ref().unwrap();
+        // one datagram per line keeps every datagram below the MTU
+        for measurement in batch {
+            socket.send(line_protocol(measurement, &self.tags).as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl ResultSink for Webhook {
+    fn name(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    fn send(&mut self, batch: &[Measurement]) -> Result<(), Box<dyn Error>> {
+        let body: Vec<_> = batch.iter()
+            .map(|measurement| WebhookMeasurement { measurement, tags: &self.tags })
+            .collect();
+        http_post(&self.url, "application/json", &[], &serde_json::to_vec(&body)?)
+    }
+}
+
+// Forwards measurements to the worker threads of all configured sinks.
+pub struct ResultSinks {
+    workers: Vec<Sender<Measurement>>,
+}
+
+impl ResultSinks {
+    pub fn start(configs: &[SinkConfig]) -> ResultSinks {
+        let workers = configs.iter().map(|config| {
+            let sink: Box<dyn ResultSink> = match &config.kind {
+                SinkKind::InfluxHttp { url, token } => Box::new(InfluxHttp {
+                    url: url.clone(),
+                    token: token.clone(),
+                    tags: config.tags.clone(),
+                }),
+                SinkKind::InfluxUdp { address } => Box::new(InfluxUdp {
+                    address: address.clone(),
+                    socket: None,
+                    tags: config.tags.clone(),
+                }),
+                SinkKind::Webhook { url } => Box::new(Webhook {
+                    url: url.clone(),
+                    tags: config.tags.clone(),
+                }),
+            };
+            let (sender, receiver) = mpsc::channel();
+            let buffer_size = config.buffer_size.max(1);
+            let retry_interval = Duration::from_millis(config.retry_interval_ms);
+            thread::spawn(move || run_sink(sink, receiver, buffer_size, retry_interval));
+            sender
+        }).collect();
+        ResultSinks { workers }
+    }
+
+    pub fn publish(&self, measurement: &Measurement) {
+        for worker in &self.workers {
+            let _ = worker.send(measurement.clone());
+        }
+    }
+}
+
+fn run_sink(mut sink: Box<dyn ResultSink>, receiver: mpsc::Receiver<Measurement>, buffer_size: usize,
+            retry_interval: Duration) {
+    info!(sink:% = sink.name(); "result sink started");
+    let mut buffer: VecDeque<Measurement> = VecDeque::new();
+    let mut retry_at: Option<Instant> = None;
+    let mut interval = retry_interval;
+    let mut dropped = 0;
+    let mut failing = false;
+    loop {
+        let received = match retry_at {
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            Some(at) => receiver.recv_timeout(at.saturating_duration_since(Instant::now())),
+        };
+        match received {
+            Ok(measurement) => buffer.push_back(measurement),
+            Err(RecvTimeoutError::Disconnected) => return,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        buffer.extend(receiver.try_iter());
+        while buffer.len() > buffer_size {
+            buffer.pop_front();
+            dropped += 1;
+        }
+        if retry_at.is_some_and(|at| Instant::now() < at) || buffer.is_empty() {
+            continue;
+        }
+        let batch = buffer.iter().take(MAX_BATCH).cloned().collect::<Vec<_>>();
+        match sink.send(&batch) {
+            Ok(()) => {
+                if failing {
+                    failing = false;
+                    info!(sink:% = sink.name(), buffered = buffer.len(), dropped = dropped; "result sink reachable again");
+                    dropped = 0;
+                }
+                buffer.drain(..batch.len());
+                interval = retry_interval;
+                // send the rest of the buffer right away
+                retry_at = if buffer.is_empty() { None } else { Some(Instant::now()) };
+            }
+            Err(e) if e.is::<Rejected>() => {
+                warn!(sink:% = sink.name(), error:% = e, dropped = batch.len(); "results rejected, dropping them");
+                buffer.drain(..batch.len());
+                retry_at = if buffer.is_empty() { None } else { Some(Instant::now()) };
+            }
+            Err(e) => {
+                warn!(sink:% = sink.name(), error:% = e, buffered = buffer.len(), retry_in:? = interval;
+                      "error sending results");
+                failing = true;
+                retry_at = Some(Instant::now() + interval);
+                interval = (interval * 2).min(MAX_RETRY_INTERVAL);
+            }
+        }
+    }
+}
+
+// `latency,profile=lab,phase=idle,mode=display delay_ms=42.1 1718000000000000000`
+fn line_protocol(measurement: &Measurement, tags: &BTreeMap<String, String>) -> String {
+    let mut line = INFLUX_MEASUREMENT.to_string();
+    let own_tags = [("profile", measurement.profile.as_str()), ("phase", measurement.phase.as_str()),
+                    ("mode", measurement.mode.name())];
+    let tags = own_tags.into_iter().chain(tags.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+    // empty tag values are not allowed
+    for (key, value) in tags.filter(|(_, v)| !v.is_empty()) {
+        line.push_str(&format!(",{}={}", escape_tag(key), escape_tag(value)));
+    }
+    line.push_str(&format!(" delay_ms={}", measurement.delay_ms));
+    if let Some(arrival) = measurement.arrival_ms {
+        line.push_str(&format!(",arrival_ms={}", arrival));
+    }
+    if let Some(uncertainty) = measurement.uncertainty_ms {
+        line.push_str(&format!(",uncertainty_ms={}", uncertainty));
+    }
+    if let Some(hz) = measurement.refresh_hz {
+        line.push_str(&format!(",refresh_hz={}", hz));
+    }
+    if let Some(hz) = measurement.remote_refresh_hz {
+        line.push_str(&format!(",remote_refresh_hz={}", hz));
+    }
+    if measurement.excluded {
+        line.push_str(",excluded=true");
+    }
+    if let Some(nanos) = DateTime::parse_from_rfc3339(&measurement.timestamp).ok()
+        .and_then(|t| t.timestamp_nanos_opt()) {
+        line.push_str(&format!(" {}", nanos));
+    }
+    line
+}
+
+fn escape_tag(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+// Minimal HTTP/1.1 POST, only plain `http://` URLs are supported.
+fn http_post(url: &str, content_type: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<(), Box<dyn Error>> {
+    let rest = url.strip_prefix("http://").ok_or(format!("url {:?} is not a http:// url", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let address = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+    let socket_address = address.to_socket_addrs()?.next().ok_or(format!("can not resolve {:?}", host))?;
+    let mut stream = TcpStream::connect_timeout(&socket_address, HTTP_TIMEOUT)?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+    let mut request = format!("POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+                               Connection: close\r\n", path, host, content_type, body.len());
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut status = String::new();
+    BufReader::new(&stream).read_line(&mut status)?;
+    let code = status.split_whitespace().nth(1).and_then(|c| c.parse::<u16>().ok())
+        .ok_or(format!("invalid response {:?}", status.trim()))?;
+    // client errors other than a timeout or too many requests do not go away by retrying
+    if (400..500).contains(&code) && code != 408 && code != 429 {
+        return Err(Box::new(Rejected(format!("server answered {}", status.trim()))));
+    }
+    if !(200..300).contains(&code) {
+        return Err(format!("server answered {}", status.trim()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use crate::config::MeasureMode;
+
+    fn measurement(profile: &str, delay_ms: u64) -> Measurement {
+        let mut measurement = Measurement::new(MeasureMode::Display, Duration::from_millis(delay_ms), profile, "");
+        measurement.timestamp = "2024-06-10T06:13:20+00:00".to_string();
+        measurement
+    }
+
+    // Answers one request on `listener` with `status` and returns its body.
+    fn answer(listener: &TcpListener, status: &str) -> String {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).as_bytes()).unwrap();
+        String::from_utf8(body).unwrap()
+    }
+
+    // Sink posting to a local listener, retrying after `retry_interval`.
+    fn start_sink(retry_interval: Duration) -> (TcpListener, Sender<Measurement>, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let sink = Box::new(InfluxHttp {
+            url: format!("http://{}/write", listener.local_addr().unwrap()),
+            token: String::new(),
+            tags: BTreeMap::new(),
+        });
+        let (sender, receiver) = mpsc::channel();
+        let worker = thread::spawn(move || run_sink(sink, receiver, 10, retry_interval));
+        (listener, sender, worker)
+    }
+
+    #[test]
+    fn tags_are_escaped() {
+        assert_eq!(escape_tag("a b,c=d\\e"), "a\\ b\\,c\\=d\\\\e");
+        assert_eq!(escape_tag("plain"), "plain");
+    }
+
+    #[test]
+    fn measurements_are_written_as_line_protocol() {
+        let mut measurement = measurement("lab 1", 42);
+        measurement.phase = "idle".to_string();
+        measurement.uncertainty_ms = Some(8.5);
+        measurement.excluded = true;
+        let tags = BTreeMap::from([("host".to_string(), "pc,1".to_string()), ("empty".to_string(), String::new())]);
+        assert_eq!(line_protocol(&measurement, &tags),
+                   "latency,profile=lab\\ 1,phase=idle,mode=display,host=pc\\,1 \
+                    delay_ms=42,uncertainty_ms=8.5,excluded=true 1718000000000000000");
+        // empty phases are left out, measurements without a valid timestamp get the time of arrival
+        let mut measurement = self::measurement("lab", 7);
+        measurement.timestamp = String::new();
+        assert_eq!(line_protocol(&measurement, &BTreeMap::new()), "latency,profile=lab,mode=display delay_ms=7");
+    }
+
+    #[test]
+    fn measurements_are_buffered_until_the_sink_is_reachable() {
+        let (listener, sender, worker) = start_sink(Duration::from_millis(200));
+        sender.send(measurement("first", 10)).unwrap();
+        assert!(answer(&listener, "503 Service Unavailable").contains("profile=first"));
+        // sent while waiting for the retry, both go out together
+        sender.send(measurement("second", 20)).unwrap();
+        let body = answer(&listener, "204 No Content");
+        assert_eq!(body.lines().count(), 2, "{}", body);
+        assert!(body.contains("profile=first") && body.contains("profile=second"), "{}", body);
+        sender.send(measurement("third", 30)).unwrap();
+        assert_eq!(answer(&listener, "204 No Content").lines().count(), 1);
+        drop(sender);
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn rejected_measurements_are_not_sent_again() {
+        let (listener, sender, worker) = start_sink(Duration::from_millis(50));
+        sender.send(measurement("invalid", 10)).unwrap();
+        assert!(answer(&listener, "400 Bad Request").contains("profile=invalid"));
+        sender.send(measurement("valid", 20)).unwrap();
+        let body = answer(&listener, "204 No Content");
+        assert!(body.contains("profile=valid") && !body.contains("profile=invalid"), "{}", body);
+        // too many requests is retried
+        sender.send(measurement("throttled", 30)).unwrap();
+        assert!(answer(&listener, "429 Too Many Requests").contains("profile=throttled"));
+        assert!(answer(&listener, "204 No Content").contains("profile=throttled"));
+        drop(sender);
+        worker.join().unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+// Summary statistics over delay samples in milliseconds.
+use std::f64::consts::TAU;
+use serde::Serialize;
+
+// bins of the phase histogram of folded delays
+pub const PHASE_BINS: usize = 8;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub p95: f64,
+    pub stddev: f64,
+}
+
+impl Summary {
+    pub fn from_samples(samples: &[f64]) -> Option<Summary> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let count = sorted.len();
+        let mean = sorted.iter().sum::<f64>() / count as f64;
+        let variance = if count > 1 {
+            sorted.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / (count - 1) as f64
+        } else {
+            0.0
+        };
+        Some(Summary {
+            count,
+            min: sorted[0],
+            max: sorted[count - 1],
+            mean,
+            median: quantile(&sorted, 0.5),
+            p95: quantile(&sorted, 0.95),
+            stddev: variance.sqrt(),
+        })
+    }
+}
+
+// Linear interpolated quantile of already sorted samples.
+pub fn quantile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let position = q.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f64)
+}
+
+// Delays folded modulo the refresh period of a display. A frame waits for the next refresh, if the delays are not
+// synchronized with the refresh the wait is spread evenly over a period: it adds half a period to the mean delay
+// and period / sqrt(12) to the standard deviation. The rest is the delay of the pipeline itself.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct VsyncFold {
+    pub period_ms: f64,
+    pub count: usize,
+    // delays per phase bin, the phase of a delay is its remainder modulo the period
+    pub phases: Vec<usize>,
+    // circular mean of the phases
+    pub mean_phase_ms: f64,
+    // length of the mean of the phases as unit vectors: near 0 for phases spread evenly, 1 for delays locked to
+    // the refresh, which the split below does not apply to
+    pub concentration: f64,
+    // mean wait for the refresh and the standard deviation it adds
+    pub quantization_ms: f64,
+    pub quantization_stddev_ms: f64,
+    // mean and standard deviation of the delays without the wait
+    pub pipeline_ms: f64,
+    pub pipeline_stddev_ms: f64,
+}
+
+impl VsyncFold {
+    pub fn from_samples(delays: &[f64], period_ms: f64) -> Option<VsyncFold> {
+        let summary = Summary::from_samples(delays)?;
+        if period_ms.is_nan() || period_ms <= 0.0 {
+            return None;
+        }
+        let mut phases = vec![0; PHASE_BINS];
+        let (mut sin, mut cos) = (0.0, 0.0);
+        for delay in delays {
+            let phase = delay.rem_euclid(period_ms) / period_ms;
+            phases[((phase * PHASE_BINS as f64) as usize).min(PHASE_BINS - 1)] += 1;
+            sin += (phase * TAU).sin();
+            cos += (phase * TAU).cos();
+        }
+        let (sin, cos) = (sin / summary.count as f64, cos / summary.count as f64);
+        let quantization_stddev_ms = period_ms / 12f64.sqrt();
+        Some(VsyncFold {
+            period_ms,
+            count: summary.count,
+            phases,
+            mean_phase_ms: sin.atan2(cos).rem_euclid(TAU) / TAU * period_ms,
+            concentration: sin.hypot(cos),
+            quantization_ms: period_ms / 2.0,
+            quantization_stddev_ms,
+            pipeline_ms: (summary.mean - period_ms / 2.0).max(0.0),
+            pipeline_stddev_ms: (summary.stddev.powi(2) - quantization_stddev_ms.powi(2)).max(0.0).sqrt(),
+        })
+    }
+}
+
+// Period in milliseconds of the median of the known refresh rates, None if there are none.
+pub fn refresh_period_ms(rates_hz: impl IntoIterator<Item = f64>) -> Option<f64> {
+    let mut rates: Vec<f64> = rates_hz.into_iter().filter(|hz| *hz > 0.0).collect();
+    rates.sort_by(f64::total_cmp);
+    rates.get(rates.len() / 2).map(|hz| 1000.0 / hz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: f64 = 1000.0 / 60.0;
+
+    #[test]
+    fn unsynchronized_delays_are_split_into_refresh_wait_and_pipeline() {
+        // a pipeline of 40 ms, the frames wait anywhere within a period for the refresh
+        let delays: Vec<f64> = (0..600).map(|i| 40.0 + (i as f64 * 0.37).rem_euclid(PERIOD)).collect();
+        let fold = VsyncFold::from_samples(&delays, PERIOD).unwrap();
+        assert_eq!(fold.count, 600);
+        assert_eq!(fold.phases.iter().sum::<usize>(), 600);
+        assert!(fold.phases.iter().all(|n| (60..=90).contains(n)), "{:?}", fold.phases);
+        assert!(fold.concentration < 0.05, "{}", fold.concentration);
+        assert!((fold.quantization_ms - PERIOD / 2.0).abs() < 1e-9);
+        assert!((fold.pipeline_ms - 40.0).abs() < 0.5, "{}", fold.pipeline_ms);
+        assert!(fold.pipeline_stddev_ms < 1.0, "{}", fold.pipeline_stddev_ms);
+    }
+
+    #[test]
+    fn delays_locked_to_the_refresh_have_one_phase() {
+        let delays: Vec<f64> = (0..30).map(|i| 5.0 + (2 + i % 3) as f64 * PERIOD).collect();
+        let fold = VsyncFold::from_samples(&delays, PERIOD).unwrap();
+        assert!((fold.concentration - 1.0).abs() < 1e-9);
+        assert!((fold.mean_phase_ms - 5.0).abs() < 1e-9);
+        assert_eq!(fold.phases[2], 30);
+    }
+
+    #[test]
+    fn no_fold_without_period_or_delays() {
+        assert_eq!(VsyncFold::from_samples(&[30.0], 0.0), None);
+        assert_eq!(VsyncFold::from_samples(&[], PERIOD), None);
+        assert_eq!(refresh_period_ms([0.0, 50.0, 60.0, 60.0]), Some(PERIOD));
+        assert_eq!(refresh_period_ms([]), None);
+    }
+}
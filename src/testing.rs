@@ -0,0 +1,167 @@
+// Synthetic screenshots for the tests: both window markers with a timer next to each, drawn on a
+// canvas and optionally degraded like a remote display stream would do it.
+use std::io::Cursor;
+use image::codecs::jpeg::JpegEncoder;
+use image::{imageops, DynamicImage, ImageFormat, Rgb, RgbImage};
+use crate::digits::{self, GlyphSet, LETTER_SPACING};
+use crate::screenshot::CaptureBox;
+
+// space between a timer and the marker right of it
+const TIMER_GAP: u32 = 16;
+const PADDING: u32 = 4;
+const BACKGROUND: Rgb<u8> = Rgb([236, 236, 236]);
+const INK: Rgb<u8> = Rgb([20, 20, 20]);
+// scale the glyphs of the reader are learned at
+const REFERENCE_SCALE: u32 = 3;
+
+// Renders `text` in the bitmap font, every font pixel becomes `scale` x `scale` pixels.
+pub fn render_text(text: &str, scale: u32) -> RgbImage {
+    let width: u32 = text.chars().map(|c| (glyph(c)[0].len() as u32 + LETTER_SPACING) * scale).sum::<u32>()
+        + 2 * PADDING;
+    let mut image = RgbImage::from_pixel(width, 7 * scale + 2 * PADDING, BACKGROUND);
+    let mut x = PADDING;
+    for c in text.chars() {
+        let rows = glyph(c);
+        for (row, line) in rows.iter().enumerate() {
+            for (column, pixel) in line.chars().enumerate() {
+                if pixel == '#' {
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            image.put_pixel(x + column as u32 * scale + dx, PADDING + row as u32 * scale + dy, INK);
+                        }
+                    }
+                }
+            }
+        }
+        x += (rows[0].len() as u32 + LETTER_SPACING) * scale;
+    }
+    image
+}
+
+fn glyph(c: char) -> [&'static str; 7] {
+    digits::font_glyph(c).unwrap_or_else(|| panic!("no glyph for {:?}", c))
+}
+
+// Teaches the digit reader the bitmap font, the glyphs can only be set once per process.
+pub fn learn_test_glyphs() {
+    if digits::glyphs().is_none() {
+        let reference = render_text(digits::REFERENCE_TEXT, REFERENCE_SCALE);
+        digits::set_glyphs(GlyphSet::learn(&reference, digits::REFERENCE_TEXT).unwrap());
+    }
+}
+
+pub struct SyntheticScreen {
+    pub width: u32,
+    pub height: u32,
+    // top left corners of the markers, the timers are drawn left of them
+    pub server_position: (u32, u32),
+    pub client_position: (u32, u32),
+    pub server_timer: String,
+    pub client_timer: String,
+    pub text_scale: u32,
+    // scale of the marker images, the markers are only found unscaled
+    pub marker_scale: f32,
+    pub jpeg_quality: Option<u8>,
+    // averages the chroma of 2x2 blocks like 4:2:0 video does
+    pub chroma_subsampling: bool,
+    // maximal change of a color channel by uniform noise
+    pub noise: u8,
+    pub seed: u64,
+}
+
+impl SyntheticScreen {
+    pub fn new(server_timer: &str, client_timer: &str) -> SyntheticScreen {
+        SyntheticScreen {
+            width: 640,
+            height: 360,
+            server_position: (280, 40),
+            client_position: (560, 220),
+            server_timer: server_timer.to_string(),
+            client_timer: client_timer.to_string(),
+            text_scale: REFERENCE_SCALE,
+            marker_scale: 1.0,
+            jpeg_quality: None,
+            chroma_subsampling: false,
+            noise: 0,
+            seed: 1,
+        }
+    }
+
+    pub fn render(&self) -> DynamicImage {
+        let mut canvas = RgbImage::from_pixel(self.width, self.height, BACKGROUND);
+        for (position, timer, marker) in [(self.server_position, &self.server_timer, crate::IMAGE_BYTES_SERVER),
+                                          (self.client_position, &self.client_timer, crate::IMAGE_BYTES_CLIENT)] {
+            let marker = image::load_from_memory_with_format(marker, ImageFormat::Jpeg).unwrap().into_rgb8();
+            let marker = if self.marker_scale == 1.0 {
+                marker
+            } else {
+                imageops::resize(&marker, (marker.width() as f32 * self.marker_scale).round() as u32,
+                                 (marker.height() as f32 * self.marker_scale).round() as u32,
+                                 imageops::FilterType::Triangle)
+            };
+            imageops::replace(&mut canvas, &marker, position.0 as i64, position.1 as i64);
+            let text = render_text(timer, self.text_scale);
+            let x = position.0 as i64 - TIMER_GAP as i64 - text.width() as i64;
+            imageops::replace(&mut canvas, &text, x, position.1 as i64);
+        }
+        if self.chroma_subsampling {
+            subsample_chroma(&mut canvas);
+        }
+        if self.noise > 0 {
+            add_noise(&mut canvas, self.noise, self.seed);
+        }
+        if let Some(quality) = self.jpeg_quality {
+            let mut data = Cursor::new(vec![]);
+            JpegEncoder::new_with_quality(&mut data, quality).encode_image(&canvas).unwrap();
+            canvas = image::load_from_memory_with_format(data.get_ref(), ImageFormat::Jpeg).unwrap().into_rgb8();
+        }
+        DynamicImage::ImageRgb8(canvas)
+    }
+
+    // Capture box around a timer of the full `HH:MM:SS.mmm` length, relative to its marker.
+    pub fn capture_box(&self) -> CaptureBox {
+        let text = render_text("00:00:00.000", self.text_scale);
+        CaptureBox::new(text.width() as i32 + 2 * PADDING as i32, text.height() as i32 + 2 * PADDING as i32,
+                        -((text.width() + TIMER_GAP + PADDING) as i32), -(PADDING as i32))
+    }
+}
+
+fn subsample_chroma(image: &mut RgbImage) {
+    let (width, height) = image.dimensions();
+    for y in (0..height - 1).step_by(2) {
+        for x in (0..width - 1).step_by(2) {
+            let block = [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)];
+            let ycbcr: Vec<[f32; 3]> = block.iter().map(|&(x, y)| to_ycbcr(image.get_pixel(x, y))).collect();
+            let cb = ycbcr.iter().map(|p| p[1]).sum::<f32>() / 4.0;
+            let cr = ycbcr.iter().map(|p| p[2]).sum::<f32>() / 4.0;
+            for (&(x, y), p) in block.iter().zip(&ycbcr) {
+                image.put_pixel(x, y, from_ycbcr(p[0], cb, cr));
+            }
+        }
+    }
+}
+
+fn to_ycbcr(p: &Rgb<u8>) -> [f32; 3] {
+    let [r, g, b] = p.0.map(|c| c as f32);
+    [0.299 * r + 0.587 * g + 0.114 * b,
+     128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b,
+     128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b]
+}
+
+fn from_ycbcr(y: f32, cb: f32, cr: f32) -> Rgb<u8> {
+    let r = y + 1.402 * (cr - 128.0);
+    let g = y - 0.344_136 * (cb - 128.0) - 0.714_136 * (cr - 128.0);
+    let b = y + 1.772 * (cb - 128.0);
+    Rgb([r, g, b].map(|c| c.round().clamp(0.0, 255.0) as u8))
+}
+
+fn add_noise(image: &mut RgbImage, amplitude: u8, seed: u64) {
+    let mut state = seed.max(1);
+    for channel in image.iter_mut() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let offset = (state % (2 * amplitude as u64 + 1)) as i16 - amplitude as i16;
+        *channel = (*channel as i16 + offset).clamp(0, 255) as u8;
+    }
+}
@@ -0,0 +1,144 @@
+// Synthetic load shown by the remote instance in its own window, so the remote display protocol
+// has to encode a reproducible amount of changing content while the delay is measured.
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+use gtk::{cairo, glib, prelude::*, DrawingArea};
+use log::{error, info};
+use crate::config::{WorkloadConfig, WorkloadKind};
+
+const TEXT_LINE_HEIGHT: f64 = 22.0;
+const TEXT_SCROLL_PER_FRAME: f64 = 4.0;
+const TEXT: &str = "The quick brown fox jumps over the lazy dog 0123456789 !\"#$%&'()*+,-./:;<=>?@[]^_{|}~";
+
+pub struct LoadWindow {
+    window: gtk::Window,
+    timer: Option<glib::SourceId>,
+}
+
+impl LoadWindow {
+    // Opens the load window, none for `WorkloadKind::None`.
+    pub fn open(config: &WorkloadConfig) -> Option<LoadWindow> {
+        if config.kind == WorkloadKind::None {
+            return None;
+        }
+        let window = gtk::Window::new(gtk::WindowType::Toplevel);
+        window.set_title("Latency Tester Load");
+        let area = DrawingArea::new();
+        window.add(&area);
+        if config.width == 0 || config.height == 0 {
+            // the timer window has to stay visible on top of the load
+            window.set_keep_below(true);
+            window.fullscreen();
+        } else {
+            area.set_size_request(config.width as i32, config.height as i32);
+            window.set_resizable(false);
+        }
+
+        let frame = Rc::new(Cell::new(0u64));
+        let noise = RefCell::new(NoiseState::new());
+        let kind = config.kind;
+        let frame_draw = Rc::clone(&frame);
+        area.connect_draw(move |area, cr| {
+            let (width, height) = (area.allocated_width(), area.allocated_height());
+            let result = match kind {
+                WorkloadKind::Noise => noise.borrow_mut().draw(cr, width, height),
+                WorkloadKind::Text => draw_text(cr, height, frame_draw.get()),
+                WorkloadKind::Gradient => draw_gradient(cr, width, height, frame_draw.get()),
+                WorkloadKind::None => Ok(()),
+            };
+            if let Err(e) = result {
+                error!(error:% = e; "error drawing load");
+            }
+            glib::Propagation::Stop
+        });
+        let timer = glib::timeout_add_local(Duration::from_secs_f64(1.0 / config.fps as f64), move || {
+            frame.set(frame.get() + 1);
+            area.queue_draw();
+            glib::ControlFlow::Continue
+        });
+        window.show_all();
+        info!(kind:? = config.kind, width = config.width, height = config.height, fps = config.fps;
+              "load started");
+        Some(LoadWindow { window, timer: Some(timer) })
+    }
+
+    pub fn close(mut self) {
+        if let Some(timer) = self.timer.take() {
+            timer.remove();
+        }
+        self.window.close();
+        info!("load stopped");
+    }
+}
+
+// Full frame of random gray values, regenerated every frame so nothing can be compressed.
+struct NoiseState {
+    surface: Option<cairo::ImageSurface>,
+    seed: u64,
+}
+
+impl NoiseState {
+    fn new() -> NoiseState {
+        NoiseState { surface: None, seed: 0x9e37_79b9_7f4a_7c15 }
+    }
+
+    fn draw(&mut self, cr: &cairo::Context, width: i32, height: i32) -> Result<(), cairo::Error> {
+        if width <= 0 || height <= 0 {
+            return Ok(());
+        }
+        let reuse = self.surface.as_ref().is_some_and(|s| s.width() == width && s.height() == height);
+        if !reuse {
+            self.surface = Some(cairo::ImageSurface::create(cairo::Format::Rgb24, width, height)?);
+        }
+        let surface = self.surface.as_mut().unwrap();
+        {
+            let mut data = surface.data().map_err(|_| cairo::Error::SurfaceFinished)?;
+            for pixels in data.chunks_mut(8) {
+                // xorshift64, one draw fills two pixels
+                self.seed ^= self.seed << 13;
+                self.seed ^= self.seed >> 7;
+                self.seed ^= self.seed << 17;
+                let bytes = self.seed.to_le_bytes();
+                for (i, p) in pixels.iter_mut().enumerate() {
+                    *p = bytes[i - i % 4];
+                }
+            }
+        }
+        cr.set_source_surface(&*surface, 0.0, 0.0)?;
+        cr.paint()
+    }
+}
+
+fn draw_text(cr: &cairo::Context, height: i32, frame: u64) -> Result<(), cairo::Error> {
+    cr.set_source_rgb(1.0, 1.0, 1.0);
+    cr.paint()?;
+    cr.set_source_rgb(0.0, 0.0, 0.0);
+    cr.select_font_face("monospace", cairo::FontSlant::Normal, cairo::FontWeight::Normal);
+    cr.set_font_size(16.0);
+    let scrolled = frame as f64 * TEXT_SCROLL_PER_FRAME;
+    let first_line = (scrolled / TEXT_LINE_HEIGHT) as u64;
+    let offset = scrolled % TEXT_LINE_HEIGHT;
+    let lines = (height as f64 / TEXT_LINE_HEIGHT) as u64 + 2;
+    for i in 0..lines {
+        let line = first_line + i;
+        // shift every line so the content of a line changes as it moves up
+        let shift = (line % TEXT.len() as u64) as usize;
+        let text = format!("{:>6} {}{}", line, &TEXT[shift..], &TEXT[..shift]);
+        cr.move_to(4.0, (i + 1) as f64 * TEXT_LINE_HEIGHT - offset);
+        cr.show_text(&text)?;
+    }
+    Ok(())
+}
+
+fn draw_gradient(cr: &cairo::Context, width: i32, height: i32, frame: u64) -> Result<(), cairo::Error> {
+    let t = frame as f64 * 0.05;
+    let x = (frame * 8 % width.max(1) as u64) as f64;
+    let gradient = cairo::LinearGradient::new(x - width as f64, 0.0, x + width as f64, height as f64);
+    gradient.set_extend(cairo::Extend::Reflect);
+    gradient.add_color_stop_rgb(0.0, 0.5 + 0.5 * t.sin(), 0.2, 0.5 + 0.5 * t.cos());
+    gradient.add_color_stop_rgb(0.5, 0.1, 0.5 + 0.5 * (t * 1.3).cos(), 0.4);
+    gradient.add_color_stop_rgb(1.0, 0.5 + 0.5 * (t * 0.7).cos(), 0.9, 0.5 + 0.5 * t.sin());
+    cr.set_source(&gradient)?;
+    cr.paint()
+}